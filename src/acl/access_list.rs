@@ -0,0 +1,191 @@
+use std::{
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::cidr::IpCidr;
+use crate::config::CONFIG_PATH;
+
+static ACL_PATH: LazyLock<PathBuf> = LazyLock::new(|| CONFIG_PATH.join("acl.toml"));
+
+/// What the rules of a scope control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AclScope {
+    /// Whether the client may connect to the proxy listener at all.
+    Proxy,
+    /// Whether the client's TLS connections may be intercepted.
+    Intercept,
+    /// Whether ad blocking applies to the client.
+    BlockAds,
+    /// Whether the client may reach the admin API.
+    Admin,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AclAction {
+    #[default]
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AclRules {
+    /// Action applied to clients matching neither list.
+    #[serde(default)]
+    pub default: AclAction,
+
+    #[serde(default)]
+    pub allow: Vec<IpCidr>,
+
+    #[serde(default)]
+    pub deny: Vec<IpCidr>,
+}
+
+impl AclRules {
+    /// Deny rules take precedence over allow rules, then the default action applies.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+
+        if self.allow.iter().any(|cidr| cidr.contains(ip)) {
+            return true;
+        }
+
+        self.default == AclAction::Allow
+    }
+
+    fn rules_mut(&mut self, action: AclAction) -> &mut Vec<IpCidr> {
+        match action {
+            AclAction::Allow => &mut self.allow,
+            AclAction::Deny => &mut self.deny,
+        }
+    }
+}
+
+// TOML file content and internal representation, every scope allows all clients by default
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessControlList {
+    #[serde(skip)]
+    pub file: PathBuf,
+
+    #[serde(default)]
+    pub proxy: AclRules,
+
+    #[serde(default)]
+    pub intercept: AclRules,
+
+    #[serde(default)]
+    pub block_ads: AclRules,
+
+    #[serde(default)]
+    pub admin: AclRules,
+}
+
+impl AccessControlList {
+    /// Load the rules from the TOML file. A missing file allows everything, but an invalid one
+    /// is an error, silently falling back to "allow all" would defeat the purpose of the rules.
+    pub fn load(file: Option<PathBuf>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let file = file.unwrap_or(ACL_PATH.clone());
+        let content = match file.exists() {
+            true => std::fs::read_to_string(&file)?,
+            false => String::new(),
+        };
+
+        let mut acl: AccessControlList = toml::from_str(&content)
+            .map_err(|e| format!("Invalid access control file {:?}: {}", file, e))?;
+        acl.file = file;
+
+        Ok(acl)
+    }
+
+    /// Dump the current rules to the TOML file with an atomic write.
+    fn dump_file(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let toml_str = toml::to_string(&self)?;
+        if let Some(parent) = self.file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let temp_path = self.file.with_extension("tmp");
+        std::fs::write(&temp_path, toml_str)?;
+        std::fs::rename(&temp_path, &self.file)?;
+
+        Ok(())
+    }
+
+    pub fn rules(&self, scope: AclScope) -> &AclRules {
+        match scope {
+            AclScope::Proxy => &self.proxy,
+            AclScope::Intercept => &self.intercept,
+            AclScope::BlockAds => &self.block_ads,
+            AclScope::Admin => &self.admin,
+        }
+    }
+
+    fn rules_mut(&mut self, scope: AclScope) -> &mut AclRules {
+        match scope {
+            AclScope::Proxy => &mut self.proxy,
+            AclScope::Intercept => &mut self.intercept,
+            AclScope::BlockAds => &mut self.block_ads,
+            AclScope::Admin => &mut self.admin,
+        }
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr, scope: AclScope) -> bool {
+        self.rules(scope).is_allowed(ip)
+    }
+
+    pub fn add_rule(
+        &mut self,
+        scope: AclScope,
+        action: AclAction,
+        cidr: IpCidr,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let rules = self.rules_mut(scope).rules_mut(action);
+        if !rules.contains(&cidr) {
+            rules.push(cidr);
+        }
+
+        self.dump_file()
+    }
+
+    /// Remove a rule and persist the lists. Returns whether the rule existed.
+    pub fn remove_rule(
+        &mut self,
+        scope: AclScope,
+        action: AclAction,
+        cidr: IpCidr,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let rules = self.rules_mut(scope).rules_mut(action);
+        let previous_len = rules.len();
+        rules.retain(|rule| *rule != cidr);
+
+        if rules.len() == previous_len {
+            return Ok(false);
+        }
+
+        self.dump_file()?;
+        Ok(true)
+    }
+
+    pub fn set_default(
+        &mut self,
+        scope: AclScope,
+        action: AclAction,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.rules_mut(scope).default = action;
+        self.dump_file()
+    }
+}
+
+pub static ACCESS_CONTROL: LazyLock<Arc<RwLock<AccessControlList>>> = LazyLock::new(|| {
+    let acl = AccessControlList::load(None).unwrap_or_else(|e| {
+        panic!("Failed to load access control lists: {}", e);
+    });
+    Arc::new(RwLock::new(acl))
+});
@@ -0,0 +1,10 @@
+// Source-IP access control lists, evaluated per client address to decide who can use the proxy,
+// whose TLS traffic can be intercepted, who gets ad blocking and who can reach the admin API.
+
+mod access_list;
+mod cidr;
+pub mod utils;
+
+pub use access_list::{AccessControlList, AclAction, AclRules, AclScope};
+pub use cidr::IpCidr;
+pub use utils::*;
@@ -0,0 +1,40 @@
+use std::net::IpAddr;
+
+use super::access_list::{ACCESS_CONTROL, AccessControlList, AclAction, AclScope};
+use super::cidr::IpCidr;
+
+pub fn is_client_allowed(ip: IpAddr, scope: AclScope) -> bool {
+    let acl = ACCESS_CONTROL.read().unwrap();
+    acl.is_allowed(ip, scope)
+}
+
+pub fn get_access_control_lists() -> AccessControlList {
+    let acl = ACCESS_CONTROL.read().unwrap();
+    acl.clone()
+}
+
+pub fn add_acl_rule(
+    scope: AclScope,
+    action: AclAction,
+    cidr: IpCidr,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut acl = ACCESS_CONTROL.write().unwrap();
+    acl.add_rule(scope, action, cidr)
+}
+
+pub fn remove_acl_rule(
+    scope: AclScope,
+    action: AclAction,
+    cidr: IpCidr,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut acl = ACCESS_CONTROL.write().unwrap();
+    acl.remove_rule(scope, action, cidr)
+}
+
+pub fn set_acl_default(
+    scope: AclScope,
+    action: AclAction,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut acl = ACCESS_CONTROL.write().unwrap();
+    acl.set_default(scope, action)
+}
@@ -1,6 +1,10 @@
+use std::{collections::HashMap, net::IpAddr};
+
 use axum::{
+    body::Bytes,
     extract::{Json, Path, Query},
     http::StatusCode,
+    response::{IntoResponse, Response},
 };
 use serde::{Deserialize, Serialize};
 use tokio::{
@@ -8,15 +12,32 @@ use tokio::{
     time::{self as TokioTime, Duration},
 };
 
+use crate::acl::{
+    AccessControlList, AclAction, AclScope, IpCidr, add_acl_rule, get_access_control_lists,
+    is_client_allowed, remove_acl_rule, set_acl_default,
+};
+use crate::ads::explain_request;
+use crate::auth::{add_user, get_users, remove_user};
 use crate::config::{
-    constants::CONFIG_PATH,
-    settings::{ProxyConfig, get_global_config, set_global_config},
+    ConfigVersion, ProxyConfig, ProxyConfigPatch, constants::CONFIG_PATH, get_config_history,
+    get_config_version, get_global_config, persist_global_config,
 };
+use crate::dns::{DnsCacheStats, flush_dns_cache, get_dns_cache_stats};
+use crate::errors::{ErrorCode, FilterError};
 use crate::filters::{
-    ListConfigType, add_domain_to_blacklist, add_domain_to_whitelist, get_blacklist, get_whitelist,
-    is_domain_blacklisted, is_domain_whitelisted, merge_from_file, remove_domain_from_blacklist,
-    remove_domain_from_whitelist, replace_from_file,
+    DEFAULT_TEMPORARY_RULE_SECS, ImportResult, InvalidLine, ListConfigType, ListEntryDetails,
+    ListFormat, ListMatch, MatchedRule, NetworkDecision, ResourceType, RuleAnnotation, RuleOrigin,
+    Subscription, SubscriptionStatus, TemporaryRule, TemporaryTarget, add_domain_to_blacklist,
+    add_domain_to_whitelist, add_subscription, add_temporary_rule, blocking_pause, explain_domain,
+    get_blacklist, get_list_details, get_subscriptions, get_temporary_rules, get_whitelist,
+    import_domains, merge_from_file, parse_list, refresh_subscription_now,
+    remove_domain_from_blacklist, remove_domain_from_whitelist, remove_subscription,
+    remove_temporary_rule, replace_from_file, temporary_allow,
 };
+use crate::limits::{
+    ConnectionStats, LimitsConfig, get_connection_stats, get_global_limits, set_global_limits,
+};
+use crate::utils::{authority::normalize_hostname, http::normalize_host};
 
 // ============================================================
 // Config Handlers
@@ -24,14 +45,74 @@ use crate::filters::{
 
 pub async fn get_config_handler() -> Json<ProxyConfig> {
     let config = get_global_config();
-    Json(config)
+    Json((*config).clone())
+}
+
+/// Validate the configuration, persist it as a new version and apply it.
+fn apply_config_update(
+    config: ProxyConfig,
+    source: &str,
+) -> Result<Json<ProxyConfig>, (StatusCode, String)> {
+    if let Err(e) = config.validate() {
+        tracing::warn!("Config update rejected: {}", e);
+        return Err((StatusCode::BAD_REQUEST, e));
+    }
+
+    match persist_global_config(config, source) {
+        Ok(entry) => {
+            tracing::info!("Config updated successfully to version {}", entry.version);
+            Ok(Json((*get_global_config()).clone()))
+        }
+        Err(e) => {
+            tracing::error!("Failed to persist config update: {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to persist config: {}", e),
+            ))
+        }
+    }
 }
 
-pub async fn update_config_handler(Json(payload): Json<ProxyConfig>) -> Json<ProxyConfig> {
+pub async fn update_config_handler(
+    Json(payload): Json<ProxyConfig>,
+) -> Result<Json<ProxyConfig>, (StatusCode, String)> {
     tracing::info!("Config update requested: {:?}", payload);
-    set_global_config(payload.clone());
-    tracing::info!("Config updated successfully");
-    Json(get_global_config())
+    apply_config_update(payload, "put")
+}
+
+pub async fn patch_config_handler(
+    Json(payload): Json<ProxyConfigPatch>,
+) -> Result<Json<ProxyConfig>, (StatusCode, String)> {
+    tracing::info!("Config patch requested: {:?}", payload);
+    apply_config_update(payload.apply(&get_global_config()), "patch")
+}
+
+pub async fn get_config_history_handler() -> Result<Json<Vec<ConfigVersion>>, StatusCode> {
+    get_config_history().map(Json).map_err(|e| {
+        tracing::error!("Failed to read config history: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+pub async fn rollback_config_handler(
+    Path(version): Path<u64>,
+) -> Result<Json<ProxyConfig>, (StatusCode, String)> {
+    let entry = match get_config_version(version) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("Config version {} not found", version),
+            ));
+        }
+        Err(e) => {
+            tracing::error!("Failed to read config history: {}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+        }
+    };
+
+    tracing::info!("Config rollback to version {} requested", version);
+    apply_config_update(entry.config, &format!("rollback to {}", version))
 }
 
 // ============================================================
@@ -97,6 +178,18 @@ pub struct ListQuery {
     pub is_blacklist: bool,
     pub text: Option<String>,
     pub config_type: ListConfigType,
+
+    /// Note kept with an added entry
+    pub comment: Option<String>,
+
+    /// Unix timestamp in seconds after which an added entry is removed
+    pub expires_at: Option<u64>,
+
+    /// Seconds after which an added entry is removed, instead of `expires_at`
+    pub expires_in: Option<u64>,
+
+    /// List the entries with their annotation and hit statistics
+    pub details: Option<bool>,
 }
 
 #[derive(Serialize)]
@@ -105,45 +198,55 @@ pub struct ListResponse {
     pub total: usize,
     pub config_type: ListConfigType,
     pub is_blacklist: bool,
+
+    /// The entries with their metadata, when asked for with `details`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules: Option<Vec<ListEntryDetails>>,
 }
 
 pub async fn get_list_handler(query: Query<ListQuery>) -> Json<ListResponse> {
     let query = query.0;
-    match query.is_blacklist {
-        true => {
-            let list = get_blacklist(query.config_type);
-            Json(ListResponse {
-                entries: list.clone(),
-                total: list.len(),
-                config_type: query.config_type,
-                is_blacklist: true,
-            })
-        }
-        false => {
-            let list = get_whitelist(query.config_type);
-            Json(ListResponse {
-                entries: list.clone(),
-                total: list.len(),
-                config_type: query.config_type,
-                is_blacklist: false,
-            })
-        }
-    }
+    let rules = match query.details.unwrap_or(false) {
+        true => Some(get_list_details(query.is_blacklist, query.config_type)),
+        false => None,
+    };
+    let list = match (&rules, query.is_blacklist) {
+        (Some(rules), _) => rules.iter().map(|rule| rule.value.clone()).collect(),
+        (None, true) => get_blacklist(query.config_type),
+        (None, false) => get_whitelist(query.config_type),
+    };
+
+    Json(ListResponse {
+        total: list.len(),
+        entries: list,
+        config_type: query.config_type,
+        is_blacklist: query.is_blacklist,
+        rules,
+    })
+}
+
+/// Body of a failed filter request, `code` is the stable error code also found in the logs.
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub code: &'static str,
+    pub error: String,
 }
 
-pub async fn add_to_list_handler(query: Query<ListQuery>) -> Result<StatusCode, StatusCode> {
+fn filter_error_response(error: FilterError) -> Response {
+    tracing::error!(error_code = error.code(), "{}", error);
+    let body = ErrorResponse {
+        code: error.code(),
+        error: error.to_string(),
+    };
+    (error.status(), Json(body)).into_response()
+}
+
+pub async fn add_to_list_handler(query: Query<ListQuery>) -> Result<StatusCode, Response> {
     let query = query.0;
 
-    let text = query.text.ok_or(StatusCode::BAD_REQUEST)?;
+    let text = query.text.ok_or(StatusCode::BAD_REQUEST.into_response())?;
     if text.trim().is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    if matches!(query.config_type, ListConfigType::Regex) {
-        if regex::Regex::new(&text).is_err() {
-            tracing::warn!("Invalid regex pattern: {}", text);
-            return Err(StatusCode::BAD_REQUEST);
-        }
+        return Err(StatusCode::BAD_REQUEST.into_response());
     }
 
     tracing::info!(
@@ -157,35 +260,34 @@ pub async fn add_to_list_handler(query: Query<ListQuery>) -> Result<StatusCode,
         query.config_type
     );
 
-    match query.is_blacklist {
-        true => add_domain_to_blacklist(&text, query.config_type)
-            .map(|_| StatusCode::CREATED)
-            .map_err(|e| {
-                tracing::error!("Failed to add to blacklist: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            }),
-        false => add_domain_to_whitelist(&text, query.config_type)
-            .map(|_| StatusCode::CREATED)
-            .map_err(|e| {
-                tracing::error!("Failed to add to whitelist: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            }),
-    }
+    let mut annotation = RuleAnnotation::new(RuleOrigin::Manual);
+    annotation.comment = query.comment.filter(|comment| !comment.trim().is_empty());
+    annotation.expires_at = match (query.expires_at, query.expires_in) {
+        (Some(_), Some(_)) => return Err(StatusCode::BAD_REQUEST.into_response()),
+        (Some(expires_at), None) => Some(expires_at),
+        (None, expires_in) => expires_in.map(|secs| {
+            annotation
+                .created_at
+                .unwrap_or_default()
+                .saturating_add(secs)
+        }),
+    };
+
+    let result = match query.is_blacklist {
+        true => add_domain_to_blacklist(&text, query.config_type, Some(annotation)),
+        false => add_domain_to_whitelist(&text, query.config_type, Some(annotation)),
+    };
+    result
+        .map(|_| StatusCode::CREATED)
+        .map_err(filter_error_response)
 }
 
-pub async fn remove_from_list_handler(query: Query<ListQuery>) -> Result<StatusCode, StatusCode> {
+pub async fn remove_from_list_handler(query: Query<ListQuery>) -> Result<StatusCode, Response> {
     let query = query.0;
 
-    let text = query.text.ok_or(StatusCode::BAD_REQUEST)?;
+    let text = query.text.ok_or(StatusCode::BAD_REQUEST.into_response())?;
     if text.trim().is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    if matches!(query.config_type, ListConfigType::Regex) {
-        if regex::Regex::new(&text).is_err() {
-            tracing::warn!("Invalid regex pattern: {}", text);
-            return Err(StatusCode::BAD_REQUEST);
-        }
+        return Err(StatusCode::BAD_REQUEST.into_response());
     }
 
     tracing::info!(
@@ -199,20 +301,13 @@ pub async fn remove_from_list_handler(query: Query<ListQuery>) -> Result<StatusC
         query.config_type
     );
 
-    match query.is_blacklist {
-        true => remove_domain_from_blacklist(&text, query.config_type)
-            .map(|_| StatusCode::OK)
-            .map_err(|e| {
-                tracing::error!("Failed to remove of blacklist: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            }),
-        false => remove_domain_from_whitelist(&text, query.config_type)
-            .map(|_| StatusCode::OK)
-            .map_err(|e| {
-                tracing::error!("Failed to remove of blacklist: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            }),
-    }
+    let result = match query.is_blacklist {
+        true => remove_domain_from_blacklist(&text, query.config_type),
+        false => remove_domain_from_whitelist(&text, query.config_type),
+    };
+    result
+        .map(|_| StatusCode::OK)
+        .map_err(filter_error_response)
 }
 
 #[derive(Deserialize)]
@@ -222,7 +317,7 @@ pub struct UpdateAdListQuery {
 
 pub async fn update_ad_list_handler(
     Query(query): Query<UpdateAdListQuery>,
-) -> Result<StatusCode, StatusCode> {
+) -> Result<StatusCode, Response> {
     tracing::info!("Updating ad list...");
 
     let updated_filter_path = CONFIG_PATH.join("filter.updated.toml");
@@ -235,16 +330,88 @@ pub async fn update_ad_list_handler(
         merge_from_file(updated_filter_path)
     };
 
-    match result {
-        Ok(_) => {
+    result
+        .map(|_| {
             tracing::info!("Ad list updated successfully");
-            Ok(StatusCode::OK)
-        }
-        Err(e) => {
-            tracing::error!("Failed to update ad list: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
-        }
-    }
+            StatusCode::OK
+        })
+        .map_err(filter_error_response)
+}
+
+/// Invalid lines listed in an import response, the count covers all of them.
+const MAX_REPORTED_INVALID_LINES: usize = 100;
+
+#[derive(Deserialize)]
+pub struct ImportListQuery {
+    pub format: Option<ListFormat>,
+    pub replace: Option<bool>,
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct ImportListResponse {
+    pub lines: usize,
+    pub blacklist: usize,
+    pub whitelist: usize,
+    pub network_rules: usize,
+    pub cosmetic_rules: usize,
+    pub duplicates: usize,
+    pub invalid_count: usize,
+    pub invalid: Vec<InvalidLine>,
+
+    /// Missing for a dry run
+    #[serde(flatten)]
+    pub result: Option<ImportResult>,
+}
+
+/// Import an uploaded blocklist, sent as the request body.
+pub async fn import_list_handler(
+    Query(query): Query<ImportListQuery>,
+    body: Bytes,
+) -> Result<Json<ImportListResponse>, Response> {
+    let format = query.format.unwrap_or_default();
+    let dry_run = query.dry_run.unwrap_or(false);
+    tracing::info!(
+        "Importing an uploaded {:?} list of {} bytes{}",
+        format,
+        body.len(),
+        if dry_run { " (dry run)" } else { "" }
+    );
+
+    // Parsing and compiling a large list is CPU bound
+    let task = tokio::task::spawn_blocking(move || {
+        let report = parse_list("upload", &String::from_utf8_lossy(&body), format);
+        let result = match dry_run {
+            true => None,
+            false => Some(import_domains(&report, query.replace.unwrap_or(false))?),
+        };
+        Ok::<_, FilterError>((report, result))
+    });
+    let (mut report, result) = task
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?
+        .map_err(filter_error_response)?;
+
+    tracing::info!(
+        "Imported {} blocked and {} allowed domains, {} invalid lines",
+        report.blacklist.len(),
+        report.whitelist.len(),
+        report.invalid.len()
+    );
+
+    let invalid_count = report.invalid.len();
+    report.invalid.truncate(MAX_REPORTED_INVALID_LINES);
+    Ok(Json(ImportListResponse {
+        lines: report.lines,
+        blacklist: report.blacklist.len(),
+        whitelist: report.whitelist.len(),
+        network_rules: report.network_rules.len(),
+        cosmetic_rules: report.cosmetic_rules.len(),
+        duplicates: report.duplicates,
+        invalid_count,
+        invalid: report.invalid,
+        result,
+    }))
 }
 
 #[derive(Deserialize)]
@@ -261,10 +428,10 @@ pub async fn is_domain_in(
     Path(domain): Path<String>,
     Query(query): Query<IsDomainInQuery>,
 ) -> Json<IsDomainInResponse> {
-    let found = match query.is_blacklist {
-        true => is_domain_blacklisted(&domain),
-        false => is_domain_whitelisted(&domain),
-    };
+    // Looked up without counting a hit, only the proxy counts them
+    let found = explain_domain(&domain)
+        .iter()
+        .any(|entry| entry.is_blacklist == query.is_blacklist);
 
     tracing::info!(
         "Domain '{}' is {}in the {}",
@@ -279,3 +446,468 @@ pub async fn is_domain_in(
 
     Json(IsDomainInResponse { found })
 }
+
+#[derive(Deserialize)]
+pub struct ExplainQuery {
+    /// URL or domain
+    pub target: String,
+
+    /// Address of the client making the request, the access control lists are only checked for
+    /// a client
+    pub client: Option<IpAddr>,
+
+    /// URL of the page making the request, as its Referer
+    pub source: Option<String>,
+
+    /// Replaces the type guessed from the URL, named like in rule options (`script`, `image`...)
+    pub resource_type: Option<ResourceType>,
+}
+
+/// Step of the filtering that decided, in the order requests go through them.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecisionStep {
+    ClientDenied,
+    AdBlockingDisabled,
+    ClientExempt,
+    ClientPaused,
+    TemporaryAllow,
+    Whitelist,
+    Blacklist,
+    NetworkRule,
+    NetworkException,
+    NotListed,
+}
+
+#[derive(Serialize)]
+pub struct ExplainResponse {
+    pub domain: String,
+    pub client: Option<IpAddr>,
+    pub blocked: bool,
+    pub decided_by: DecisionStep,
+    pub reason: String,
+
+    /// Entries of both lists matching the domain, whitelist first
+    pub matches: Vec<ListMatch>,
+
+    /// Whether a whitelist entry overrides a matching blacklist entry
+    pub whitelist_override: bool,
+
+    /// Network rules matching the URL, evaluated even when an earlier step decided. Missing when
+    /// the target is a domain.
+    pub network: Option<NetworkDecision>,
+}
+
+fn describe_origin(subscription: &Option<String>, default: &str) -> String {
+    match subscription {
+        Some(name) => format!("subscription '{}'", name),
+        None => default.to_string(),
+    }
+}
+
+fn describe_list_match(entry: &ListMatch) -> String {
+    format!(
+        "{} entry '{}' of {}",
+        format!("{:?}", entry.config_type).to_lowercase(),
+        entry.entry,
+        describe_origin(&entry.subscription, "the filter file")
+    )
+}
+
+fn describe_rule(rule: &MatchedRule) -> String {
+    format!(
+        "'{}' of {}",
+        rule.rule,
+        describe_origin(&rule.subscription, "the network rules file")
+    )
+}
+
+/// Why requests to a URL or domain are blocked or allowed: the entries and rules matching it, the
+/// subscriptions they come from and the step that decided.
+pub async fn explain_decision_handler(
+    Query(query): Query<ExplainQuery>,
+) -> Result<Json<ExplainResponse>, (StatusCode, String)> {
+    let target = query.target.trim();
+    let url = match target.contains("://") {
+        true => Some(target.to_string()),
+        false if target.contains('/') => Some(format!("http://{}", target)),
+        false => None,
+    };
+    let domain = match &url {
+        Some(url) => url
+            .parse::<http::Uri>()
+            .ok()
+            .and_then(|uri| uri.host().map(normalize_host)),
+        None => Some(normalize_hostname(target)),
+    }
+    .filter(|domain| !domain.is_empty())
+    .ok_or((
+        StatusCode::BAD_REQUEST,
+        format!("'{}' is neither a URL nor a domain", target),
+    ))?;
+
+    let matches = explain_domain(&domain);
+    let whitelisted = matches.iter().find(|entry| !entry.is_blacklist);
+    let blacklisted = matches.iter().find(|entry| entry.is_blacklist);
+
+    let network = url.as_deref().map(|url| {
+        let headers = query
+            .source
+            .iter()
+            .map(|source| ("referer".to_string(), source.clone()))
+            .collect::<HashMap<_, _>>();
+        explain_request(url, &headers, &domain, query.resource_type)
+    });
+
+    let config = get_global_config();
+    let is_denied = |scope| query.client.is_some_and(|ip| !is_client_allowed(ip, scope));
+    let (decided_by, reason) = if is_denied(AclScope::Proxy) {
+        (
+            DecisionStep::ClientDenied,
+            "The access control lists do not allow the client to use the proxy".to_string(),
+        )
+    } else if !config.block_ads {
+        (
+            DecisionStep::AdBlockingDisabled,
+            "Ad blocking is disabled in the configuration".to_string(),
+        )
+    } else if is_denied(AclScope::BlockAds) {
+        (
+            DecisionStep::ClientExempt,
+            "The access control lists exempt the client from ad blocking".to_string(),
+        )
+    } else if let Some(rule) = query.client.and_then(blocking_pause) {
+        (
+            DecisionStep::ClientPaused,
+            format!(
+                "Ad blocking is paused for the client by temporary rule {} until {}",
+                rule.id, rule.expires_at
+            ),
+        )
+    } else if let Some(rule) = temporary_allow(&domain) {
+        (
+            DecisionStep::TemporaryAllow,
+            format!(
+                "Allowed by temporary rule {} until {}",
+                rule.id, rule.expires_at
+            ),
+        )
+    } else if let Some(entry) = whitelisted {
+        let reason = match blacklisted {
+            Some(blocked) => format!(
+                "Allowed by the whitelist {}, which overrides the blacklist {}",
+                describe_list_match(entry),
+                describe_list_match(blocked)
+            ),
+            None => format!("Allowed by the whitelist {}", describe_list_match(entry)),
+        };
+        (DecisionStep::Whitelist, reason)
+    } else if let Some(entry) = blacklisted {
+        (
+            DecisionStep::Blacklist,
+            format!("Blocked by the blacklist {}", describe_list_match(entry)),
+        )
+    } else if let Some(network) = &network
+        && let Some(rule) = &network.rule
+    {
+        match &network.exception {
+            Some(exception) => (
+                DecisionStep::NetworkException,
+                format!(
+                    "The network rule {} is overridden by the exception {}",
+                    describe_rule(rule),
+                    describe_rule(exception)
+                ),
+            ),
+            None => (
+                DecisionStep::NetworkRule,
+                format!("Blocked by the network rule {}", describe_rule(rule)),
+            ),
+        }
+    } else {
+        (
+            DecisionStep::NotListed,
+            "No filter entry or network rule matches".to_string(),
+        )
+    };
+
+    let blocked = matches!(
+        decided_by,
+        DecisionStep::ClientDenied | DecisionStep::Blacklist | DecisionStep::NetworkRule
+    );
+    tracing::info!("Explained '{}': {}", target, reason);
+
+    Ok(Json(ExplainResponse {
+        domain,
+        client: query.client,
+        blocked,
+        decided_by,
+        reason,
+        whitelist_override: whitelisted.is_some() && blacklisted.is_some(),
+        matches,
+        network,
+    }))
+}
+
+// ============================================================
+// Temporary Rule Handlers
+// ============================================================
+
+#[derive(Deserialize)]
+pub struct TemporaryRuleRequest {
+    #[serde(flatten)]
+    pub target: TemporaryTarget,
+
+    /// Defaults to ten minutes
+    pub duration_secs: Option<u64>,
+    pub comment: Option<String>,
+}
+
+pub async fn get_temporary_rules_handler() -> Json<Vec<TemporaryRule>> {
+    Json(get_temporary_rules())
+}
+
+/// Allow a domain or pause ad blocking for a client until the rule expires, without touching the
+/// filter file.
+pub async fn add_temporary_rule_handler(
+    Json(request): Json<TemporaryRuleRequest>,
+) -> Result<(StatusCode, Json<TemporaryRule>), Response> {
+    let rule = add_temporary_rule(
+        request.target,
+        request.duration_secs.unwrap_or(DEFAULT_TEMPORARY_RULE_SECS),
+        request.comment,
+    )
+    .map_err(filter_error_response)?;
+
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+pub async fn remove_temporary_rule_handler(
+    Path(id): Path<u64>,
+) -> Result<Json<TemporaryRule>, Response> {
+    let rule = remove_temporary_rule(id).map_err(filter_error_response)?;
+    tracing::info!("Temporary rule {} ({:?}) reverted", rule.id, rule.target);
+    Ok(Json(rule))
+}
+
+// ============================================================
+// Subscription Handlers
+// ============================================================
+
+pub async fn get_subscriptions_handler() -> Json<Vec<SubscriptionStatus>> {
+    Json(get_subscriptions())
+}
+
+/// Add a subscription, its first download starts right away.
+pub async fn add_subscription_handler(
+    Json(subscription): Json<Subscription>,
+) -> Result<StatusCode, Response> {
+    let name = subscription.name.clone();
+    add_subscription(subscription).map_err(filter_error_response)?;
+    tracing::info!("Subscription '{}' added", name);
+
+    tokio::spawn(async move {
+        if let Err(e) = refresh_subscription_now(&name).await {
+            tracing::warn!("Unable to refresh subscription '{}': {}", name, e);
+        }
+    });
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn remove_subscription_handler(Path(name): Path<String>) -> Result<StatusCode, Response> {
+    remove_subscription(&name).map_err(filter_error_response)?;
+    tracing::info!("Subscription '{}' removed", name);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Refresh a subscription now. A failed download is reported in `last_error`, the previous
+/// entries stay active.
+pub async fn refresh_subscription_handler(
+    Path(name): Path<String>,
+) -> Result<Json<SubscriptionStatus>, Response> {
+    refresh_subscription_now(&name)
+        .await
+        .map(Json)
+        .map_err(filter_error_response)
+}
+
+// ============================================================
+// User Handlers
+// ============================================================
+
+#[derive(Deserialize)]
+pub struct UserPayload {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize)]
+pub struct UsersResponse {
+    pub users: Vec<String>,
+    pub total: usize,
+}
+
+pub async fn get_users_handler() -> Json<UsersResponse> {
+    let users = get_users();
+    Json(UsersResponse {
+        total: users.len(),
+        users,
+    })
+}
+
+pub async fn set_user_handler(Json(payload): Json<UserPayload>) -> Result<StatusCode, StatusCode> {
+    let username = payload.username.trim();
+
+    // The colon separates user and password in Basic credentials
+    if username.is_empty() || username.contains(':') || payload.password.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    tracing::info!("Setting credentials for proxy user '{}'", username);
+
+    add_user(username, &payload.password)
+        .await
+        .map(|_| StatusCode::CREATED)
+        .map_err(|e| {
+            tracing::error!("Failed to set proxy user: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+pub async fn remove_user_handler(Path(username): Path<String>) -> Result<StatusCode, StatusCode> {
+    tracing::info!("Removing proxy user '{}'", username);
+
+    match remove_user(&username) {
+        Ok(true) => Ok(StatusCode::OK),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to remove proxy user: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// ============================================================
+// Access Control Handlers
+// ============================================================
+
+#[derive(Deserialize)]
+pub struct AclRuleQuery {
+    pub scope: AclScope,
+    pub action: AclAction,
+    pub cidr: String,
+}
+
+#[derive(Deserialize)]
+pub struct AclDefaultQuery {
+    pub scope: AclScope,
+    pub action: AclAction,
+}
+
+pub async fn get_acl_handler() -> Json<AccessControlList> {
+    Json(get_access_control_lists())
+}
+
+pub async fn add_acl_rule_handler(
+    Query(query): Query<AclRuleQuery>,
+) -> Result<StatusCode, StatusCode> {
+    let cidr: IpCidr = query.cidr.parse().map_err(|e| {
+        tracing::warn!("Invalid CIDR: {}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    tracing::info!(
+        "Adding {:?} rule for {} (scope: {:?})",
+        query.action,
+        cidr,
+        query.scope
+    );
+
+    add_acl_rule(query.scope, query.action, cidr)
+        .map(|_| StatusCode::CREATED)
+        .map_err(|e| {
+            tracing::error!("Failed to add access control rule: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+pub async fn remove_acl_rule_handler(
+    Query(query): Query<AclRuleQuery>,
+) -> Result<StatusCode, StatusCode> {
+    let cidr: IpCidr = query.cidr.parse().map_err(|e| {
+        tracing::warn!("Invalid CIDR: {}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    tracing::info!(
+        "Removing {:?} rule for {} (scope: {:?})",
+        query.action,
+        cidr,
+        query.scope
+    );
+
+    match remove_acl_rule(query.scope, query.action, cidr) {
+        Ok(true) => Ok(StatusCode::OK),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to remove access control rule: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn set_acl_default_handler(
+    Query(query): Query<AclDefaultQuery>,
+) -> Result<StatusCode, StatusCode> {
+    tracing::info!(
+        "Setting default action {:?} (scope: {:?})",
+        query.action,
+        query.scope
+    );
+
+    set_acl_default(query.scope, query.action)
+        .map(|_| StatusCode::OK)
+        .map_err(|e| {
+            tracing::error!("Failed to set default access control action: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+// ============================================================
+// Limits Handlers
+// ============================================================
+
+pub async fn get_limits_handler() -> Json<LimitsConfig> {
+    Json(get_global_limits())
+}
+
+pub async fn update_limits_handler(
+    Json(payload): Json<LimitsConfig>,
+) -> Result<Json<LimitsConfig>, StatusCode> {
+    if let Err(e) = payload.validate() {
+        tracing::warn!("Invalid limits: {}", e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    tracing::info!("Limits update requested: {:?}", payload);
+    set_global_limits(payload);
+    Ok(Json(get_global_limits()))
+}
+
+pub async fn get_connections_handler() -> Json<ConnectionStats> {
+    Json(get_connection_stats())
+}
+
+// ============================================================
+// DNS Handlers
+// ============================================================
+
+pub async fn get_dns_cache_handler() -> Json<DnsCacheStats> {
+    Json(get_dns_cache_stats())
+}
+
+pub async fn flush_dns_cache_handler() -> Json<DnsCacheStats> {
+    flush_dns_cache();
+    Json(get_dns_cache_stats())
+}
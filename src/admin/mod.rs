@@ -1,20 +1,45 @@
 pub mod handlers;
 pub mod routes;
 
+use std::future::IntoFuture;
 use std::net::SocketAddr;
 
-use axum::Router;
-use tokio::net::TcpListener;
+use axum::{
+    Router,
+    extract::{ConnectInfo, Request},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::Response,
+};
+use futures_util::future::try_join_all;
 use tower_http::cors::{Any, CorsLayer};
 
-use crate::utils::DNS_RESOLVER;
-use routes::{create_config_routes, create_health_routes, create_list_routes};
+use crate::acl::{AclScope, is_client_allowed};
+use crate::shutdown::shutdown_token;
+use crate::utils::listen::{ListenFamily, bind_tcp_listeners};
+use routes::{
+    create_acl_routes, create_config_routes, create_dns_routes, create_health_routes,
+    create_limits_routes, create_list_routes, create_subscription_routes, create_user_routes,
+};
+
+async fn admin_access_control(
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if !is_client_allowed(peer_addr.ip(), AclScope::Admin) {
+        tracing::warn!("Admin request from {} denied by access control", peer_addr);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(next.run(request).await)
+}
 
 #[tracing::instrument(level = "info", name = "Admin Server")]
 pub async fn start_admin_server(
     host: String,
     port: u16,
-    is_v4: Option<bool>,
+    family: ListenFamily,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let cors = CorsLayer::new()
         .allow_methods(Any)
@@ -25,25 +50,33 @@ pub async fn start_admin_server(
         .merge(create_config_routes())
         .merge(create_health_routes())
         .merge(create_list_routes())
+        .merge(create_subscription_routes())
+        .merge(create_user_routes())
+        .merge(create_acl_routes())
+        .merge(create_limits_routes())
+        .merge(create_dns_routes())
+        .layer(middleware::from_fn(admin_access_control))
         .layer(cors);
 
-    let lookup = DNS_RESOLVER.lookup_ip(host).await?;
-    let ip = match is_v4 {
-        Some(false) => lookup
-            .iter()
-            .find(|ip| ip.is_ipv6())
-            .ok_or("No IPv6 address found for the specified host")?,
-        _ => lookup
-            .iter()
-            .find(|ip| ip.is_ipv4())
-            .ok_or("No IPv4 address found for the specified host")?,
-    };
-
-    let addr = SocketAddr::new(ip, port);
-    tracing::info!("Starting admin server at http://{}", addr);
-    let listener = TcpListener::bind(addr).await?;
-
-    axum::serve(listener, app).await?;
+    let listeners = bind_tcp_listeners(&host, port, family).await?;
+    let servers = listeners
+        .into_iter()
+        .map(|listener| {
+            if let Ok(addr) = listener.local_addr() {
+                tracing::info!("Starting admin server at http://{}", addr);
+            }
+
+            axum::serve(
+                listener,
+                app.clone()
+                    .into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown_token().cancelled_owned())
+            .into_future()
+        })
+        .collect::<Vec<_>>();
+
+    try_join_all(servers).await?;
 
     Ok(())
 }
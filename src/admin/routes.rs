@@ -1,24 +1,40 @@
 use axum::{
     Router,
-    routing::{get, put},
+    extract::DefaultBodyLimit,
+    routing::{delete, get, post, put},
 };
 
 use super::handlers::{
-    add_to_list_handler, get_config_handler, get_health_handler, get_list_handler, is_domain_in,
-    remove_from_list_handler, update_ad_list_handler, update_config_handler,
+    add_acl_rule_handler, add_subscription_handler, add_temporary_rule_handler,
+    add_to_list_handler, explain_decision_handler, flush_dns_cache_handler, get_acl_handler,
+    get_config_handler, get_config_history_handler, get_connections_handler, get_dns_cache_handler,
+    get_health_handler, get_limits_handler, get_list_handler, get_subscriptions_handler,
+    get_temporary_rules_handler, get_users_handler, import_list_handler, is_domain_in,
+    patch_config_handler, refresh_subscription_handler, remove_acl_rule_handler,
+    remove_from_list_handler, remove_subscription_handler, remove_temporary_rule_handler,
+    remove_user_handler, rollback_config_handler, set_acl_default_handler, set_user_handler,
+    update_ad_list_handler, update_config_handler, update_limits_handler,
 };
 
 pub fn create_config_routes() -> Router {
-    Router::new().route(
-        "/config",
-        get(get_config_handler).put(update_config_handler),
-    )
+    Router::new()
+        .route(
+            "/config",
+            get(get_config_handler)
+                .put(update_config_handler)
+                .patch(patch_config_handler),
+        )
+        .route("/config/history", get(get_config_history_handler))
+        .route("/config/rollback/{version}", post(rollback_config_handler))
 }
 
 pub fn create_health_routes() -> Router {
     Router::new().route("/health", get(get_health_handler))
 }
 
+/// Uploaded blocklists, hosts lists with a million entries are around 30 MiB.
+const IMPORT_MAX_BODY_BYTES: usize = 128 * 1024 * 1024;
+
 pub fn create_list_routes() -> Router {
     Router::new()
         .route(
@@ -28,5 +44,64 @@ pub fn create_list_routes() -> Router {
                 .delete(remove_from_list_handler),
         )
         .route("/list/update-ads", put(update_ad_list_handler))
+        .route(
+            "/list/import",
+            post(import_list_handler).layer(DefaultBodyLimit::max(IMPORT_MAX_BODY_BYTES)),
+        )
+        .route("/list/explain", get(explain_decision_handler))
+        .route(
+            "/list/temporary",
+            get(get_temporary_rules_handler).post(add_temporary_rule_handler),
+        )
+        .route(
+            "/list/temporary/{id}",
+            delete(remove_temporary_rule_handler),
+        )
         .route("/list/{domain}", get(is_domain_in))
 }
+
+pub fn create_subscription_routes() -> Router {
+    Router::new()
+        .route(
+            "/subscriptions",
+            get(get_subscriptions_handler).post(add_subscription_handler),
+        )
+        .route("/subscriptions/{name}", delete(remove_subscription_handler))
+        .route(
+            "/subscriptions/{name}/refresh",
+            post(refresh_subscription_handler),
+        )
+}
+
+pub fn create_user_routes() -> Router {
+    Router::new()
+        .route("/users", get(get_users_handler).post(set_user_handler))
+        .route("/users/{username}", delete(remove_user_handler))
+}
+
+pub fn create_acl_routes() -> Router {
+    Router::new()
+        .route(
+            "/acl",
+            get(get_acl_handler)
+                .post(add_acl_rule_handler)
+                .delete(remove_acl_rule_handler),
+        )
+        .route("/acl/default", put(set_acl_default_handler))
+}
+
+pub fn create_limits_routes() -> Router {
+    Router::new()
+        .route(
+            "/limits",
+            get(get_limits_handler).put(update_limits_handler),
+        )
+        .route("/limits/connections", get(get_connections_handler))
+}
+
+pub fn create_dns_routes() -> Router {
+    Router::new().route(
+        "/dns/cache",
+        get(get_dns_cache_handler).delete(flush_dns_cache_handler),
+    )
+}
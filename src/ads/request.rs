@@ -1,6 +1,150 @@
+use std::collections::HashMap;
+
+use crate::filters::{
+    NETWORK_FILTER, NetworkDecision, RequestContext, ResourceType, is_third_party,
+};
 use crate::schemas::Request;
+use crate::utils::http::normalize_host;
 
+/// Requests are forwarded unchanged, whether they are blocked is decided by [`blocking_rule`].
 pub fn analyze_and_modify_request(req: &Request) -> Request {
-    // TODO: Implement ad-blocking logic here.
     req.clone()
 }
+
+fn header<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.trim())
+        .filter(|v| !v.is_empty())
+}
+
+fn url_host(url: &str) -> Option<String> {
+    url.parse::<http::Uri>()
+        .ok()
+        .and_then(|uri| uri.host().map(normalize_host))
+        .filter(|host| !host.is_empty())
+}
+
+/// Kind of resource requested, from the Sec-Fetch-Dest header browsers send, or guessed from the
+/// other headers and the extension of the path.
+fn resource_type(headers: &HashMap<String, String>, url: &str) -> ResourceType {
+    let is_websocket =
+        header(headers, "upgrade").is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+
+    if let Some(destination) = header(headers, "sec-fetch-dest") {
+        return match destination.to_ascii_lowercase().as_str() {
+            "document" => ResourceType::Document,
+            "iframe" | "frame" | "fencedframe" => ResourceType::Subdocument,
+            "script" | "worker" | "sharedworker" | "serviceworker" | "audioworklet"
+            | "paintworklet" => ResourceType::Script,
+            "style" => ResourceType::Stylesheet,
+            "image" => ResourceType::Image,
+            "font" => ResourceType::Font,
+            "audio" | "video" | "track" => ResourceType::Media,
+            "object" | "embed" => ResourceType::Object,
+            "report" => ResourceType::Ping,
+            "empty" if is_websocket => ResourceType::Websocket,
+            "empty" => ResourceType::XmlHttpRequest,
+            _ => ResourceType::Other,
+        };
+    }
+
+    if is_websocket {
+        return ResourceType::Websocket;
+    }
+    if header(headers, "x-requested-with").is_some() {
+        return ResourceType::XmlHttpRequest;
+    }
+
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let extension = path
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("js" | "mjs") => ResourceType::Script,
+        Some("css") => ResourceType::Stylesheet,
+        Some("png" | "jpg" | "jpeg" | "gif" | "webp" | "avif" | "svg" | "ico") => {
+            ResourceType::Image
+        }
+        Some("woff" | "woff2" | "ttf" | "otf") => ResourceType::Font,
+        Some("mp4" | "webm" | "mp3" | "m3u8" | "ogg") => ResourceType::Media,
+        _ if header(headers, "accept").is_some_and(|accept| accept.starts_with("text/html")) => {
+            ResourceType::Document
+        }
+        _ => ResourceType::Other,
+    }
+}
+
+/// The network rule blocking a request to `host`, if any. `scheme` completes origin-form targets
+/// into the full URL the rules match. The page that made the request is known from its Referer or
+/// Origin, and Sec-Fetch-Site tells whether it is a third-party request.
+pub fn blocking_rule(
+    uri: &str,
+    headers: &HashMap<String, String>,
+    scheme: &str,
+    host: &str,
+) -> Option<String> {
+    let filter = NETWORK_FILTER.load();
+    if filter.is_empty() {
+        return None;
+    }
+
+    with_request_context(uri, headers, scheme, host, None, |context| {
+        filter.blocking_rule(context).map(str::to_string)
+    })
+}
+
+/// How the network rules decide on a request, like [`blocking_rule`] but with the rules involved.
+/// `resource_type` replaces the type guessed from the headers and the URL.
+pub fn explain_request(
+    url: &str,
+    headers: &HashMap<String, String>,
+    host: &str,
+    resource_type: Option<ResourceType>,
+) -> NetworkDecision {
+    let filter = NETWORK_FILTER.load();
+    with_request_context(url, headers, "http", host, resource_type, |context| {
+        filter.explain(context)
+    })
+}
+
+fn with_request_context<T>(
+    uri: &str,
+    headers: &HashMap<String, String>,
+    scheme: &str,
+    host: &str,
+    resource_type: Option<ResourceType>,
+    check: impl FnOnce(&RequestContext) -> T,
+) -> T {
+    let url = match uri.starts_with("http://") || uri.starts_with("https://") {
+        true => uri.to_string(),
+        false => format!("{}://{}{}", scheme, host, uri),
+    };
+
+    let resource_type = resource_type.unwrap_or_else(|| self::resource_type(headers, &url));
+    let source_url = header(headers, "referer").or_else(|| header(headers, "origin"));
+    let source_host = source_url.and_then(url_host);
+
+    let third_party = match header(headers, "sec-fetch-site") {
+        Some("cross-site") => Some(true),
+        Some("same-site" | "same-origin" | "none") => Some(false),
+        _ => match &source_host {
+            Some(source_host) => Some(is_third_party(host, source_host)),
+            // A page loaded without a referer was opened by the user
+            None if resource_type == ResourceType::Document => Some(false),
+            None => None,
+        },
+    };
+
+    let context = RequestContext {
+        url: &url,
+        resource_type,
+        source_host: source_host.as_deref(),
+        source_url,
+        third_party,
+    };
+    check(&context)
+}
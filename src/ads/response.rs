@@ -1,13 +1,17 @@
-use crate::filters::is_domain_blacklisted;
+use crate::filters::{COSMETIC_FILTER, is_domain_blocked};
 use crate::schemas::Response;
 
-pub fn analyze_and_modify_response(resp: &Response) -> Response {
+/// Rewrite an HTML page of `host`, before it reaches the client.
+pub fn analyze_and_modify_response(resp: &Response, host: &str) -> Response {
     let mut modified_response = resp.clone();
 
     csp_stripping(&mut modified_response);
 
     if let Some(html) = resp.body_as_string() {
         let mut modified_html = remove_ad_scripts(&html);
+        if let Some(css) = COSMETIC_FILTER.load().style_sheet(host, &html) {
+            modified_html = inject_style(&modified_html, &css);
+        }
         // modified_html = inject_mutation_observer(&modified_html);
         modified_html = inject_customs_script(
             &modified_html,
@@ -40,7 +44,7 @@ pub fn remove_ad_scripts(html: &str) -> String {
     let mut modified_html = strip_inline_adsbygoogle_scripts(html);
 
     for (script_snippet, [host]) in external_re.captures_iter(html).map(|c| c.extract()) {
-        if is_domain_blacklisted(host) {
+        if is_domain_blocked(host) {
             modified_html = modified_html.replace(&script_snippet, "");
         }
     }
@@ -121,6 +125,21 @@ fn inject_mutation_observer(html: &str) -> String {
     );
 }
 
+/// Add a style sheet at the end of the head, so it wins over the styles of the page.
+pub fn inject_style(html: &str, css: &str) -> String {
+    let injection = format!("<style>\n{}</style>", css);
+    let pos = html
+        .find("</head>")
+        .or_else(|| html.find("</HEAD>"))
+        .unwrap_or(0);
+
+    let mut modified_html = String::with_capacity(html.len() + injection.len());
+    modified_html.push_str(&html[..pos]);
+    modified_html.push_str(&injection);
+    modified_html.push_str(&html[pos..]);
+    modified_html
+}
+
 pub fn inject_customs_script(html: &str, script: &str) -> String {
     let injection = format!("<script>{}</script>", script);
     if let Some(pos) = html.rfind("</body>") {
@@ -0,0 +1,8 @@
+// Proxy authentication: per-user credentials stored hashed under the configuration path
+// and checked against the `Proxy-Authorization` header of HTTP and CONNECT requests.
+
+mod user_store;
+pub mod utils;
+
+pub use user_store::UserStore;
+pub use utils::*;
@@ -0,0 +1,249 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    sync::{Arc, LazyLock, Mutex, RwLock},
+};
+
+use openssl::{base64, hash::MessageDigest, memcmp, pkcs5::pbkdf2_hmac, rand::rand_bytes, sha};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AUTH_PBKDF2_ITERATIONS, CONFIG_PATH};
+
+static USERS_PATH: LazyLock<PathBuf> = LazyLock::new(|| CONFIG_PATH.join("users.toml"));
+
+// Successful verifications keyed by a SHA-256 digest of the raw credentials, so PBKDF2 only
+// runs once per client instead of on every request. Cleared whenever the store changes.
+static VERIFIED_CACHE: LazyLock<Mutex<VerifiedCache>> =
+    LazyLock::new(|| Mutex::new(VerifiedCache::default()));
+
+/// Verified credentials kept at most, the oldest are forgotten first.
+const VERIFIED_CACHE_CAPACITY: usize = 1024;
+
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+// TOML file content
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UsersConfig {
+    #[serde(default)]
+    users: Vec<UserEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UserEntry {
+    username: String,
+    password_hash: String,
+}
+
+#[derive(Debug, Default)]
+struct VerifiedCache {
+    users: HashMap<[u8; 32], String>,
+    order: VecDeque<[u8; 32]>,
+
+    /// Bumped on every clear, so a verification started before a password change is not cached
+    generation: u64,
+}
+
+impl VerifiedCache {
+    fn insert(&mut self, digest: [u8; 32], username: String) {
+        if self.users.insert(digest, username).is_some() {
+            return;
+        }
+
+        self.order.push_back(digest);
+        while self.order.len() > VERIFIED_CACHE_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.users.remove(&oldest);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.users.clear();
+        self.order.clear();
+        self.generation += 1;
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct UserStore {
+    pub file: PathBuf,
+
+    /// Username to password hash, encoded as `pbkdf2-sha256$<iterations>$<salt>$<key>`
+    users: HashMap<String, String>,
+}
+
+fn hash_password(
+    password: &str,
+    iterations: usize,
+    salt: &[u8],
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut key = vec![0u8; KEY_LEN];
+    pbkdf2_hmac(
+        password.as_bytes(),
+        salt,
+        iterations,
+        MessageDigest::sha256(),
+        &mut key,
+    )?;
+    Ok(key)
+}
+
+pub(super) fn encode_password_hash(
+    password: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut salt = [0u8; SALT_LEN];
+    rand_bytes(&mut salt)?;
+
+    let key = hash_password(password, AUTH_PBKDF2_ITERATIONS, &salt)?;
+    Ok(format!(
+        "{}${}${}${}",
+        HASH_SCHEME,
+        AUTH_PBKDF2_ITERATIONS,
+        base64::encode_block(&salt),
+        base64::encode_block(&key)
+    ))
+}
+
+fn verify_password_hash(password: &str, encoded: &str) -> bool {
+    let [scheme, iterations, salt, key] = encoded.split('$').collect::<Vec<&str>>()[..] else {
+        return false;
+    };
+
+    if scheme != HASH_SCHEME {
+        return false;
+    }
+
+    let (Ok(iterations), Ok(salt), Ok(expected)) = (
+        iterations.parse::<usize>(),
+        base64::decode_block(salt),
+        base64::decode_block(key),
+    ) else {
+        return false;
+    };
+
+    match hash_password(password, iterations, &salt) {
+        Ok(key) => key.len() == expected.len() && memcmp::eq(&key, &expected),
+        Err(_) => false,
+    }
+}
+
+impl UserStore {
+    pub fn load(file: Option<PathBuf>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let file = file.unwrap_or(USERS_PATH.clone());
+        let content = std::fs::read_to_string(&file).unwrap_or_default();
+        let config: UsersConfig = toml::from_str(&content)
+            .map_err(|e| format!("Invalid proxy users file {:?}: {}", file, e))?;
+
+        let users = config
+            .users
+            .into_iter()
+            .map(|entry| (entry.username, entry.password_hash))
+            .collect();
+
+        Ok(UserStore { file, users })
+    }
+
+    /// Dump the current users to the TOML file with an atomic write.
+    fn dump_file(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut users = self
+            .users
+            .iter()
+            .map(|(username, password_hash)| UserEntry {
+                username: username.clone(),
+                password_hash: password_hash.clone(),
+            })
+            .collect::<Vec<_>>();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+
+        let toml_str = toml::to_string(&UsersConfig { users })?;
+        if let Some(parent) = self.file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let temp_path = self.file.with_extension("tmp");
+        std::fs::write(&temp_path, toml_str)?;
+        std::fs::rename(&temp_path, &self.file)?;
+
+        VERIFIED_CACHE.lock().unwrap().clear();
+        Ok(())
+    }
+
+    pub fn usernames(&self) -> Vec<String> {
+        let mut usernames = self.users.keys().cloned().collect::<Vec<_>>();
+        usernames.sort();
+        usernames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    /// Create the user or replace its password hash, then persist the store. The hash is
+    /// computed by the caller, so the store is not locked while PBKDF2 runs.
+    pub fn set_user(
+        &mut self,
+        username: &str,
+        password_hash: String,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.users.insert(username.to_string(), password_hash);
+        self.dump_file()
+    }
+
+    /// Remove the user and persist the store. Returns whether the user existed.
+    pub fn remove_user(
+        &mut self,
+        username: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        if self.users.remove(username).is_none() {
+            return Ok(false);
+        }
+
+        self.dump_file()?;
+        Ok(true)
+    }
+
+    fn password_hash(&self, username: &str) -> Option<String> {
+        self.users.get(username).cloned()
+    }
+}
+
+/// Check the credentials against the store. PBKDF2 is slow by design, so the hash runs on the
+/// blocking pool instead of an async worker.
+pub async fn verify_credentials(username: &str, password: &str) -> bool {
+    let digest = sha::sha256(format!("{}:{}", username, password).as_bytes());
+    let generation = {
+        let cache = VERIFIED_CACHE.lock().unwrap();
+        if let Some(cached) = cache.users.get(&digest) {
+            return cached == username;
+        }
+        cache.generation
+    };
+
+    let Some(password_hash) = USER_STORE.read().unwrap().password_hash(username) else {
+        return false;
+    };
+
+    let password = password.to_string();
+    let verified =
+        tokio::task::spawn_blocking(move || verify_password_hash(&password, &password_hash))
+            .await
+            .unwrap_or(false);
+
+    if verified {
+        let mut cache = VERIFIED_CACHE.lock().unwrap();
+        if cache.generation == generation {
+            cache.insert(digest, username.to_string());
+        }
+    }
+
+    verified
+}
+
+pub static USER_STORE: LazyLock<Arc<RwLock<UserStore>>> = LazyLock::new(|| {
+    let store = UserStore::load(None).unwrap_or_else(|e| {
+        panic!("Failed to load proxy users file: {}", e);
+    });
+    Arc::new(RwLock::new(store))
+});
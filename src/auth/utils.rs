@@ -0,0 +1,55 @@
+use openssl::base64;
+
+use super::user_store::{USER_STORE, encode_password_hash, verify_credentials};
+
+/// Parse a `Proxy-Authorization: Basic <base64(user:password)>` header value.
+pub fn parse_basic_credentials(header_value: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = header_value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = base64::decode_block(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Authenticate a `Proxy-Authorization` header value, returning the user name if it is valid.
+pub async fn authenticate(header_value: &str) -> Option<String> {
+    let (username, password) = parse_basic_credentials(header_value)?;
+
+    verify_credentials(&username, &password)
+        .await
+        .then_some(username)
+}
+
+/// Create the user or replace its password. The password is hashed on the blocking pool before
+/// the store is locked, so authentication is not held up while PBKDF2 runs.
+pub async fn add_user(
+    username: &str,
+    password: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let password = password.to_string();
+    let password_hash =
+        tokio::task::spawn_blocking(move || encode_password_hash(&password)).await??;
+
+    let mut store = USER_STORE.write().unwrap();
+    store.set_user(username, password_hash)
+}
+
+pub fn remove_user(username: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut store = USER_STORE.write().unwrap();
+    store.remove_user(username)
+}
+
+pub fn get_users() -> Vec<String> {
+    let store = USER_STORE.read().unwrap();
+    store.usernames()
+}
+
+pub fn has_users() -> bool {
+    let store = USER_STORE.read().unwrap();
+    !store.is_empty()
+}
@@ -0,0 +1,182 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+};
+
+use clap::Parser;
+
+use crate::config::AppConfig;
+use crate::filters::{MAX_TEMPORARY_RULE_SECS, TemporaryRule, TemporaryTarget};
+use crate::utils::now_secs;
+
+#[derive(Parser, Debug)]
+#[command(
+    about = "Temporarily allow a domain, or pause ad blocking for a client, on the running proxy",
+    after_help = "Temporary rules revert on their own and are never written to the filter file."
+)]
+pub struct AllowCommand {
+    #[arg(
+        required_unless_present_any = ["client", "list", "revoke"],
+        conflicts_with = "client",
+        help = "Domain to allow, with its subdomains"
+    )]
+    pub domain: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "IP",
+        help = "Pause ad blocking for this client instead of allowing a domain"
+    )]
+    pub client: Option<IpAddr>,
+
+    #[arg(
+        short = 'm',
+        long,
+        default_value_t = 10,
+        help = "Minutes before the rule reverts"
+    )]
+    pub minutes: u64,
+
+    #[arg(long, help = "Note shown in the list of temporary rules")]
+    pub comment: Option<String>,
+
+    #[arg(
+        short = 'l',
+        long,
+        conflicts_with_all = ["domain", "client", "revoke"],
+        help = "List the temporary rules"
+    )]
+    pub list: bool,
+
+    #[arg(
+        long,
+        value_name = "ID",
+        conflicts_with_all = ["domain", "client"],
+        help = "Revert a temporary rule before it expires"
+    )]
+    pub revoke: Option<u64>,
+
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        help = "Path to the config file the admin port is read from (defaults to .config/proxy.toml)"
+    )]
+    pub config: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Address of the admin API, instead of the one of the config file"
+    )]
+    pub admin_url: Option<String>,
+}
+
+impl AllowCommand {
+    /// The admin API of the proxy started with the same config file.
+    fn admin_url(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(url) = &self.admin_url {
+            return Ok(url.trim_end_matches('/').to_string());
+        }
+
+        let settings = AppConfig::load(self.config.as_ref().map(PathBuf::from))?;
+        let host = settings.server.host.trim_matches(['[', ']']);
+        Ok(match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) if ip.is_unspecified() => {
+                format!(
+                    "http://{}",
+                    SocketAddr::from((Ipv4Addr::LOCALHOST, settings.admin.port))
+                )
+            }
+            Ok(IpAddr::V6(ip)) if ip.is_unspecified() => {
+                format!(
+                    "http://{}",
+                    SocketAddr::from((Ipv6Addr::LOCALHOST, settings.admin.port))
+                )
+            }
+            Ok(ip) => format!("http://{}", SocketAddr::from((ip, settings.admin.port))),
+            Err(_) => format!("http://{}:{}", host, settings.admin.port),
+        })
+    }
+
+    pub async fn execute(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let url = format!("{}/list/temporary", self.admin_url()?);
+        let client = reqwest::Client::new();
+
+        let request = if self.list {
+            client.get(&url)
+        } else if let Some(id) = self.revoke {
+            client.delete(format!("{}/{}", url, id))
+        } else {
+            let secs = self.minutes.saturating_mul(60);
+            if secs == 0 || secs > MAX_TEMPORARY_RULE_SECS {
+                return Err(format!(
+                    "The duration must be between 1 and {} minutes",
+                    MAX_TEMPORARY_RULE_SECS / 60
+                )
+                .into());
+            }
+
+            let target = match (&self.domain, self.client) {
+                (_, Some(client)) => TemporaryTarget::PauseClient { client },
+                (domain, None) => TemporaryTarget::AllowDomain {
+                    domain: domain.clone().unwrap_or_default(),
+                },
+            };
+            let mut body = serde_json::to_value(&target)?;
+            body["duration_secs"] = secs.into();
+            if let Some(comment) = &self.comment {
+                body["comment"] = comment.as_str().into();
+            }
+            client.post(&url).json(&body)
+        };
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Unable to reach the admin API at {}: {}", url, e))?;
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            return Err(format!("The admin API answered {}: {}", status, text).into());
+        }
+
+        if self.list {
+            let rules = serde_json::from_str::<Vec<TemporaryRule>>(&text)?;
+            if rules.is_empty() {
+                println!("No temporary rules");
+            }
+            for rule in rules {
+                println!("{}", describe_rule(&rule));
+            }
+        } else {
+            let rule = serde_json::from_str::<TemporaryRule>(&text)?;
+            match self.revoke {
+                Some(_) => println!("Reverted {}", describe_rule(&rule)),
+                None => println!("Added {}", describe_rule(&rule)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn describe_rule(rule: &TemporaryRule) -> String {
+    let target = match &rule.target {
+        TemporaryTarget::AllowDomain { domain } => format!("{} allowed", domain),
+        TemporaryTarget::PauseClient { client } => format!("ad blocking paused for {}", client),
+    };
+    let remaining = rule.expires_at.saturating_sub(now_secs());
+
+    let mut line = format!(
+        "#{}: {} for {}m{:02}s",
+        rule.id,
+        target,
+        remaining / 60,
+        remaining % 60
+    );
+    if let Some(comment) = &rule.comment {
+        line.push_str(&format!(" ({})", comment));
+    }
+    line
+}
@@ -0,0 +1,33 @@
+use clap::Parser;
+
+use crate::cli::ProxyCommand;
+use crate::config::AppConfig;
+
+#[derive(Parser, Debug)]
+#[command(
+    about = "Print the effective proxy configuration (config file merged with the given flags)"
+)]
+pub struct ConfigCommand {
+    #[command(flatten)]
+    pub proxy: ProxyCommand,
+}
+
+impl ConfigCommand {
+    pub async fn execute(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let settings = match AppConfig::from_cli(&self.proxy) {
+            Ok(settings) => settings,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        match settings.file.exists() {
+            true => println!("# Loaded from {}", settings.file.display()),
+            false => println!("# {} not found, showing defaults", settings.file.display()),
+        }
+        print!("{}", toml::to_string_pretty(&settings)?);
+
+        Ok(())
+    }
+}
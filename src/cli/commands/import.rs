@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+
+use crate::filters::{
+    Importer, ListFormat, MatchMode, check_filter_file, get_cosmetic_filter_file, get_filter_file,
+    get_network_filter_file, import_domains,
+};
+
+#[derive(Parser, Debug)]
+#[command(
+    about = "Import blocklists (hosts, domain lists, dnsmasq, unbound, AdBlock) into the filter"
+)]
+pub struct ImportCommand {
+    #[arg(
+        required = true,
+        help = "Blocklist files, or directories to import every file of"
+    )]
+    pub paths: Vec<PathBuf>,
+
+    #[arg(short = 'f', long = "format", default_value_t = ListFormat::Auto, value_enum, help = "Format of the lists, detected line by line by default")]
+    pub format: ListFormat,
+
+    #[arg(
+        long = "replace",
+        help = "Replace the exact entries of the lists instead of adding to them"
+    )]
+    pub replace: bool,
+
+    #[arg(
+        long = "dry-run",
+        help = "Parse the lists and print the report without changing the filter"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long = "max-invalid",
+        default_value_t = 20,
+        help = "Number of invalid lines to print"
+    )]
+    pub max_invalid: usize,
+}
+
+/// The files under a path, in a stable order.
+fn collect_files(
+    path: &Path,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for entry in entries {
+        collect_files(&entry, files)?;
+    }
+
+    Ok(())
+}
+
+impl ImportCommand {
+    pub async fn execute(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut files = Vec::new();
+        for path in &self.paths {
+            collect_files(path, &mut files)
+                .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        }
+
+        let mut importer = Importer::new(self.format);
+        for file in &files {
+            let content = std::fs::read(file)
+                .map_err(|e| format!("Unable to read {}: {}", file.display(), e))?;
+            importer.parse(
+                &file.display().to_string(),
+                &String::from_utf8_lossy(&content),
+            );
+        }
+        let report = importer.finish();
+
+        println!(
+            "Parsed {} lines from {} files: {} blocked and {} allowed domains, {} network and {} cosmetic rules, {} duplicates, {} invalid lines",
+            report.lines,
+            files.len(),
+            report.blacklist.len(),
+            report.whitelist.len(),
+            report.network_rules.len(),
+            report.cosmetic_rules.len(),
+            report.duplicates,
+            report.invalid.len()
+        );
+        for invalid in report.invalid.iter().take(self.max_invalid) {
+            println!(
+                "  {}:{}: {}: {}",
+                invalid.source, invalid.line, invalid.reason, invalid.content
+            );
+        }
+        if report.invalid.len() > self.max_invalid {
+            println!(
+                "  ... and {} more invalid lines",
+                report.invalid.len() - self.max_invalid
+            );
+        }
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        check_filter_file()?;
+        let result = import_domains(&report, self.replace)?;
+        println!(
+            "Added {} blacklist and {} whitelist entries to {}",
+            result.blacklist_added,
+            result.whitelist_added,
+            get_filter_file().display()
+        );
+        if !report.network_rules.is_empty() {
+            println!(
+                "Added {} network rules to {}",
+                result.network_rules_added,
+                get_network_filter_file().display()
+            );
+        }
+        if !report.cosmetic_rules.is_empty() {
+            println!(
+                "Added {} cosmetic rules to {}",
+                result.cosmetic_rules_added,
+                get_cosmetic_filter_file().display()
+            );
+        }
+
+        if report.subdomain_rules > 0 && result.blacklist_mode == MatchMode::Exact {
+            println!(
+                "Note: {} rules also cover subdomains, set match_mode = \"subdomains\" in the [blacklist] section to match them",
+                report.subdomain_rules
+            );
+        }
+
+        Ok(())
+    }
+}
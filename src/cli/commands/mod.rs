@@ -1,5 +1,11 @@
+pub mod allow;
+pub mod config;
+pub mod import;
 pub mod proxy;
 pub mod scan;
 
+pub use allow::AllowCommand;
+pub use config::ConfigCommand;
+pub use import::ImportCommand;
 pub use proxy::ProxyCommand;
 pub use scan::ScanCommand;
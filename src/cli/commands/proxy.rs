@@ -1,49 +1,92 @@
+use std::{path::PathBuf, sync::LazyLock};
+
 use clap::Parser;
+use tokio::time::Duration;
 
 use crate::{
+    acl::AccessControlList,
     admin::start_admin_server,
+    auth::{UserStore, has_users},
     cli::types::{LogFormat, LogLevel},
-    config::{ProxyConfig, set_global_config},
+    config::{
+        AppConfig, CONNECT_ATTEMPT_DELAY_MSECS, CONNECT_TIMEOUT_MSECS, ProxyConfig,
+        set_config_file, set_global_config,
+    },
+    dns::{DnsConfig, init_dns_resolver, start_dns_server},
+    filters::{
+        COSMETIC_FILTER, NETWORK_FILTER, PUBLIC_SUFFIX_LIST, check_filter_file,
+        run_filter_maintenance, run_subscription_refresh,
+    },
+    limits::set_global_limits,
     logging::{LogConfig, configure_global_tracing},
-    server::start_proxy_server,
+    reload::watch_config_files,
+    reverse::start_reverse_proxy_server,
+    server::{ListenerConfig, start_proxy_server},
+    shutdown::{drain_connections, wait_for_signal},
+    utils::listen::ListenFamily,
 };
 
-#[derive(Parser, Debug)]
-#[command(about = "Start the HTTP/HTTPS proxy server")]
+#[derive(Parser, Debug, Clone)]
+#[command(
+    about = "Start the HTTP/HTTPS proxy server",
+    after_help = "Every option can also be set in the config file, command line flags take precedence."
+)]
 pub struct ProxyCommand {
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        help = "Path to the config file (defaults to .config/proxy.toml)"
+    )]
+    pub config: Option<String>,
+
     #[arg(
         short = 'H',
         long,
-        default_value = "127.0.0.1",
-        help = "Host address to bind the proxy server"
+        help = "Host address to bind the proxy server [default: 127.0.0.1]"
     )]
-    pub host: String,
+    pub host: Option<String>,
 
     #[arg(
         short = 'p',
         long,
-        default_value = "8080",
-        help = "Port number to bind the proxy server"
+        help = "Port number to bind the proxy server [default: 8080]"
     )]
-    pub port: u16,
+    pub port: Option<u16>,
 
-    #[arg(long, default_value = "false", help = "Force IPv6 usage")]
-    pub ipv6: bool,
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Force IPv6 usage"
+    )]
+    pub ipv6: Option<bool>,
 
     #[arg(
-        short,
         long,
-        default_value = "info",
-        value_enum,
-        help = "Logging level"
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Listen on both IPv4 and IPv6 (0.0.0.0 or :: listens on every address of both families)"
     )]
-    pub log_level: LogLevel,
+    pub dual_stack: Option<bool>,
+
+    #[arg(
+        long = "listen",
+        value_name = "ADDR[,OPTIONS]",
+        help = "Proxy listener as host:port with optional ',transparent' and ',proxy-protocol,trusted-proxy=CIDR', repeatable (replaces --host/--port)"
+    )]
+    pub listeners: Vec<ListenerConfig>,
+
+    #[arg(short, long, value_enum, help = "Logging level [default: info]")]
+    pub log_level: Option<LogLevel>,
 
     #[arg(long, help = "Path to log file (if not specified, logs go to stdout)")]
     pub log_file: Option<String>,
 
-    #[arg(long, default_value = "pretty", value_enum, help = "Log output format")]
-    pub log_format: LogFormat,
+    #[arg(long, value_enum, help = "Log output format [default: pretty]")]
+    pub log_format: Option<LogFormat>,
 
     #[arg(
         long,
@@ -51,43 +94,140 @@ pub struct ProxyCommand {
     )]
     pub log_max_files: Option<usize>,
 
-    #[arg(long, default_value = "8000", help = "Administrative interface port")]
-    pub admin_port: u16,
+    #[arg(long, help = "Administrative interface port [default: 8000]")]
+    pub admin_port: Option<u16>,
 
     #[arg(
         long,
-        default_value = "false",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
         help = "Enable TLS interception (requires CA certificate installed)"
     )]
-    pub intercept_tls: bool,
+    pub intercept_tls: Option<bool>,
 
     #[arg(
         long,
-        default_value = "false",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
         help = "Enable ad blocking (blocks known ad/tracker domains)"
     )]
-    pub block_ads: bool,
+    pub block_ads: Option<bool>,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Enable response caching"
+    )]
+    pub cache_enabled: Option<bool>,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Require Proxy-Authorization credentials (users are managed through the admin API)"
+    )]
+    pub require_auth: Option<bool>,
+
+    #[arg(long, help = "Maximum upload bandwidth per client in bytes per second")]
+    pub upload_limit: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Maximum download bandwidth per client in bytes per second"
+    )]
+    pub download_limit: Option<u64>,
+
+    #[arg(long, help = "Maximum concurrent connections per client")]
+    pub max_connections_per_client: Option<usize>,
+
+    #[arg(long, help = "Maximum concurrent connections across all clients")]
+    pub max_connections: Option<usize>,
+
+    #[arg(long, help = format!("Timeout in milliseconds for each upstream connection attempt [default: {}]", CONNECT_TIMEOUT_MSECS))]
+    pub connect_timeout: Option<u64>,
+
+    #[arg(long, help = format!("Delay in milliseconds before trying the next upstream address while an attempt is pending [default: {}]", CONNECT_ATTEMPT_DELAY_MSECS))]
+    pub connect_attempt_delay: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Port for the reverse proxy listener (reverse proxy is disabled if not set)"
+    )]
+    pub reverse_port: Option<u16>,
 
-    #[arg(long, default_value = "false", help = "Enable response caching")]
-    pub cache_enabled: bool,
+    #[arg(
+        long,
+        help = "Path to the reverse proxy routes file (defaults to .config/reverse.toml)"
+    )]
+    pub reverse_config: Option<String>,
+
+    #[arg(
+        long,
+        help = "Port for the built-in DNS sinkhole server, usually 53 (DNS server is disabled if not set)"
+    )]
+    pub dns_port: Option<u16>,
+
+    #[arg(
+        long,
+        help = "Seconds to wait for open connections to finish after SIGTERM/SIGINT [default: 30]"
+    )]
+    pub shutdown_timeout: Option<u64>,
 }
 
 impl ProxyCommand {
     pub async fn execute(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        if (self.admin_port == 0 || self.port == 0) && self.admin_port == self.port {
-            eprintln!("Error: Admin port cannot be the same as the proxy server port.");
+        let settings = match AppConfig::from_cli(self) {
+            Ok(settings) => settings,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        // Fail early on invalid rules instead of falling back to allowing everyone
+        if let Err(e) = AccessControlList::load(None) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+
+        // Same for the users, a malformed file would otherwise panic on the first request
+        if let Err(e) = UserStore::load(None) {
+            eprintln!("Error: {}", e);
             std::process::exit(1);
         }
 
-        // Configure logging based on CLI options
+        if let Err(e) = check_filter_file() {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+
+        let dns_config = match DnsConfig::load(None) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = init_dns_resolver(dns_config.clone()) {
+            eprintln!("Error: Invalid DNS settings ({:?}): {}", dns_config.file, e);
+            std::process::exit(1);
+        }
+
+        // Configure logging based on the config file and CLI options
         let log_config = LogConfig {
-            level: self.log_level,
-            format: self.log_format,
-            file_path: self.log_file.clone(),
-            max_log_files: self.log_max_files,
+            level: settings.logging.level,
+            format: settings.logging.format,
+            file_path: settings.logging.file.clone(),
+            max_log_files: settings.logging.max_files,
         };
 
-        configure_global_tracing(log_config);
+        // Kept until the end of the function so buffered log lines are flushed on exit
+        let _log_guard = configure_global_tracing(log_config);
 
         // Display startup banner
         println!("");
@@ -99,13 +239,24 @@ impl ProxyCommand {
         println!("╚═══════════════════════════════════════════════╝");
         println!("");
         println!("Configuration:");
-        println!("  → Host: {}", self.host);
-        println!("  → Port: {}", self.port);
-        println!("  → IPv6: {}", self.ipv6);
-        println!("  → Log Level: {:?}", self.log_level);
-        println!("  → Log Format: {:?}", self.log_format);
+        if settings.file.exists() {
+            println!("  → Config File: {}", settings.file.display());
+        }
+        println!("  → Host: {}", settings.server.host);
+        println!("  → Port: {}", settings.server.port);
+        println!("  → IPv6: {}", settings.server.ipv6);
+        println!("  → Dual Stack: {}", settings.server.dual_stack);
+        if !settings.server.listeners.is_empty() {
+            println!("  → Listeners:");
+            for listener in &settings.server.listeners {
+                println!("      {}", listener);
+            }
+        }
+        println!("  → Log Level: {:?}", settings.logging.level);
+        println!("  → Log Format: {:?}", settings.logging.format);
+        println!("  → DNS Upstream: {:?}", dns_config.upstream);
 
-        if let Some(ref file) = self.log_file {
+        if let Some(ref file) = settings.logging.file {
             println!("  → Log File: {}", file);
         }
 
@@ -113,7 +264,7 @@ impl ProxyCommand {
         println!("Features:");
         println!(
             "  → TLS Interception: {}",
-            if self.intercept_tls {
+            if settings.tls.intercept {
                 "✓ Enabled"
             } else {
                 "✗ Disabled"
@@ -121,7 +272,7 @@ impl ProxyCommand {
         );
         println!(
             "  → Ad Blocking: {}",
-            if self.block_ads {
+            if settings.filters.block_ads {
                 "✓ Enabled"
             } else {
                 "✗ Disabled"
@@ -129,36 +280,146 @@ impl ProxyCommand {
         );
         println!(
             "  → Caching: {}",
-            if self.cache_enabled {
+            if settings.server.cache_enabled {
+                "✓ Enabled"
+            } else {
+                "✗ Disabled"
+            }
+        );
+        println!(
+            "  → Proxy Authentication: {}",
+            if settings.server.require_auth {
                 "✓ Enabled"
             } else {
                 "✗ Disabled"
             }
         );
+        println!(
+            "  → Reverse Proxy: {}",
+            match settings.reverse.port {
+                Some(port) => format!("✓ Enabled (port {})", port),
+                None => "✗ Disabled".to_string(),
+            }
+        );
+        println!(
+            "  → DNS Server: {}",
+            match settings.dns.port {
+                Some(port) => format!("✓ Enabled (port {})", port),
+                None => "✗ Disabled".to_string(),
+            }
+        );
         println!("");
 
         // Set global configuration
-        let config = ProxyConfig::from_cli(self);
+        let config = ProxyConfig::from_app_config(&settings);
         set_global_config(config);
+        // Changes made through the admin API are written back to the same file
+        set_config_file(settings.file.clone());
+        set_global_limits(settings.limits);
+
+        let watch_cli = self.clone();
+        let watch_file = settings.file.clone();
+        tokio::spawn(async move {
+            if let Err(e) = watch_config_files(watch_cli, watch_file).await {
+                tracing::error!("Config hot reload disabled: {}", e);
+            }
+        });
+        tokio::spawn(run_subscription_refresh());
+        let maintenance_handle = tokio::spawn(run_filter_maintenance());
+        // Parse the network and element hiding rules and the Public Suffix List now rather than
+        // on the first request
+        LazyLock::force(&NETWORK_FILTER);
+        LazyLock::force(&COSMETIC_FILTER);
+        LazyLock::force(&PUBLIC_SUFFIX_LIST);
+
+        if settings.server.require_auth && !has_users() {
+            tracing::warn!(
+                "Proxy authentication is enabled but no users are configured, every request will be rejected"
+            );
+        }
 
         // Start servers
-        let host = self.host.clone();
-        let is_v4 = if self.ipv6 { Some(false) } else { None };
+        let host = settings.server.host.clone();
+        let family = ListenFamily::from_flags(settings.server.ipv6, settings.server.dual_stack);
 
-        let proxy_handle = tokio::spawn(start_proxy_server(host.clone(), self.port, is_v4));
-        let admin_handle = tokio::spawn(start_admin_server(host, self.admin_port, is_v4));
+        let proxy_handle = tokio::spawn(start_proxy_server(settings.proxy_listeners(), family));
+        let admin_handle = tokio::spawn(start_admin_server(
+            host.clone(),
+            settings.admin.port,
+            family,
+        ));
+        let reverse_handle = settings.reverse.port.map(|port| {
+            tokio::spawn(start_reverse_proxy_server(
+                host.clone(),
+                port,
+                family,
+                settings.reverse.routes.as_ref().map(PathBuf::from),
+            ))
+        });
+        let dns_handle = settings
+            .dns
+            .port
+            .map(|port| tokio::spawn(start_dns_server(host, port, family)));
 
-        tokio::select! {
-            result = proxy_handle => {
-                if let Err(e) = result {
-                    tracing::error!("Proxy server panicked: {:?}", e);
+        let servers = async {
+            tokio::select! {
+                result = proxy_handle => {
+                    if let Err(e) = result {
+                        tracing::error!("Proxy server panicked: {:?}", e);
+                    }
                 }
-            }
-            result = admin_handle => {
-                if let Err(e) = result {
-                    tracing::error!("Admin server panicked: {:?}", e);
+                result = admin_handle => {
+                    if let Err(e) = result {
+                        tracing::error!("Admin server panicked: {:?}", e);
+                    }
+                }
+                result = async {
+                    match reverse_handle {
+                        Some(handle) => handle.await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    match result {
+                        Ok(Err(e)) => tracing::error!("Reverse proxy server failed: {}", e),
+                        Err(e) => tracing::error!("Reverse proxy server panicked: {:?}", e),
+                        Ok(Ok(_)) => {}
+                    }
+                }
+                result = async {
+                    match dns_handle {
+                        Some(handle) => handle.await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    match result {
+                        Ok(Err(e)) => tracing::error!("DNS server failed: {}", e),
+                        Err(e) => tracing::error!("DNS server panicked: {:?}", e),
+                        Ok(Ok(_)) => {}
+                    }
                 }
             }
+        };
+
+        tokio::select! {
+            _ = servers => {}
+            signal = wait_for_signal() => {
+                tracing::info!("Received {}, shutting down", signal);
+            }
+        }
+
+        let remaining =
+            drain_connections(Duration::from_secs(settings.timeouts.shutdown_secs)).await;
+        match remaining {
+            0 => tracing::info!("All connections closed, exiting"),
+            n => tracing::warn!(
+                "Shutdown deadline reached, closing {} connections still open",
+                n
+            ),
+        }
+
+        // Writes the hit statistics once it sees the shutdown
+        if let Err(e) = maintenance_handle.await {
+            tracing::error!("Filter maintenance task panicked: {:?}", e);
         }
 
         Ok(())
@@ -52,6 +52,8 @@ impl ScanCommand {
             self.verbose,
             self.num_threads,
             self.retries,
-        )
+        )?;
+
+        Ok(())
     }
 }
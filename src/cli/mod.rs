@@ -1,7 +1,7 @@
 mod commands;
 pub mod types;
 
-pub use commands::{ProxyCommand, ScanCommand};
+pub use commands::{AllowCommand, ConfigCommand, ImportCommand, ProxyCommand, ScanCommand};
 
 use clap::{Parser, Subcommand};
 
@@ -22,4 +22,7 @@ pub struct Cli {
 pub enum Commands {
     Proxy(ProxyCommand),
     Scan(ScanCommand),
+    Config(ConfigCommand),
+    Import(ImportCommand),
+    Allow(AllowCommand),
 }
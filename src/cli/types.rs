@@ -1,8 +1,11 @@
 use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 
+use crate::errors::ScanError;
 use crate::schemas::ArpResponse;
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum LogLevel {
     Trace,
     Debug,
@@ -23,7 +26,8 @@ impl LogLevel {
     }
 }
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum LogFormat {
     // Human-readable format with colors
     Pretty,
@@ -53,7 +57,7 @@ impl OutputFormat {
         }
     }
 
-    pub fn show_scanning_results(&self, results: &Vec<ArpResponse>) {
+    pub fn show_scanning_results(&self, results: &Vec<ArpResponse>) -> Result<(), ScanError> {
         match self {
             OutputFormat::Txt => {
                 // Implement table output
@@ -68,7 +72,8 @@ impl OutputFormat {
             }
             OutputFormat::Json => {
                 // Implement JSON output
-                let json = serde_json::to_string_pretty(&results).unwrap();
+                let json = serde_json::to_string_pretty(&results)
+                    .map_err(|e| ScanError::Output(e.to_string()))?;
                 println!("{}", json);
             }
             OutputFormat::Csv => {
@@ -85,9 +90,12 @@ impl OutputFormat {
             }
             OutputFormat::Yaml => {
                 // Implement YAML output
-                let yaml = serde_yaml::to_string(&results).unwrap();
+                let yaml = serde_yaml::to_string(&results)
+                    .map_err(|e| ScanError::Output(e.to_string()))?;
                 println!("{}", yaml);
             }
         }
+
+        Ok(())
     }
 }
@@ -1,10 +1,11 @@
-use std::convert::Infallible;
+use std::{convert::Infallible, sync::LazyLock};
 
 use bytes::Bytes;
 use http::Response;
 use http_body_util::Full;
 use uuid::Uuid;
 
+use crate::errors::{ConnectError, ErrorCode, ParseError, error_response};
 use crate::schemas::HttpRequest;
 
 #[tracing::instrument(level = "info", name = "ForwardHTTPRequest", skip(req_params))]
@@ -22,22 +23,24 @@ pub async fn forward_http_request(
         _ => client_builder.http1_only(),
     };
 
-    let client = client_builder
-        .build()
-        .expect("Error creating the http client");
+    let client = match client_builder.build() {
+        Ok(client) => client,
+        Err(e) => return Ok(upstream_error(ConnectError::from_reqwest(e))),
+    };
 
     // This should never failed if the server is acting as a proxy
-    if req_params.uri.authority().is_none() {
-        tracing::error!("No authority found in the URI");
-        return Ok(Response::new(Full::new(Bytes::from(
-            "Error: No authority found in the URI",
-        ))));
-    }
+    let Some(authority) = req_params.uri.authority() else {
+        tracing::error!(
+            error_code = "PARSE_MISSING_HOST",
+            "No authority found in the URI"
+        );
+        return Ok(error_response(&ParseError::MissingHost));
+    };
 
     let url = format!(
         "{}://{}{}",
         SCHEME,
-        req_params.uri.authority().unwrap(),
+        authority,
         req_params
             .uri
             .path_and_query()
@@ -45,6 +48,45 @@ pub async fn forward_http_request(
             .unwrap_or("/")
     );
 
+    send_http_request(req_id, url, req_params, client).await
+}
+
+/// Shared by the requests to the reverse proxy backends so their connections are reused. Redirects
+/// are passed back to the client, which sees the public host, rather than followed here.
+static BACKEND_CLIENT: LazyLock<Result<reqwest::Client, String>> = LazyLock::new(|| {
+    reqwest::ClientBuilder::new()
+        .http1_only()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|e| e.to_string())
+});
+
+/// Send the request to an already resolved URL, used by the reverse proxy to reach its backends.
+#[tracing::instrument(level = "info", name = "ForwardHTTPRequestToURL", skip(req_params))]
+pub async fn forward_http_request_to_url(
+    req_id: Uuid,
+    url: String,
+    req_params: HttpRequest,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let client = match BACKEND_CLIENT.as_ref() {
+        Ok(client) => client.clone(),
+        Err(e) => return Ok(upstream_error(ConnectError::Upstream(e.clone()))),
+    };
+
+    send_http_request(req_id, url, req_params, client).await
+}
+
+fn upstream_error(error: ConnectError) -> Response<Full<Bytes>> {
+    tracing::error!(error_code = error.code(), "{}", error);
+    error_response(&error)
+}
+
+async fn send_http_request(
+    req_id: Uuid,
+    url: String,
+    req_params: HttpRequest,
+    client: reqwest::Client,
+) -> Result<Response<Full<Bytes>>, Infallible> {
     let reqwest_method = match req_params.method.as_str() {
         "GET" => reqwest::Method::GET,
         "POST" => reqwest::Method::POST,
@@ -92,20 +134,13 @@ pub async fn forward_http_request(
                         builder = builder.header(key, value);
                     }
 
-                    return Ok(builder.body(Full::new(bytes)).unwrap());
-                }
-                Err(e) => {
-                    let error_response = format!("Error reading response body: {e}");
-                    tracing::error!(error_response);
-                    return Ok(Response::new(Full::new(Bytes::from(error_response))));
+                    Ok(builder
+                        .body(Full::new(bytes))
+                        .unwrap_or_else(|e| upstream_error(ConnectError::Upstream(e.to_string()))))
                 }
+                Err(e) => Ok(upstream_error(ConnectError::from_reqwest(e))),
             }
         }
-        Err(err) => {
-            tracing::error!("Error making request to destination server: {}", err);
-            Ok(Response::new(Full::new(Bytes::from(format!(
-                "Error: {err}"
-            )))))
-        }
+        Err(err) => Ok(upstream_error(ConnectError::from_reqwest(err))),
     }
 }
@@ -1,50 +1,29 @@
-use std::net::SocketAddr;
-
 use tokio::{
-    io::AsyncWriteExt,
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
-    time::{self as TokioTime, Duration},
 };
-use tokio_native_tls::TlsStream;
 use uuid::Uuid;
 
-use crate::ads::{analyze_and_modify_request, analyze_and_modify_response};
+use crate::acl::{AclScope, is_client_allowed};
+use crate::ads::{analyze_and_modify_request, analyze_and_modify_response, blocking_rule};
 use crate::config::get_global_config;
-use crate::filters::{is_domain_blacklisted, is_domain_whitelisted};
-use crate::schemas::{HttpsRequest, HttpsResponse};
+use crate::errors::{FilterError, ProxyError, https_error_response};
+use crate::filters::{is_blocking_paused, is_domain_blacklisted, is_domain_whitelisted};
+use crate::limits::ThrottledStream;
+use crate::schemas::{ClientContext, HttpsRequest};
+use crate::shutdown::shutdown_token;
 use crate::utils::{
-    DNS_RESOLVER,
+    authority::Authority,
+    connect::connect_to_host,
     decoders::{decode_brotli, decode_deflate, decode_gzip, decode_zstd},
-    http::{read_http_stream, read_stream_response, write_request, write_response},
+    http::{normalize_host, read_http_stream, read_stream_response, write_request, write_response},
 };
 
-fn normalize_host(value: &str) -> String {
-    let mut host = value.trim();
-
-    // Strip IPv6 brackets if present.
-    if host.starts_with('[') {
-        if let Some(end) = host.find(']') {
-            host = &host[1..end];
-        }
-        return host.to_string();
-    }
-
-    // Strip optional port for normal hostnames.
-    if let Some((h, _)) = host.split_once(':') {
-        return h.to_string();
-    }
-
-    host.to_string()
-}
-
 fn host_from_https_request(req: &HttpsRequest) -> Option<String> {
-    // CONNECT-style authority form, e.g. host:443
+    // CONNECT-style authority form, e.g. host:443 or [2001:db8::1]:443
     if req.method.eq_ignore_ascii_case("CONNECT") {
-        if let Some((host, _)) = req.uri.split_once(':') {
-            return Some(normalize_host(host));
-        }
-        if !req.uri.trim().is_empty() {
-            return Some(normalize_host(&req.uri));
+        if let Ok(authority) = Authority::parse(&req.uri, 443) {
+            return Some(authority.host);
         }
     }
 
@@ -62,13 +41,11 @@ fn host_from_https_request(req: &HttpsRequest) -> Option<String> {
     }
 
     // Absolute-form fallback.
-    if let Some((host, _)) = req.uri.split_once(':') {
-        if !host.trim().is_empty() {
-            return Some(normalize_host(host));
-        }
-    }
-
-    None
+    req.uri
+        .parse::<http::Uri>()
+        .ok()
+        .and_then(|uri| uri.host().map(normalize_host))
+        .filter(|host| !host.is_empty())
 }
 
 #[tracing::instrument(
@@ -80,25 +57,30 @@ pub async fn forward_https_request_tunnel(
     req_id: Uuid,
     client_stream: &mut TcpStream,
     req_params: HttpsRequest,
+    client: &ClientContext,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // 2. Connect to destination server
-    let (host, port_str) = req_params.uri.split_once(':').ok_or("Invalid authority")?;
-    let port: u16 = port_str.parse()?;
-
-    tracing::info!("Resolving DNS for {}", host);
-
-    // Resolve the host using the async DNS resolver
-    let lookup = DNS_RESOLVER.lookup_ip(host).await?;
-    let ip = lookup.iter().next().ok_or("No IP address found")?;
+    let connected = match Authority::parse(&req_params.uri, 443) {
+        Ok(Authority { host, port }) => {
+            tracing::info!("Resolving DNS for {}", host);
 
-    tracing::info!("Resolved {} to {}", host, ip);
-
-    // Connect directly to the resolved IP address
-    let addr = SocketAddr::new(ip, port);
-    let mut dest_stream =
-        TokioTime::timeout(Duration::from_secs(5), TcpStream::connect(addr)).await??;
+            // Race every resolved address, so a broken address family does not fail the tunnel
+            connect_to_host(&host, port).await
+        }
+        Err(e) => Err(ProxyError::from(e)),
+    };
+    let mut dest_stream = match connected {
+        Ok(stream) => stream,
+        Err(e) => {
+            let response = https_error_response(&e, &req_params.version);
+            if let Err(write_error) = write_response(client_stream, &response).await {
+                tracing::debug!("Unable to send the error page: {}", write_error);
+            }
+            return Err(e.into());
+        }
+    };
 
-    tracing::info!("Connected to {}", addr);
+    tracing::info!("Connected to {}", dest_stream.peer_addr()?);
 
     // 3. Send back 200 Connection Established to the client
     let client_response = format!("{} 200 Connection Established\r\n\r\n", req_params.version);
@@ -107,7 +89,8 @@ pub async fn forward_https_request_tunnel(
     // 4. Tunnel data between client and destination server
     tracing::info!("Establishing HTTPS tunnel for request ID {}", req_id);
 
-    match tokio::io::copy_bidirectional(client_stream, &mut dest_stream).await {
+    let mut client_stream = ThrottledStream::for_client(client_stream, client.peer_addr.ip());
+    match tokio::io::copy_bidirectional(&mut client_stream, &mut dest_stream).await {
         Ok((client_to_server, server_to_client)) => {
             tracing::info!(
                 bytes_up = client_to_server,
@@ -124,18 +107,37 @@ pub async fn forward_https_request_tunnel(
     Ok(())
 }
 
-pub async fn forward_https_request_no_tunnel(
+pub async fn forward_https_request_no_tunnel<C, D>(
     req_id: Uuid,
-    client_tls_stream: &mut TlsStream<&mut TcpStream>,
-    dest_tls_stream: &mut TlsStream<&mut TcpStream>,
+    client_tls_stream: &mut C,
+    dest_tls_stream: &mut D,
     version: &str,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    client: &ClientContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    D: AsyncRead + AsyncWrite + Unpin,
+{
+    let block_ads_allowed = is_client_allowed(client.peer_addr.ip(), AclScope::BlockAds);
+    // A temporary pause of the client starts and ends between two requests of the connection
+    let mut block_ads_active = block_ads_allowed;
+
     let mut last_request_host = String::new();
     let mut last_request_uri = String::new();
     let mut last_request_whitelisted = false;
 
+    // Requests forwarded upstream whose response was not written back yet, on shutdown the
+    // connection is only closed once it is idle
+    let mut pending_responses: usize = 0;
+    let shutdown = shutdown_token();
+
     loop {
         tokio::select! {
+                _ = shutdown.cancelled(), if pending_responses == 0 => {
+                    tracing::info!("Closing idle intercepted connection for request ID {} due to shutdown", req_id);
+                    break;
+                }
+
                 http_request = read_http_stream(client_tls_stream) => {
                     if let Err(e) = http_request {
                         tracing::error!("Error reading HTTP request from client TLS stream for request ID {}: {}", req_id, e);
@@ -146,7 +148,8 @@ pub async fn forward_https_request_no_tunnel(
                     tracing::debug!("Intercepted HTTPS request ID {}: {:?}", req_id, http_request);
 
                     let config = get_global_config();
-                    let modified_request = match config.block_ads {
+                    block_ads_active = block_ads_allowed && !is_blocking_paused(client.peer_addr.ip());
+                    let modified_request = match config.block_ads && block_ads_active {
                         true => {
                             let request: HttpsRequest = analyze_and_modify_request(&http_request.into()).into();
                             let host = host_from_https_request(&request).unwrap_or_default();
@@ -157,13 +160,21 @@ pub async fn forward_https_request_no_tunnel(
                             if !last_request_whitelisted && is_domain_blacklisted(&host) {
                                 tracing::info!("Blocking ad request for request ID {}", req_id);
 
-                                let response = HttpsResponse {
-                                    version: version.to_string(),
-                                    status_code: 204,  // No Content
-                                    status_text: "No Content".to_string(),
-                                    headers: Default::default(),
-                                    body: Some("Blocked by Network Administrator".as_bytes().to_vec()),
-                                };
+                                let response = https_error_response(&FilterError::Blocked { host }, version);
+
+                                write_response(client_tls_stream, &response).await?;
+                                continue;
+                            }
+
+                            if !last_request_whitelisted
+                                && let Some(rule) = blocking_rule(&request.uri, &request.headers, "https", &host)
+                            {
+                                tracing::info!("Blocking request ID {} to {}{} by network rule {}", req_id, host, request.uri, rule);
+
+                                let response = https_error_response(
+                                    &FilterError::BlockedRequest { url: format!("https://{}{}", host, request.uri) },
+                                    version,
+                                );
 
                                 write_response(client_tls_stream, &response).await?;
                                 continue;
@@ -175,6 +186,7 @@ pub async fn forward_https_request_no_tunnel(
                 };
 
                 write_request(dest_tls_stream, &modified_request).await?;
+                pending_responses += 1;
             }
 
             http_response = read_stream_response(dest_tls_stream) => {
@@ -205,7 +217,7 @@ pub async fn forward_https_request_no_tunnel(
                     last_request_host.ends_with("cloudflare.com") ||
                     last_request_host.ends_with("challenges.cloudflare.com");
                 let should_rewrite_html =
-                    config.block_ads && is_html_response && !last_request_whitelisted && !is_cloudflare_challenge_flow;
+                    config.block_ads && block_ads_active && is_html_response && !last_request_whitelisted && !is_cloudflare_challenge_flow;
 
                 if should_rewrite_html && let Some(encoding) = http_response.headers.get("content-encoding") && let Some(body) = http_response.body.as_ref() {
                     let encodings: Vec<&str> = encoding.split(',')
@@ -286,7 +298,7 @@ pub async fn forward_https_request_no_tunnel(
 
                 let mut modified_response = http_response.clone();
                 let content_type = modified_response.headers.get("content-type");
-                if let Some(ct) = content_type && ct.contains("text/html") && config.block_ads {
+                if let Some(ct) = content_type && ct.contains("text/html") && config.block_ads && block_ads_active {
                     if last_request_whitelisted {
                         tracing::debug!(
                             "Skipping ad-block response rewrite for whitelisted host '{}' (request ID {})",
@@ -329,12 +341,13 @@ pub async fn forward_https_request_no_tunnel(
                             modified_response.body = Some(rewritten_body);
                             // Body is decoded/rewritten now, so remove content-encoding to keep headers consistent.
                             modified_response.headers.remove("content-encoding");
-                            modified_response = analyze_and_modify_response(&modified_response.into()).into();
+                            modified_response = analyze_and_modify_response(&modified_response.into(), &last_request_host).into();
                         }
                     }
                 }
 
                 write_response(client_tls_stream, &modified_response).await?;
+                pending_responses = pending_responses.saturating_sub(1);
             }
         }
     }
@@ -1,5 +1,5 @@
 mod http;
 mod https;
 
-pub use http::forward_http_request;
+pub use http::{forward_http_request, forward_http_request_to_url};
 pub use https::{forward_https_request_no_tunnel, forward_https_request_tunnel};
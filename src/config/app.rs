@@ -0,0 +1,320 @@
+use std::{collections::HashMap, path::PathBuf, sync::LazyLock};
+
+use serde::{Deserialize, Serialize};
+
+use super::constants::{CONFIG_PATH, CONNECT_ATTEMPT_DELAY_MSECS, CONNECT_TIMEOUT_MSECS};
+use super::settings::ProxyConfig;
+use crate::cli::{
+    ProxyCommand,
+    types::{LogFormat, LogLevel},
+};
+use crate::limits::LimitsConfig;
+use crate::server::ListenerConfig;
+
+pub(crate) static APP_CONFIG_PATH: LazyLock<PathBuf> =
+    LazyLock::new(|| CONFIG_PATH.join("proxy.toml"));
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    /// Address of the default proxy listener, also used by the admin, reverse proxy and DNS
+    /// servers.
+    pub host: String,
+    pub port: u16,
+    pub ipv6: bool,
+    /// Listen on both IPv4 and IPv6, `0.0.0.0` or `::` listens on every address of both families.
+    pub dual_stack: bool,
+    /// Proxy listeners, `host:port[,transparent][,proxy-protocol]`. Replaces `host`/`port`.
+    pub listeners: Vec<ListenerConfig>,
+    /// Require Proxy-Authorization credentials (users are managed through the admin API).
+    pub require_auth: bool,
+    pub cache_enabled: bool,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            ipv6: false,
+            dual_stack: false,
+            listeners: Vec::new(),
+            require_auth: false,
+            cache_enabled: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminSettings {
+    pub port: u16,
+}
+
+impl Default for AdminSettings {
+    fn default() -> Self {
+        Self { port: 8000 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSettings {
+    pub level: LogLevel,
+    pub format: LogFormat,
+    /// Log file, logs go to stdout when not set.
+    pub file: Option<String>,
+    /// Maximum number of rotated log files to retain.
+    pub max_files: Option<usize>,
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        Self {
+            level: LogLevel::Info,
+            format: LogFormat::Pretty,
+            file: None,
+            max_files: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSettings {
+    /// Intercept TLS connections with certificates issued by our CA.
+    pub intercept: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterSettings {
+    /// Block the domains of the blacklist in `.config/filter.toml`.
+    pub block_ads: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutSettings {
+    /// Timeout of each upstream connection attempt, in milliseconds.
+    pub connect_ms: u64,
+    /// Delay before racing the next resolved address while an attempt is still pending.
+    pub connect_attempt_delay_ms: u64,
+    /// Seconds to wait for open connections to finish after SIGTERM/SIGINT.
+    pub shutdown_secs: u64,
+}
+
+impl Default for TimeoutSettings {
+    fn default() -> Self {
+        Self {
+            connect_ms: CONNECT_TIMEOUT_MSECS,
+            connect_attempt_delay_ms: CONNECT_ATTEMPT_DELAY_MSECS,
+            shutdown_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReverseSettings {
+    /// Reverse proxy listener port, the reverse proxy is disabled when not set.
+    pub port: Option<u16>,
+    /// Routes file, `.config/reverse.toml` when not set.
+    pub routes: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DnsServerSettings {
+    /// Built-in DNS sinkhole server port, the server is disabled when not set.
+    pub port: Option<u16>,
+}
+
+/// Settings of the proxy command, read from `.config/proxy.toml` and overridden by the flags
+/// given on the command line. A missing file keeps the defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppConfig {
+    #[serde(skip)]
+    pub file: PathBuf,
+
+    #[serde(default)]
+    pub server: ServerSettings,
+
+    #[serde(default)]
+    pub admin: AdminSettings,
+
+    #[serde(default)]
+    pub logging: LoggingSettings,
+
+    #[serde(default)]
+    pub tls: TlsSettings,
+
+    #[serde(default)]
+    pub filters: FilterSettings,
+
+    #[serde(default)]
+    pub timeouts: TimeoutSettings,
+
+    #[serde(default)]
+    pub limits: LimitsConfig,
+
+    #[serde(default)]
+    pub reverse: ReverseSettings,
+
+    #[serde(default)]
+    pub dns: DnsServerSettings,
+}
+
+impl AppConfig {
+    /// Load the settings from the TOML file without validating them, so command line flags can
+    /// still fix a value before [`AppConfig::validate`].
+    pub fn load(file: Option<PathBuf>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let file = file.unwrap_or(APP_CONFIG_PATH.clone());
+        let content = match file.exists() {
+            true => std::fs::read_to_string(&file)?,
+            false => String::new(),
+        };
+
+        let mut config: AppConfig = toml::from_str(&content)
+            .map_err(|e| format!("Invalid config file {:?}: {}", file, e))?;
+        config.file = file;
+
+        Ok(config)
+    }
+
+    /// Load the file given with `--config` (or the default one), apply the command line flags on
+    /// top of it and validate the result.
+    pub fn from_cli(cli: &ProxyCommand) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut config = AppConfig::load(cli.config.as_ref().map(PathBuf::from))?;
+        config.apply_cli(cli);
+        config
+            .validate()
+            .map_err(|e| format!("Invalid configuration ({:?}): {}", config.file, e))?;
+
+        Ok(config)
+    }
+
+    /// Override the file values with the flags given on the command line.
+    pub fn apply_cli(&mut self, cli: &ProxyCommand) {
+        fn set<T: Clone>(target: &mut T, value: &Option<T>) {
+            if let Some(value) = value {
+                *target = value.clone();
+            }
+        }
+
+        set(&mut self.server.host, &cli.host);
+        set(&mut self.server.port, &cli.port);
+        set(&mut self.server.ipv6, &cli.ipv6);
+        set(&mut self.server.dual_stack, &cli.dual_stack);
+        if !cli.listeners.is_empty() {
+            self.server.listeners = cli.listeners.clone();
+        }
+        set(&mut self.server.require_auth, &cli.require_auth);
+        set(&mut self.server.cache_enabled, &cli.cache_enabled);
+
+        set(&mut self.admin.port, &cli.admin_port);
+
+        set(&mut self.logging.level, &cli.log_level);
+        set(&mut self.logging.format, &cli.log_format);
+        if cli.log_file.is_some() {
+            self.logging.file = cli.log_file.clone();
+        }
+        if cli.log_max_files.is_some() {
+            self.logging.max_files = cli.log_max_files;
+        }
+
+        set(&mut self.tls.intercept, &cli.intercept_tls);
+        set(&mut self.filters.block_ads, &cli.block_ads);
+
+        set(&mut self.timeouts.connect_ms, &cli.connect_timeout);
+        set(
+            &mut self.timeouts.connect_attempt_delay_ms,
+            &cli.connect_attempt_delay,
+        );
+        set(&mut self.timeouts.shutdown_secs, &cli.shutdown_timeout);
+
+        if cli.upload_limit.is_some() {
+            self.limits.upload_bytes_per_sec = cli.upload_limit;
+        }
+        if cli.download_limit.is_some() {
+            self.limits.download_bytes_per_sec = cli.download_limit;
+        }
+        if cli.max_connections_per_client.is_some() {
+            self.limits.max_connections_per_client = cli.max_connections_per_client;
+        }
+        if cli.max_connections.is_some() {
+            self.limits.max_connections = cli.max_connections;
+        }
+
+        if cli.reverse_port.is_some() {
+            self.reverse.port = cli.reverse_port;
+        }
+        if cli.reverse_config.is_some() {
+            self.reverse.routes = cli.reverse_config.clone();
+        }
+
+        if cli.dns_port.is_some() {
+            self.dns.port = cli.dns_port;
+        }
+    }
+
+    /// Proxy listeners, the `host`/`port` pair when no listener is configured.
+    pub fn proxy_listeners(&self) -> Vec<ListenerConfig> {
+        match self.server.listeners.is_empty() {
+            true => vec![ListenerConfig::new(
+                self.server.host.clone(),
+                self.server.port,
+            )],
+            false => self.server.listeners.clone(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.server.host.trim().is_empty() {
+            return Err("[server] host cannot be empty".to_string());
+        }
+
+        if self.server.ipv6 && self.server.dual_stack {
+            return Err("[server] ipv6 and dual_stack cannot be enabled together".to_string());
+        }
+
+        // Every TCP server binds a port of its own, port 0 lets the system pick a free one
+        let mut ports = HashMap::new();
+        let mut bindings = self
+            .proxy_listeners()
+            .into_iter()
+            .map(|listener| (listener.host, listener.port, "proxy listener"))
+            .collect::<Vec<_>>();
+        bindings.push((self.server.host.clone(), self.admin.port, "admin server"));
+        if let Some(port) = self.reverse.port {
+            bindings.push((self.server.host.clone(), port, "reverse proxy"));
+        }
+        if let Some(port) = self.dns.port {
+            bindings.push((self.server.host.clone(), port, "DNS server"));
+        }
+
+        for (host, port, name) in bindings.into_iter().filter(|(_, port, _)| *port != 0) {
+            if let Some(other) = ports.insert((host.clone(), port), name) {
+                return Err(format!(
+                    "The {} and the {} cannot both listen on {}:{}",
+                    other, name, host, port
+                ));
+            }
+        }
+
+        if self.logging.max_files == Some(0) {
+            return Err("[logging] max_files must be greater than zero".to_string());
+        }
+
+        if self.timeouts.connect_ms == 0 {
+            return Err("[timeouts] connect_ms must be greater than zero".to_string());
+        }
+
+        self.limits
+            .validate()
+            .map_err(|e| format!("[limits] {}", e))?;
+
+        ProxyConfig::from_app_config(self).validate()
+    }
+}
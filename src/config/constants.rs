@@ -7,8 +7,16 @@ pub const ARP_RETRIES: usize = 4;
 pub const ARP_REQUEST_INTERVAL_MSECS: u64 = 50;
 
 // Configuration paths
-pub const CONFIG_PATH: LazyLock<&'static Path> = LazyLock::new(|| Path::new("./.config"));
+pub static CONFIG_PATH: LazyLock<&'static Path> = LazyLock::new(|| Path::new("./.config"));
 
 // Certificate configuration
 pub const CERT_DAYS_VALID: usize = 365;
 pub const CERT_PATH: LazyLock<&'static Path> = LazyLock::new(|| Path::new("./certs"));
+
+// Proxy authentication configuration
+pub const AUTH_REALM: &str = "Network Administrator Proxy";
+pub const AUTH_PBKDF2_ITERATIONS: usize = 100_000;
+
+// Upstream connection configuration (RFC 8305 recommends 250ms between connection attempts)
+pub const CONNECT_TIMEOUT_MSECS: u64 = 5000;
+pub const CONNECT_ATTEMPT_DELAY_MSECS: u64 = 250;
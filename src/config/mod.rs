@@ -1,8 +1,15 @@
+pub mod app;
 pub mod constants;
 pub mod settings;
+pub mod store;
 
+pub use app::AppConfig;
 pub use constants::{
-    ARP_REQUEST_INTERVAL_MSECS, ARP_RETRIES, ARP_TIMEOUT_SECS, CERT_DAYS_VALID, CERT_PATH,
-    CONFIG_PATH,
+    ARP_REQUEST_INTERVAL_MSECS, ARP_RETRIES, ARP_TIMEOUT_SECS, AUTH_PBKDF2_ITERATIONS, AUTH_REALM,
+    CERT_DAYS_VALID, CERT_PATH, CONFIG_PATH, CONNECT_ATTEMPT_DELAY_MSECS, CONNECT_TIMEOUT_MSECS,
+};
+pub use settings::{ProxyConfig, ProxyConfigPatch, get_global_config, set_global_config};
+pub use store::{
+    ConfigVersion, get_config_history, get_config_version, is_config_file_written,
+    persist_global_config, set_config_file,
 };
-pub use settings::{ProxyConfig, get_global_config, set_global_config};
@@ -1,33 +1,129 @@
-use std::sync::{Arc, LazyLock, RwLock};
+use std::sync::{Arc, LazyLock};
 
+use arc_swap::ArcSwap;
 use serde::{Deserialize, Serialize};
 
+use super::app::AppConfig;
+use super::constants::{CONNECT_ATTEMPT_DELAY_MSECS, CONNECT_TIMEOUT_MSECS};
+use crate::utils::tls::validate_ca;
+
+fn default_connect_timeout_ms() -> u64 {
+    CONNECT_TIMEOUT_MSECS
+}
+
+fn default_connect_attempt_delay_ms() -> u64 {
+    CONNECT_ATTEMPT_DELAY_MSECS
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProxyConfig {
     pub intercept_tls: bool,
     pub block_ads: bool,
     pub cache_enabled: bool,
+
+    #[serde(default)]
+    pub require_auth: bool,
+
+    /// Timeout of each upstream connection attempt, in milliseconds.
+    #[serde(default = "default_connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+
+    /// Delay before racing the next resolved address while an attempt is still pending.
+    #[serde(default = "default_connect_attempt_delay_ms")]
+    pub connect_attempt_delay_ms: u64,
 }
 
 impl ProxyConfig {
-    pub fn from_cli(cli: &crate::cli::ProxyCommand) -> Self {
+    pub fn from_app_config(config: &AppConfig) -> Self {
+        Self {
+            intercept_tls: config.tls.intercept,
+            block_ads: config.filters.block_ads,
+            cache_enabled: config.server.cache_enabled,
+            require_auth: config.server.require_auth,
+            connect_timeout_ms: config.timeouts.connect_ms,
+            connect_attempt_delay_ms: config.timeouts.connect_attempt_delay_ms,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.connect_timeout_ms == 0 {
+            return Err("connect_timeout_ms must be greater than zero".to_string());
+        }
+
+        // Every intercepted connection would fail the handshake without a usable CA
+        if self.intercept_tls {
+            validate_ca().map_err(|e| format!("Cannot enable intercept_tls: {}", e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Partial update of [`ProxyConfig`], missing fields keep their current value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfigPatch {
+    pub intercept_tls: Option<bool>,
+    pub block_ads: Option<bool>,
+    pub cache_enabled: Option<bool>,
+    pub require_auth: Option<bool>,
+    pub connect_timeout_ms: Option<u64>,
+    pub connect_attempt_delay_ms: Option<u64>,
+}
+
+impl ProxyConfigPatch {
+    /// The fields that differ between two configurations, with their value in `new`.
+    pub fn diff(old: &ProxyConfig, new: &ProxyConfig) -> Self {
+        fn changed<T: PartialEq + Copy>(old: T, new: T) -> Option<T> {
+            (old != new).then_some(new)
+        }
+
         Self {
-            intercept_tls: cli.intercept_tls,
-            block_ads: cli.block_ads,
-            cache_enabled: cli.cache_enabled,
+            intercept_tls: changed(old.intercept_tls, new.intercept_tls),
+            block_ads: changed(old.block_ads, new.block_ads),
+            cache_enabled: changed(old.cache_enabled, new.cache_enabled),
+            require_auth: changed(old.require_auth, new.require_auth),
+            connect_timeout_ms: changed(old.connect_timeout_ms, new.connect_timeout_ms),
+            connect_attempt_delay_ms: changed(
+                old.connect_attempt_delay_ms,
+                new.connect_attempt_delay_ms,
+            ),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intercept_tls.is_none()
+            && self.block_ads.is_none()
+            && self.cache_enabled.is_none()
+            && self.require_auth.is_none()
+            && self.connect_timeout_ms.is_none()
+            && self.connect_attempt_delay_ms.is_none()
+    }
+
+    pub fn apply(&self, config: &ProxyConfig) -> ProxyConfig {
+        ProxyConfig {
+            intercept_tls: self.intercept_tls.unwrap_or(config.intercept_tls),
+            block_ads: self.block_ads.unwrap_or(config.block_ads),
+            cache_enabled: self.cache_enabled.unwrap_or(config.cache_enabled),
+            require_auth: self.require_auth.unwrap_or(config.require_auth),
+            connect_timeout_ms: self.connect_timeout_ms.unwrap_or(config.connect_timeout_ms),
+            connect_attempt_delay_ms: self
+                .connect_attempt_delay_ms
+                .unwrap_or(config.connect_attempt_delay_ms),
         }
     }
 }
 
-pub static GLOBAL_CONFIG: LazyLock<Arc<RwLock<Option<ProxyConfig>>>> =
-    LazyLock::new(|| Arc::new(RwLock::new(None)));
+/// The active configuration, swapped as a whole so readers on the request path never wait on a
+/// writer. It starts from the defaults until `set_global_config` runs at startup.
+pub static GLOBAL_CONFIG: LazyLock<ArcSwap<ProxyConfig>> =
+    LazyLock::new(|| ArcSwap::from_pointee(ProxyConfig::from_app_config(&AppConfig::default())));
 
 pub fn set_global_config(config: ProxyConfig) {
-    let mut global = GLOBAL_CONFIG.write().unwrap();
-    *global = Some(config);
+    GLOBAL_CONFIG.store(Arc::new(config));
 }
 
-pub fn get_global_config() -> ProxyConfig {
-    let global = GLOBAL_CONFIG.read().unwrap();
-    global.as_ref().unwrap().clone()
+/// A snapshot of the active configuration, later updates do not change it.
+pub fn get_global_config() -> Arc<ProxyConfig> {
+    GLOBAL_CONFIG.load_full()
 }
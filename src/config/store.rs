@@ -0,0 +1,252 @@
+// Persistence of the runtime configuration changes made through the admin API. Accepted changes
+// are written back to the config file, editing only the keys that change so its comments and
+// layout are kept, and every version is kept in a history file next to it (`proxy.history.toml`
+// for `proxy.toml`) so an earlier one can be restored.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+use toml_edit::{DocumentMut, Item, Value};
+
+use super::app::{APP_CONFIG_PATH, AppConfig};
+use super::settings::{ProxyConfig, get_global_config, set_global_config};
+use crate::utils::now_secs;
+
+/// Versions kept in the history file, the oldest ones are dropped first.
+const MAX_CONFIG_VERSIONS: usize = 50;
+
+static CONFIG_FILE: LazyLock<RwLock<PathBuf>> =
+    LazyLock::new(|| RwLock::new(APP_CONFIG_PATH.clone()));
+
+// Serializes the read-modify-write of the config and history files
+static UPDATE_LOCK: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+/// Content of the last write of the config file, so the watcher can tell it from an edit.
+static LAST_WRITTEN: LazyLock<Mutex<Option<String>>> = LazyLock::new(|| Mutex::new(None));
+
+/// A configuration applied at some point, `source` tells how (`initial`, `put`, `patch`,
+/// `rollback to N`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigVersion {
+    pub version: u64,
+    /// Unix timestamp in seconds.
+    pub created_at: u64,
+    pub source: String,
+    pub config: ProxyConfig,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ConfigHistory {
+    #[serde(default)]
+    versions: Vec<ConfigVersion>,
+}
+
+/// Set the config file the runtime changes are written to, the one the proxy was started with.
+pub fn set_config_file(file: PathBuf) {
+    *CONFIG_FILE.write().unwrap() = file;
+}
+
+fn history_file(config_file: &Path) -> PathBuf {
+    config_file.with_extension("history.toml")
+}
+
+fn write_file_atomic(
+    path: &Path,
+    content: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = path.with_extension("tmp");
+    std::fs::write(&temp_path, content)?;
+    std::fs::rename(&temp_path, path)?; // rename is atomic on POSIX systems
+
+    Ok(())
+}
+
+/// Whether the config file holds what the admin API last wrote to it.
+pub fn is_config_file_written(content: &str) -> bool {
+    LAST_WRITTEN.lock().unwrap().as_deref() == Some(content)
+}
+
+/// Set a key of the config file, keeping the comments around its previous value. Missing tables
+/// are added at the end.
+fn set_config_value(document: &mut DocumentMut, table: &str, key: &str, value: Value) {
+    let table = document.entry(table).or_insert_with(toml_edit::table);
+    match table.get_mut(key).and_then(Item::as_value_mut) {
+        Some(previous) => {
+            let decor = previous.decor().clone();
+            *previous = value;
+            *previous.decor_mut() = decor;
+        }
+        None => table[key] = Item::Value(value),
+    }
+}
+
+/// Edit the settings changed from `previous` in the content of the config file. The others are
+/// left as the file has them, so values that only come from command line flags are not saved.
+fn edit_config_file(
+    config_file: &Path,
+    previous: &ProxyConfig,
+    config: &ProxyConfig,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    // Loading it first validates the file, so its tables have the expected shape
+    let current = ProxyConfig::from_app_config(&AppConfig::load(Some(config_file.to_path_buf()))?);
+    let content = match config_file.exists() {
+        true => std::fs::read_to_string(config_file)?,
+        false => String::new(),
+    };
+    let mut document = content.parse::<DocumentMut>()?;
+
+    let mut target = current.clone();
+    if previous.intercept_tls != config.intercept_tls {
+        target.intercept_tls = config.intercept_tls;
+    }
+    if previous.block_ads != config.block_ads {
+        target.block_ads = config.block_ads;
+    }
+    if previous.cache_enabled != config.cache_enabled {
+        target.cache_enabled = config.cache_enabled;
+    }
+    if previous.require_auth != config.require_auth {
+        target.require_auth = config.require_auth;
+    }
+    if previous.connect_timeout_ms != config.connect_timeout_ms {
+        target.connect_timeout_ms = config.connect_timeout_ms;
+    }
+    if previous.connect_attempt_delay_ms != config.connect_attempt_delay_ms {
+        target.connect_attempt_delay_ms = config.connect_attempt_delay_ms;
+    }
+    let config = &target;
+
+    if current.intercept_tls != config.intercept_tls {
+        set_config_value(
+            &mut document,
+            "tls",
+            "intercept",
+            config.intercept_tls.into(),
+        );
+    }
+    if current.block_ads != config.block_ads {
+        set_config_value(
+            &mut document,
+            "filters",
+            "block_ads",
+            config.block_ads.into(),
+        );
+    }
+    if current.cache_enabled != config.cache_enabled {
+        set_config_value(
+            &mut document,
+            "server",
+            "cache_enabled",
+            config.cache_enabled.into(),
+        );
+    }
+    if current.require_auth != config.require_auth {
+        set_config_value(
+            &mut document,
+            "server",
+            "require_auth",
+            config.require_auth.into(),
+        );
+    }
+    if current.connect_timeout_ms != config.connect_timeout_ms {
+        let value = i64::try_from(config.connect_timeout_ms)?;
+        set_config_value(&mut document, "timeouts", "connect_ms", value.into());
+    }
+    if current.connect_attempt_delay_ms != config.connect_attempt_delay_ms {
+        let value = i64::try_from(config.connect_attempt_delay_ms)?;
+        set_config_value(
+            &mut document,
+            "timeouts",
+            "connect_attempt_delay_ms",
+            value.into(),
+        );
+    }
+
+    Ok(document.to_string())
+}
+
+fn load_history(path: &Path) -> Result<ConfigHistory, Box<dyn std::error::Error + Send + Sync>> {
+    if !path.exists() {
+        return Ok(ConfigHistory::default());
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    let history = toml::from_str(&content)
+        .map_err(|e| format!("Invalid config history file {:?}: {}", path, e))?;
+    Ok(history)
+}
+
+/// Every stored version, oldest first.
+pub fn get_config_history() -> Result<Vec<ConfigVersion>, Box<dyn std::error::Error + Send + Sync>>
+{
+    let config_file = CONFIG_FILE.read().unwrap().clone();
+    Ok(load_history(&history_file(&config_file))?.versions)
+}
+
+pub fn get_config_version(
+    version: u64,
+) -> Result<Option<ConfigVersion>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(get_config_history()?
+        .into_iter()
+        .find(|entry| entry.version == version))
+}
+
+/// Write the configuration to the config file, record it as a new version and apply it. The
+/// configuration must already be validated.
+pub fn persist_global_config(
+    config: ProxyConfig,
+    source: &str,
+) -> Result<ConfigVersion, Box<dyn std::error::Error + Send + Sync>> {
+    let _update_guard = UPDATE_LOCK.lock().unwrap();
+
+    let config_file = CONFIG_FILE.read().unwrap().clone();
+    let history_path = history_file(&config_file);
+    let mut history = load_history(&history_path)?;
+
+    let previous = get_global_config();
+
+    // The configuration the proxy started with, so the first change can be rolled back too
+    if history.versions.is_empty() {
+        history.versions.push(ConfigVersion {
+            version: 1,
+            created_at: now_secs(),
+            source: "initial".to_string(),
+            config: (*previous).clone(),
+        });
+    }
+
+    // Re-read the file so manual edits of the other settings are kept
+    let content = edit_config_file(&config_file, &previous, &config)?;
+    write_file_atomic(&config_file, &content)?;
+    *LAST_WRITTEN.lock().unwrap() = Some(content);
+
+    let entry = ConfigVersion {
+        version: history.versions.last().map_or(1, |last| last.version + 1),
+        created_at: now_secs(),
+        source: source.to_string(),
+        config: config.clone(),
+    };
+    history.versions.push(entry.clone());
+    if history.versions.len() > MAX_CONFIG_VERSIONS {
+        let excess = history.versions.len() - MAX_CONFIG_VERSIONS;
+        history.versions.drain(..excess);
+    }
+    write_file_atomic(&history_path, &toml::to_string(&history)?)?;
+
+    set_global_config(config);
+    tracing::info!(
+        "Configuration version {} ({}) saved to {:?}",
+        entry.version,
+        entry.source,
+        config_file
+    );
+
+    Ok(entry)
+}
@@ -0,0 +1,114 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    ips: Vec<IpAddr>,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct DnsCacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Positive answers per host name, kept until their (clamped) TTL expires.
+#[derive(Debug, Default)]
+pub struct DnsCache {
+    entries: HashMap<String, CacheEntry>,
+    capacity: usize,
+    min_ttl: Option<Duration>,
+    max_ttl: Option<Duration>,
+    hits: u64,
+    misses: u64,
+}
+
+impl DnsCache {
+    pub fn new(capacity: usize, min_ttl: Option<Duration>, max_ttl: Option<Duration>) -> Self {
+        Self {
+            capacity,
+            min_ttl,
+            max_ttl,
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&mut self, host: &str) -> Option<Vec<IpAddr>> {
+        let now = Instant::now();
+
+        match self.entries.get(host) {
+            Some(entry) if entry.expires_at > now => {
+                self.hits += 1;
+                Some(entry.ips.clone())
+            }
+            Some(_) => {
+                self.entries.remove(host);
+                self.misses += 1;
+                None
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Cache the answer for `ttl`, clamped to the configured bounds. When the cache is full,
+    /// expired entries are dropped first, then the entry closest to expiring.
+    pub fn insert(&mut self, host: String, ips: Vec<IpAddr>, ttl: Duration) {
+        if self.capacity == 0 || ips.is_empty() {
+            return;
+        }
+
+        let ttl = match (self.min_ttl, self.max_ttl) {
+            (Some(min_ttl), _) if ttl < min_ttl => min_ttl,
+            (_, Some(max_ttl)) if ttl > max_ttl => max_ttl,
+            _ => ttl,
+        };
+
+        let now = Instant::now();
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&host) {
+            self.entries.retain(|_, entry| entry.expires_at > now);
+        }
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&host) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(host, _)| host.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(
+            host,
+            CacheEntry {
+                ips,
+                expires_at: now + ttl,
+            },
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn stats(&self) -> DnsCacheStats {
+        DnsCacheStats {
+            entries: self.entries.len(),
+            capacity: self.capacity,
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+}
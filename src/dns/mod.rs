@@ -0,0 +1,22 @@
+// Upstream DNS resolution: configurable name servers (system, UDP, DNS-over-TLS, DNS-over-HTTPS),
+// static per-host overrides and an answer cache with TTL bounds. The built-in DNS server answers
+// blacklisted names with a sinkhole and forwards everything else through the same resolver.
+
+mod cache;
+mod resolver;
+mod server;
+mod settings;
+
+pub use cache::DnsCacheStats;
+pub use resolver::{DNS_RESOLVER, DnsResolver, LookupIp, init_dns_resolver};
+pub use server::start_dns_server;
+pub use settings::{BlockedResponse, DnsConfig, DnsUpstream};
+
+pub fn get_dns_cache_stats() -> DnsCacheStats {
+    DNS_RESOLVER.cache_stats()
+}
+
+pub fn flush_dns_cache() {
+    DNS_RESOLVER.flush_cache();
+    tracing::info!("DNS cache flushed");
+}
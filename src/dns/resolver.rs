@@ -0,0 +1,188 @@
+use std::{
+    net::IpAddr,
+    sync::{LazyLock, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use trust_dns_proto::rr::{
+    Name, RData, Record, RecordType,
+    rdata::{A, AAAA},
+};
+use trust_dns_resolver::TokioAsyncResolver;
+use trust_dns_resolver::config::{
+    LookupIpStrategy, NameServerConfig, Protocol, ResolverConfig, ResolverOpts,
+};
+use trust_dns_resolver::error::ResolveError;
+use trust_dns_resolver::system_conf::read_system_conf;
+
+use super::cache::{DnsCache, DnsCacheStats};
+use super::settings::{DnsConfig, DnsUpstream, normalize_name};
+use crate::errors::DnsError;
+
+/// TTL of the answers built from static overrides, in seconds.
+const OVERRIDE_TTL: u32 = 300;
+
+/// Addresses returned by a lookup, in the order given by the upstream server.
+#[derive(Debug, Clone)]
+pub struct LookupIp {
+    ips: Vec<IpAddr>,
+}
+
+impl LookupIp {
+    pub fn iter(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.ips.iter().copied()
+    }
+}
+
+/// Upstream resolver with static overrides and a TTL-bounded answer cache in front of it.
+pub struct DnsResolver {
+    config: DnsConfig,
+    resolver: TokioAsyncResolver,
+    cache: Mutex<DnsCache>,
+}
+
+fn build_resolver_config(config: &DnsConfig) -> Result<ResolverConfig, String> {
+    let protocols: &[Protocol] = match config.upstream {
+        DnsUpstream::Google => return Ok(ResolverConfig::google()),
+        DnsUpstream::System => {
+            let (resolver_config, _) = read_system_conf()
+                .map_err(|e| format!("Unable to read the system DNS configuration: {}", e))?;
+            return Ok(resolver_config);
+        }
+        DnsUpstream::Udp => &[Protocol::Udp, Protocol::Tcp],
+        DnsUpstream::Tls => &[Protocol::Tls],
+        DnsUpstream::Https => &[Protocol::Https],
+    };
+
+    let mut resolver_config = ResolverConfig::new();
+    for addr in config.server_addrs()? {
+        for protocol in protocols {
+            let mut name_server = NameServerConfig::new(addr, *protocol);
+            name_server.tls_dns_name = config.tls_name.clone();
+            resolver_config.add_name_server(name_server);
+        }
+    }
+
+    Ok(resolver_config)
+}
+
+impl DnsResolver {
+    pub fn new(config: DnsConfig) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let resolver_config = build_resolver_config(&config)?;
+
+        let min_ttl = config.min_ttl.map(Duration::from_secs);
+        let max_ttl = config.max_ttl.map(Duration::from_secs);
+
+        let mut opts = ResolverOpts::default();
+        // Both families are needed to race IPv6 and IPv4 upstream connections
+        opts.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+        // Address lookups are cached here, where the stats apply, so the answers are not kept
+        // twice
+        opts.cache_size = 0;
+        opts.positive_min_ttl = min_ttl;
+        opts.positive_max_ttl = max_ttl;
+
+        Ok(Self {
+            resolver: TokioAsyncResolver::tokio(resolver_config, opts),
+            cache: Mutex::new(DnsCache::new(config.cache_size, min_ttl, max_ttl)),
+            config,
+        })
+    }
+
+    pub fn config(&self) -> &DnsConfig {
+        &self.config
+    }
+
+    /// Resolve a host name. IP literals are returned as-is, overrides never reach upstream.
+    pub async fn lookup_ip(&self, host: impl AsRef<str>) -> Result<LookupIp, DnsError> {
+        let host = host.as_ref();
+        if let Ok(ip) = host
+            .trim_matches(|c| c == '[' || c == ']')
+            .parse::<IpAddr>()
+        {
+            return Ok(LookupIp { ips: vec![ip] });
+        }
+
+        let name = normalize_name(host);
+        if let Some(ips) = self.config.overrides.get(&name) {
+            tracing::debug!("Resolved {} from static overrides", name);
+            return Ok(LookupIp { ips: ips.clone() });
+        }
+
+        if let Some(ips) = self.cache.lock().unwrap().get(&name) {
+            return Ok(LookupIp { ips });
+        }
+
+        let lookup = self
+            .resolver
+            .lookup_ip(name.as_str())
+            .await
+            .map_err(|e| DnsError::from_resolve(&name, e))?;
+        let ips = lookup.iter().collect::<Vec<_>>();
+        let ttl = lookup
+            .valid_until()
+            .saturating_duration_since(Instant::now());
+
+        self.cache.lock().unwrap().insert(name, ips.clone(), ttl);
+        Ok(LookupIp { ips })
+    }
+
+    /// Forward a query of any record type upstream, as the built-in DNS server does. A and AAAA
+    /// queries for overridden names are answered from the overrides.
+    pub async fn lookup_records(
+        &self,
+        name: &Name,
+        record_type: RecordType,
+    ) -> Result<Vec<Record>, ResolveError> {
+        if let Some(ips) = self.config.overrides.get(&normalize_name(&name.to_utf8())) {
+            let records = ips
+                .iter()
+                .filter_map(|ip| match (ip, record_type) {
+                    (IpAddr::V4(ip), RecordType::A) => Some(RData::A(A(*ip))),
+                    (IpAddr::V6(ip), RecordType::AAAA) => Some(RData::AAAA(AAAA(*ip))),
+                    _ => None,
+                })
+                .map(|rdata| Record::from_rdata(name.clone(), OVERRIDE_TTL, rdata))
+                .collect();
+            return Ok(records);
+        }
+
+        let lookup = self.resolver.lookup(name.clone(), record_type).await?;
+        Ok(lookup.records().to_vec())
+    }
+
+    pub fn cache_stats(&self) -> DnsCacheStats {
+        self.cache.lock().unwrap().stats()
+    }
+
+    pub fn flush_cache(&self) {
+        self.cache.lock().unwrap().clear();
+        self.resolver.clear_cache();
+    }
+}
+
+static CONFIGURED_RESOLVER: OnceLock<DnsResolver> = OnceLock::new();
+
+/// Build the resolver from the settings loaded at startup, so an invalid upstream is reported
+/// there rather than by the first lookup.
+pub fn init_dns_resolver(
+    config: DnsConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let resolver = DnsResolver::new(config)?;
+    CONFIGURED_RESOLVER
+        .set(resolver)
+        .map_err(|_| "The DNS resolver is already initialized")?;
+    Ok(())
+}
+
+/// The resolver built by [`init_dns_resolver`]. Commands that do not call it use the settings of
+/// the DNS config file, or the defaults when the file is invalid.
+pub static DNS_RESOLVER: LazyLock<&'static DnsResolver> = LazyLock::new(|| {
+    CONFIGURED_RESOLVER.get_or_init(|| match DnsConfig::load(None).and_then(DnsResolver::new) {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            tracing::error!("Using the default DNS settings: {}", e);
+            DnsResolver::new(DnsConfig::default()).expect("the default DNS settings are valid")
+        }
+    })
+});
@@ -0,0 +1,253 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::try_join_all;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::time::timeout;
+use trust_dns_proto::op::{Message, MessageType, OpCode, ResponseCode};
+use trust_dns_proto::rr::{
+    RData, Record, RecordType,
+    rdata::{A, AAAA},
+};
+use trust_dns_resolver::error::ResolveErrorKind;
+
+use super::resolver::DNS_RESOLVER;
+use super::settings::BlockedResponse;
+use crate::acl::{AclScope, is_client_allowed};
+use crate::config::get_global_config;
+use crate::filters::{is_blocking_paused, is_domain_blocked};
+use crate::shutdown::{shutdown_token, spawn_connection};
+use crate::utils::listen::{
+    ListenFamily, bind_tcp_listener, bind_udp_socket, resolve_listen_addrs,
+};
+
+/// TTL of the sinkhole answers, short so unblocked names recover quickly.
+const BLOCKED_TTL: u32 = 60;
+const MAX_UDP_MESSAGE_SIZE: usize = 4096;
+
+/// Time a TCP client has to send its next query, or the rest of one. RFC 7766 recommends an idle
+/// timeout of a few seconds so clients cannot hold connections open.
+const TCP_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn is_name_blocked(name: &str, client_ip: IpAddr) -> bool {
+    let config = get_global_config();
+
+    config.block_ads
+        && is_client_allowed(client_ip, AclScope::BlockAds)
+        && !is_blocking_paused(client_ip)
+        && is_domain_blocked(name)
+}
+
+/// Build the answer to a query message. Returns `None` for data that is not a DNS query.
+async fn handle_query(request: &[u8], client_ip: IpAddr) -> Option<Message> {
+    let request = Message::from_vec(request).ok()?;
+    if request.message_type() != MessageType::Query {
+        return None;
+    }
+
+    let mut response = Message::new();
+    response
+        .set_id(request.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(request.op_code())
+        .set_recursion_desired(request.recursion_desired())
+        .set_recursion_available(true);
+
+    let Some(query) = request.queries().first().cloned() else {
+        response.set_response_code(ResponseCode::FormErr);
+        return Some(response);
+    };
+    response.add_query(query.clone());
+
+    if request.op_code() != OpCode::Query {
+        response.set_response_code(ResponseCode::NotImp);
+        return Some(response);
+    }
+
+    if !is_client_allowed(client_ip, AclScope::Proxy) {
+        tracing::warn!("DNS query from {} denied by access control", client_ip);
+        response.set_response_code(ResponseCode::Refused);
+        return Some(response);
+    }
+
+    let name = query.name().to_utf8();
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let record_type = query.query_type();
+
+    if is_name_blocked(&name, client_ip) {
+        tracing::info!(
+            "Blocked DNS query {} {} from {}",
+            record_type,
+            name,
+            client_ip
+        );
+
+        match DNS_RESOLVER.config().blocked_response {
+            BlockedResponse::Nxdomain => {
+                response.set_response_code(ResponseCode::NXDomain);
+            }
+            BlockedResponse::NullIp => {
+                let rdata = match record_type {
+                    RecordType::A => Some(RData::A(A(Ipv4Addr::UNSPECIFIED))),
+                    RecordType::AAAA => Some(RData::AAAA(AAAA(Ipv6Addr::UNSPECIFIED))),
+                    _ => None,
+                };
+                if let Some(rdata) = rdata {
+                    response.add_answer(Record::from_rdata(
+                        query.name().clone(),
+                        BLOCKED_TTL,
+                        rdata,
+                    ));
+                }
+            }
+        }
+
+        return Some(response);
+    }
+
+    tracing::debug!(
+        "Forwarding DNS query {} {} from {}",
+        record_type,
+        name,
+        client_ip
+    );
+    match DNS_RESOLVER.lookup_records(query.name(), record_type).await {
+        Ok(records) => {
+            response.add_answers(records);
+        }
+        Err(e) => match e.kind() {
+            ResolveErrorKind::NoRecordsFound { response_code, .. } => {
+                response.set_response_code(*response_code);
+            }
+            _ => {
+                tracing::warn!("Upstream DNS query {} {} failed: {}", record_type, name, e);
+                response.set_response_code(ResponseCode::ServFail);
+            }
+        },
+    }
+
+    Some(response)
+}
+
+async fn handle_udp_query(
+    socket: Arc<UdpSocket>,
+    request: Vec<u8>,
+    peer_addr: SocketAddr,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let max_payload = Message::from_vec(&request)
+        .map(|message| message.max_payload() as usize)
+        .unwrap_or(512);
+
+    let Some(mut response) = handle_query(&request, peer_addr.ip()).await else {
+        return Ok(());
+    };
+
+    let mut bytes = response.to_vec()?;
+    // Answers that do not fit are dropped, the client retries over TCP
+    if bytes.len() > max_payload {
+        response.take_answers();
+        response.set_truncated(true);
+        bytes = response.to_vec()?;
+    }
+
+    socket.send_to(&bytes, peer_addr).await?;
+    Ok(())
+}
+
+/// Serve length-prefixed queries until the client closes the connection or goes idle.
+async fn handle_tcp_connection(
+    mut stream: TcpStream,
+    peer_addr: SocketAddr,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let shutdown = shutdown_token();
+
+    loop {
+        let len = tokio::select! {
+            len = timeout(TCP_READ_TIMEOUT, stream.read_u16()) => match len {
+                Ok(Ok(len)) => len as usize,
+                // Closed, or no query within the timeout
+                _ => return Ok(()),
+            },
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+
+        let mut request = vec![0u8; len];
+        timeout(TCP_READ_TIMEOUT, stream.read_exact(&mut request))
+            .await
+            .map_err(|_| format!("Timed out reading a query of {} bytes", len))??;
+
+        let Some(response) = handle_query(&request, peer_addr.ip()).await else {
+            return Ok(());
+        };
+
+        let bytes = response.to_vec()?;
+        stream.write_u16(bytes.len() as u16).await?;
+        stream.write_all(&bytes).await?;
+    }
+}
+
+#[tracing::instrument(level = "info", name = "DNS Server")]
+pub async fn start_dns_server(
+    host: String,
+    port: u16,
+    family: ListenFamily,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut servers = Vec::new();
+    for addr in resolve_listen_addrs(&host, port, family).await? {
+        tracing::info!("Starting DNS server at {} (UDP and TCP)", addr);
+
+        let socket =
+            bind_udp_socket(addr).map_err(|e| format!("Unable to listen on {}: {}", addr, e))?;
+        let listener =
+            bind_tcp_listener(addr).map_err(|e| format!("Unable to listen on {}: {}", addr, e))?;
+        servers.push(serve_dns(Arc::new(socket), listener));
+    }
+
+    try_join_all(servers).await?;
+    Ok(())
+}
+
+async fn serve_dns(
+    socket: Arc<UdpSocket>,
+    listener: TcpListener,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let shutdown = shutdown_token();
+    let mut buffer = vec![0u8; MAX_UDP_MESSAGE_SIZE];
+
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buffer) => {
+                let (n, peer_addr) = match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        // e.g. ICMP port unreachable from a previous answer, keep serving
+                        tracing::debug!("Error receiving DNS query: {}", e);
+                        continue;
+                    }
+                };
+
+                let request = buffer[..n].to_vec();
+                let socket = socket.clone();
+                spawn_connection(async move {
+                    if let Err(e) = handle_udp_query(socket, request, peer_addr).await {
+                        tracing::error!("Error answering DNS query from {}: {}", peer_addr, e);
+                    }
+                });
+            }
+            accepted = listener.accept() => {
+                let (stream, peer_addr) = accepted?;
+                spawn_connection(async move {
+                    if let Err(e) = handle_tcp_connection(stream, peer_addr).await {
+                        tracing::error!("Error serving DNS connection from {}: {}", peer_addr, e);
+                    }
+                });
+            }
+            _ = shutdown.cancelled() => {
+                tracing::info!("DNS server stopped accepting queries");
+                return Ok(());
+            }
+        }
+    }
+}
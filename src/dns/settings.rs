@@ -0,0 +1,194 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::LazyLock,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::CONFIG_PATH;
+
+static DNS_CONFIG_PATH: LazyLock<PathBuf> = LazyLock::new(|| CONFIG_PATH.join("dns.toml"));
+
+fn default_cache_size() -> usize {
+    1024
+}
+
+/// Where upstream queries are sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsUpstream {
+    /// Google public DNS over plain UDP/TCP.
+    #[default]
+    Google,
+    /// Name servers from the system configuration (`/etc/resolv.conf`).
+    System,
+    /// `servers` over plain UDP/TCP, port 53 unless specified.
+    Udp,
+    /// `servers` over DNS-over-TLS, port 853 unless specified.
+    Tls,
+    /// `servers` over DNS-over-HTTPS, port 443 unless specified.
+    Https,
+}
+
+impl DnsUpstream {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            DnsUpstream::Tls => 853,
+            DnsUpstream::Https => 443,
+            _ => 53,
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, DnsUpstream::Tls | DnsUpstream::Https)
+    }
+}
+
+/// Answer given by the built-in DNS server for blacklisted names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockedResponse {
+    /// `0.0.0.0` for A queries and `::` for AAAA queries, empty answers for other types.
+    #[default]
+    NullIp,
+    /// NXDOMAIN, as if the name did not exist.
+    Nxdomain,
+}
+
+// TOML file content, a missing file keeps the previous behaviour (Google DNS with default options)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsConfig {
+    #[serde(skip)]
+    pub file: PathBuf,
+
+    #[serde(default)]
+    pub upstream: DnsUpstream,
+
+    /// Name server addresses, `ip` or `ip:port`. Required for `udp`, `tls` and `https`.
+    #[serde(default)]
+    pub servers: Vec<String>,
+
+    /// Name presented in the certificate of the `tls` and `https` servers.
+    #[serde(default)]
+    pub tls_name: Option<String>,
+
+    /// Maximum number of cached host names, 0 disables the cache.
+    #[serde(default = "default_cache_size")]
+    pub cache_size: usize,
+
+    /// Lower bound in seconds for the TTL of cached answers.
+    #[serde(default)]
+    pub min_ttl: Option<u64>,
+
+    /// Upper bound in seconds for the TTL of cached answers.
+    #[serde(default)]
+    pub max_ttl: Option<u64>,
+
+    /// Static answers per host name, resolved without querying upstream (like a hosts file).
+    #[serde(default)]
+    pub overrides: BTreeMap<String, Vec<IpAddr>>,
+
+    /// How the built-in DNS server answers blacklisted names.
+    #[serde(default)]
+    pub blocked_response: BlockedResponse,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            file: DNS_CONFIG_PATH.clone(),
+            upstream: DnsUpstream::default(),
+            servers: Vec::new(),
+            tls_name: None,
+            cache_size: default_cache_size(),
+            min_ttl: None,
+            max_ttl: None,
+            overrides: BTreeMap::new(),
+            blocked_response: BlockedResponse::default(),
+        }
+    }
+}
+
+impl DnsConfig {
+    /// Load the resolver settings from the TOML file. A missing file keeps the defaults, an
+    /// invalid one is an error.
+    pub fn load(file: Option<PathBuf>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let file = file.unwrap_or(DNS_CONFIG_PATH.clone());
+        let content = match file.exists() {
+            true => std::fs::read_to_string(&file)?,
+            false => String::new(),
+        };
+
+        let mut config: DnsConfig = toml::from_str(&content)
+            .map_err(|e| format!("Invalid DNS config file {:?}: {}", file, e))?;
+        config.file = file;
+
+        // Host names are matched the same way the resolver receives them
+        config.overrides = config
+            .overrides
+            .into_iter()
+            .map(|(host, ips)| (normalize_name(&host), ips))
+            .collect();
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let needs_servers = !matches!(self.upstream, DnsUpstream::Google | DnsUpstream::System);
+        if needs_servers && self.servers.is_empty() {
+            return Err(format!(
+                "The '{:?}' DNS upstream requires at least one server",
+                self.upstream
+            ));
+        }
+
+        if self.upstream.is_encrypted() && self.tls_name.is_none() {
+            return Err(format!(
+                "The '{:?}' DNS upstream requires 'tls_name'",
+                self.upstream
+            ));
+        }
+
+        self.server_addrs()?;
+
+        if let (Some(min_ttl), Some(max_ttl)) = (self.min_ttl, self.max_ttl)
+            && min_ttl > max_ttl
+        {
+            return Err(format!(
+                "DNS min_ttl ({}) cannot be greater than max_ttl ({})",
+                min_ttl, max_ttl
+            ));
+        }
+
+        if let Some((host, _)) = self.overrides.iter().find(|(_, ips)| ips.is_empty()) {
+            return Err(format!("DNS override for '{}' has no addresses", host));
+        }
+
+        Ok(())
+    }
+
+    /// Parse `servers`, applying the default port of the upstream protocol.
+    pub fn server_addrs(&self) -> Result<Vec<SocketAddr>, String> {
+        self.servers
+            .iter()
+            .map(|server| {
+                server
+                    .parse::<SocketAddr>()
+                    .or_else(|_| {
+                        server
+                            .parse::<IpAddr>()
+                            .map(|ip| SocketAddr::new(ip, self.upstream.default_port()))
+                    })
+                    .map_err(|_| format!("Invalid DNS server address '{}'", server))
+            })
+            .collect()
+    }
+}
+
+/// Lowercase the name and drop the trailing dot of fully qualified names.
+pub fn normalize_name(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_ascii_lowercase()
+}
@@ -0,0 +1,372 @@
+// Typed errors of the proxy, one enum per subsystem. Every variant has a stable code, logged as
+// `error_code` and sent to clients in the `X-Proxy-Error` header, and the HTTP status a client of
+// the proxy or of the admin API receives for it.
+
+mod page;
+
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use http::StatusCode;
+use thiserror::Error;
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+
+pub use page::{ERROR_CODE_HEADER, error_page, error_response, https_error_response};
+
+/// Stable identifier and HTTP status of an error.
+pub trait ErrorCode: Error {
+    fn code(&self) -> &'static str;
+    fn status(&self) -> StatusCode;
+}
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error("Malformed request line '{0}'")]
+    RequestLine(String),
+
+    #[error("Invalid authority '{authority}': {reason}")]
+    Authority { authority: String, reason: String },
+
+    #[error("Invalid header '{0}'")]
+    Header(String),
+
+    #[error("The request has no host")]
+    MissingHost,
+
+    #[error("Invalid URI '{uri}': {reason}")]
+    Uri { uri: String, reason: String },
+}
+
+impl ErrorCode for ParseError {
+    fn code(&self) -> &'static str {
+        match self {
+            ParseError::RequestLine(_) => "PARSE_REQUEST_LINE",
+            ParseError::Authority { .. } => "PARSE_AUTHORITY",
+            ParseError::Header(_) => "PARSE_HEADER",
+            ParseError::MissingHost => "PARSE_MISSING_HOST",
+            ParseError::Uri { .. } => "PARSE_URI",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DnsError {
+    #[error("No DNS records found for {host}")]
+    NoRecords { host: String },
+
+    #[error("DNS lookup of {host} timed out")]
+    Timeout { host: String },
+
+    #[error("DNS lookup of {host} failed: {reason}")]
+    Lookup { host: String, reason: String },
+}
+
+impl DnsError {
+    pub fn from_resolve(host: &str, error: ResolveError) -> Self {
+        let host = host.to_string();
+        match error.kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => DnsError::NoRecords { host },
+            ResolveErrorKind::Timeout => DnsError::Timeout { host },
+            _ => DnsError::Lookup {
+                host,
+                reason: error.to_string(),
+            },
+        }
+    }
+}
+
+impl ErrorCode for DnsError {
+    fn code(&self) -> &'static str {
+        match self {
+            DnsError::NoRecords { .. } => "DNS_NO_RECORDS",
+            DnsError::Timeout { .. } => "DNS_TIMEOUT",
+            DnsError::Lookup { .. } => "DNS_LOOKUP",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            DnsError::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConnectError {
+    #[error("No IP address to connect to")]
+    NoAddress,
+
+    #[error("Unable to connect to any address ({0})")]
+    Failed(String),
+
+    #[error("Connection timed out after {0:?}")]
+    Timeout(Duration),
+
+    #[error("Upstream request failed: {0}")]
+    Upstream(String),
+
+    #[error("Upstream request timed out: {0}")]
+    UpstreamTimeout(String),
+}
+
+impl ConnectError {
+    pub fn from_reqwest(error: reqwest::Error) -> Self {
+        // The reason of a failed connection is in the source, reqwest only names the URL
+        let reason = match error.source() {
+            Some(source) => format!("{} ({})", error, source),
+            None => error.to_string(),
+        };
+
+        if error.is_timeout() {
+            ConnectError::UpstreamTimeout(reason)
+        } else if error.is_connect() {
+            ConnectError::Failed(reason)
+        } else {
+            ConnectError::Upstream(reason)
+        }
+    }
+}
+
+impl ErrorCode for ConnectError {
+    fn code(&self) -> &'static str {
+        match self {
+            ConnectError::NoAddress => "CONNECT_NO_ADDRESS",
+            ConnectError::Failed(_) => "CONNECT_FAILED",
+            ConnectError::Timeout(_) => "CONNECT_TIMEOUT",
+            ConnectError::Upstream(_) => "UPSTREAM_REQUEST",
+            ConnectError::UpstreamTimeout(_) => "UPSTREAM_TIMEOUT",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            ConnectError::Timeout(_) | ConnectError::UpstreamTimeout(_) => {
+                StatusCode::GATEWAY_TIMEOUT
+            }
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Unable to issue a certificate for {host}: {reason}")]
+    Certificate { host: String, reason: String },
+
+    #[error("TLS handshake with the client failed: {0}")]
+    ClientHandshake(String),
+
+    #[error("TLS handshake with {host} failed: {reason}")]
+    UpstreamHandshake { host: String, reason: String },
+
+    #[error("The CA certificate has expired, please regenerate it")]
+    CaExpired,
+}
+
+impl ErrorCode for TlsError {
+    fn code(&self) -> &'static str {
+        match self {
+            TlsError::Certificate { .. } => "TLS_CERTIFICATE",
+            TlsError::ClientHandshake(_) => "TLS_CLIENT_HANDSHAKE",
+            TlsError::UpstreamHandshake { .. } => "TLS_UPSTREAM_HANDSHAKE",
+            TlsError::CaExpired => "TLS_CA_EXPIRED",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        StatusCode::BAD_GATEWAY
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum FilterError {
+    #[error("The host {host} is blocked by the network administrator")]
+    Blocked { host: String },
+
+    #[error("The request to {url} is blocked by the network administrator")]
+    BlockedRequest { url: String },
+
+    #[error("Invalid filter file {file:?}: {reason}")]
+    InvalidFile { file: PathBuf, reason: String },
+
+    #[error("Invalid regex '{pattern}': {reason}")]
+    InvalidPattern { pattern: String, reason: String },
+
+    #[error("Unable to write the filter file: {0}")]
+    Persist(String),
+
+    #[error("{0}")]
+    InvalidSubscription(String),
+
+    #[error("Subscription '{0}' not found")]
+    SubscriptionNotFound(String),
+
+    #[error("{0}")]
+    InvalidTemporaryRule(String),
+
+    #[error("Temporary rule {0} not found")]
+    TemporaryRuleNotFound(u64),
+}
+
+impl ErrorCode for FilterError {
+    fn code(&self) -> &'static str {
+        match self {
+            FilterError::Blocked { .. } => "FILTER_BLOCKED",
+            FilterError::BlockedRequest { .. } => "FILTER_BLOCKED_REQUEST",
+            FilterError::InvalidFile { .. } => "FILTER_INVALID_FILE",
+            FilterError::InvalidPattern { .. } => "FILTER_INVALID_PATTERN",
+            FilterError::Persist(_) => "FILTER_PERSIST",
+            FilterError::InvalidSubscription(_) => "FILTER_INVALID_SUBSCRIPTION",
+            FilterError::SubscriptionNotFound(_) => "FILTER_SUBSCRIPTION_NOT_FOUND",
+            FilterError::InvalidTemporaryRule(_) => "FILTER_INVALID_TEMPORARY_RULE",
+            FilterError::TemporaryRuleNotFound(_) => "FILTER_TEMPORARY_RULE_NOT_FOUND",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            FilterError::Blocked { .. } | FilterError::BlockedRequest { .. } => {
+                StatusCode::FORBIDDEN
+            }
+            FilterError::InvalidFile { .. }
+            | FilterError::InvalidPattern { .. }
+            | FilterError::InvalidSubscription(_)
+            | FilterError::InvalidTemporaryRule(_) => StatusCode::BAD_REQUEST,
+            FilterError::SubscriptionNotFound(_) | FilterError::TemporaryRuleNotFound(_) => {
+                StatusCode::NOT_FOUND
+            }
+            FilterError::Persist(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ScanError {
+    #[error("Invalid network '{network}': {reason}")]
+    InvalidNetwork { network: String, reason: String },
+
+    #[error("Network interface '{0}' not found")]
+    InterfaceNotFound(String),
+
+    #[error("Network interface '{interface}' has no {missing}")]
+    InterfaceAddress {
+        interface: String,
+        missing: &'static str,
+    },
+
+    #[error("Unable to open a datalink channel: {0}")]
+    Channel(String),
+
+    #[error("Unable to send the ARP request: {0}")]
+    Send(String),
+
+    #[error("Unable to start the scanning threads: {0}")]
+    ThreadPool(String),
+
+    #[error("Unable to format the results: {0}")]
+    Output(String),
+}
+
+impl ErrorCode for ScanError {
+    fn code(&self) -> &'static str {
+        match self {
+            ScanError::InvalidNetwork { .. } => "SCAN_INVALID_NETWORK",
+            ScanError::InterfaceNotFound(_) => "SCAN_INTERFACE_NOT_FOUND",
+            ScanError::InterfaceAddress { .. } => "SCAN_INTERFACE_ADDRESS",
+            ScanError::Channel(_) => "SCAN_CHANNEL",
+            ScanError::Send(_) => "SCAN_SEND",
+            ScanError::ThreadPool(_) => "SCAN_THREAD_POOL",
+            ScanError::Output(_) => "SCAN_OUTPUT",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            ScanError::InvalidNetwork { .. }
+            | ScanError::InterfaceNotFound(_)
+            | ScanError::InterfaceAddress { .. } => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Any of the subsystem errors, for the paths that cross several of them.
+#[derive(Debug, Error)]
+pub enum ProxyError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+
+    #[error(transparent)]
+    Dns(#[from] DnsError),
+
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+
+    #[error(transparent)]
+    Tls(#[from] TlsError),
+
+    #[error(transparent)]
+    Filter(#[from] FilterError),
+
+    #[error(transparent)]
+    Scan(#[from] ScanError),
+}
+
+impl ProxyError {
+    fn inner(&self) -> &dyn ErrorCode {
+        match self {
+            ProxyError::Parse(e) => e,
+            ProxyError::Dns(e) => e,
+            ProxyError::Connect(e) => e,
+            ProxyError::Tls(e) => e,
+            ProxyError::Filter(e) => e,
+            ProxyError::Scan(e) => e,
+        }
+    }
+}
+
+impl ErrorCode for ProxyError {
+    fn code(&self) -> &'static str {
+        self.inner().code()
+    }
+
+    fn status(&self) -> StatusCode {
+        self.inner().status()
+    }
+}
+
+/// The typed error inside a boxed one, looking through its sources.
+pub fn find_error_code<'a>(error: &'a (dyn Error + 'static)) -> Option<&'a dyn ErrorCode> {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(e) = error.downcast_ref::<ProxyError>() {
+            return Some(e);
+        } else if let Some(e) = error.downcast_ref::<ParseError>() {
+            return Some(e);
+        } else if let Some(e) = error.downcast_ref::<DnsError>() {
+            return Some(e);
+        } else if let Some(e) = error.downcast_ref::<ConnectError>() {
+            return Some(e);
+        } else if let Some(e) = error.downcast_ref::<TlsError>() {
+            return Some(e);
+        } else if let Some(e) = error.downcast_ref::<FilterError>() {
+            return Some(e);
+        } else if let Some(e) = error.downcast_ref::<ScanError>() {
+            return Some(e);
+        }
+        current = error.source();
+    }
+
+    None
+}
+
+/// Code to log for any error, `INTERNAL` for the ones that are not typed yet.
+pub fn error_code(error: &(dyn Error + 'static)) -> &'static str {
+    find_error_code(error).map_or("INTERNAL", |e| e.code())
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use http::{HeaderValue, Response, StatusCode, header::CONTENT_TYPE};
+use http_body_util::Full;
+
+use super::ErrorCode;
+use crate::schemas::HttpsResponse;
+
+/// Response header carrying the error code, for clients and scripts that do not read the page.
+pub const ERROR_CODE_HEADER: &str = "x-proxy-error";
+
+fn explanation(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "The proxy could not understand the request.",
+        StatusCode::FORBIDDEN => "Access to this site is blocked by the network administrator.",
+        StatusCode::BAD_GATEWAY => "The proxy could not reach the destination server.",
+        StatusCode::GATEWAY_TIMEOUT => "The destination server did not answer in time.",
+        _ => "The proxy could not complete the request.",
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// HTML page explaining the error to the user of the proxy.
+pub fn error_page(error: &dyn ErrorCode) -> String {
+    let status = error.status();
+    let title = format!(
+        "{} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    );
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n<p>{}</p>\n<p>{}</p>\n<p><small>Error code: {}</small></p>\n</body>\n</html>\n",
+        explanation(status),
+        escape_html(&error.to_string()),
+        error.code(),
+    )
+}
+
+/// Error page as a hyper response, for the requests served through hyper.
+pub fn error_response(error: &dyn ErrorCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(error_page(error))));
+    *response.status_mut() = error.status();
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    response
+        .headers_mut()
+        .insert(ERROR_CODE_HEADER, HeaderValue::from_static(error.code()));
+    response
+}
+
+/// Error page for the connections the proxy writes to by hand: CONNECT tunnels and intercepted
+/// TLS streams.
+pub fn https_error_response(error: &dyn ErrorCode, version: &str) -> HttpsResponse {
+    let status = error.status();
+    let headers = HashMap::from([
+        (
+            "content-type".to_string(),
+            "text/html; charset=utf-8".to_string(),
+        ),
+        ("connection".to_string(), "close".to_string()),
+        (ERROR_CODE_HEADER.to_string(), error.code().to_string()),
+    ]);
+
+    HttpsResponse {
+        version: version.to_string(),
+        status_code: status.as_u16(),
+        status_text: status.canonical_reason().unwrap_or_default().to_string(),
+        headers,
+        body: Some(error_page(error).into_bytes()),
+    }
+}
@@ -0,0 +1,503 @@
+// Cosmetic filtering: the element hiding rules of AdBlock lists.
+//
+// - `##.ad-banner` hides the elements matching the selector on every site
+// - `example.com,~shop.example.com##.sponsored` only on some sites, or on all but some
+// - `#@#.ad-banner` and `example.com#@#.ad-banner` are exceptions to them
+// - `@@||example.com^$generichide` disables the rules of every site on a site, `$elemhide` all of
+//   them
+//
+// HTML pages get a `<style>` hiding the elements, built from the rules of their site and the
+// generic rules whose class or id appears in the page: EasyList has tens of thousands of generic
+// selectors and a page only needs a handful of them. Procedural rules (`#?#`, `:has-text()`...)
+// and scriptlets (`##+js()`) need a script in the page and are skipped.
+//
+// Rules come from `cosmetic_filters.txt` next to the filter file and from the AdBlock lists of the
+// subscriptions.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, LazyLock},
+};
+
+use arc_swap::ArcSwap;
+
+use super::domain_filter::DOMAIN_FILTER;
+use super::network::{append_rules, domain_matches};
+use super::subscriptions::cosmetic_rules_file;
+use crate::config::CONFIG_PATH;
+
+static COSMETIC_FILTERS_PATH: LazyLock<PathBuf> =
+    LazyLock::new(|| CONFIG_PATH.join("cosmetic_filters.txt"));
+
+/// Extended selectors of uBlock Origin and AdBlock Plus, which are not CSS.
+const PROCEDURAL_OPERATORS: &[&str] = &[
+    ":has-text(",
+    ":-abp-",
+    ":contains(",
+    ":xpath(",
+    ":upward(",
+    ":remove(",
+    ":remove-attr(",
+    ":remove-class(",
+    ":style(",
+    ":matches-css",
+    ":matches-attr(",
+    ":matches-path(",
+    ":matches-media(",
+    ":min-text-length(",
+    ":watch-attr(",
+    ":others(",
+    ":if(",
+    ":if-not(",
+];
+
+enum CosmeticRule {
+    Hide {
+        domains: Vec<(String, bool)>,
+        selector: String,
+    },
+    Exception {
+        domains: Vec<String>,
+        selector: String,
+    },
+    /// `$generichide` when `generic_only`, `$elemhide` otherwise
+    Disable { domain: String, generic_only: bool },
+}
+
+/// The host and its parent domains, `a.b.example.com`, `b.example.com`, `example.com`, `com`.
+fn domain_chain(host: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(host), |domain| {
+        domain.split_once('.').map(|(_, parent)| parent)
+    })
+}
+
+fn is_ident_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_'
+}
+
+/// A class (`.name`) or id (`#name`) an element must have to match the selector, outside of
+/// attribute selectors and pseudo-classes.
+fn selector_key(selector: &str) -> Option<&str> {
+    let bytes = selector.as_bytes();
+    let (mut brackets, mut parentheses) = (0usize, 0usize);
+    let mut quote = None;
+
+    for (i, &byte) in bytes.iter().enumerate() {
+        match (quote, byte) {
+            (Some(q), _) if byte == q => quote = None,
+            (Some(_), _) => {}
+            (None, b'"' | b'\'') => quote = Some(byte),
+            (None, b'[') => brackets += 1,
+            (None, b']') => brackets = brackets.saturating_sub(1),
+            (None, b'(') => parentheses += 1,
+            (None, b')') => parentheses = parentheses.saturating_sub(1),
+            (None, b'.' | b'#') if brackets == 0 && parentheses == 0 => {
+                let end = bytes[i + 1..]
+                    .iter()
+                    .position(|&b| !is_ident_byte(b))
+                    .map_or(bytes.len(), |len| i + 1 + len);
+                // An escaped name (`.\31 23`) is not compared with the page
+                if end > i + 1 && bytes.get(end) != Some(&b'\\') {
+                    return Some(&selector[i..end]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Classes (`.name`) and ids (`#name`) of the elements of a page.
+fn page_keys(html: &str) -> HashSet<String> {
+    let mut keys = HashSet::new();
+    let bytes = html.as_bytes();
+
+    for (attribute, prefix) in [("class=", '.'), ("id=", '#')] {
+        let mut start = 0;
+        while let Some(found) = html[start..].find(attribute) {
+            let name_start = start + found;
+            let mut value_start = name_start + attribute.len();
+            start = value_start;
+
+            // `data-id=` and the like are other attributes
+            if name_start == 0 || !bytes[name_start - 1].is_ascii_whitespace() {
+                continue;
+            }
+
+            let value_end = match bytes.get(value_start) {
+                Some(&quote @ (b'"' | b'\'')) => {
+                    value_start += 1;
+                    html[value_start..]
+                        .find(quote as char)
+                        .map_or(html.len(), |len| value_start + len)
+                }
+                _ => html[value_start..]
+                    .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                    .map_or(html.len(), |len| value_start + len),
+            };
+
+            for name in html[value_start..value_end].split_ascii_whitespace() {
+                keys.insert(format!("{}{}", prefix, name));
+            }
+            start = value_end;
+        }
+    }
+
+    keys
+}
+
+impl CosmeticRule {
+    /// Parse a cosmetic rule. Lines that are not cosmetic rules are `Ok(None)`, cosmetic rules
+    /// that cannot be applied are an error.
+    fn parse(line: &str) -> Result<Option<Self>, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('!') {
+            return Ok(None);
+        }
+
+        if let Some(rule) = line.strip_prefix("@@||") {
+            return Ok(Self::parse_disable(rule));
+        }
+
+        if ["#?#", "#$#", "#@?#", "#@$#", "#%#", "#@%#"]
+            .iter()
+            .any(|marker| line.contains(marker))
+        {
+            return Err("procedural and scriptlet rules are not supported".to_string());
+        }
+
+        let (domains, selector, exception) = match (line.find("#@#"), line.find("##")) {
+            (Some(i), _) => (&line[..i], &line[i + 3..], true),
+            (None, Some(i)) => (&line[..i], &line[i + 2..], false),
+            (None, None) => return Ok(None),
+        };
+
+        let selector = selector.trim();
+        if selector.is_empty() {
+            return Err("missing selector".to_string());
+        }
+        if selector.starts_with('+') || selector.starts_with('^') {
+            return Err("scriptlet and HTML filtering rules are not supported".to_string());
+        }
+        if PROCEDURAL_OPERATORS.iter().any(|op| selector.contains(op)) {
+            return Err("procedural selectors are not supported".to_string());
+        }
+        // The selector goes in a style sheet of the page
+        if selector.contains(['{', '}', '<']) {
+            return Err("invalid selector".to_string());
+        }
+
+        let domains = domains
+            .split(',')
+            .map(str::trim)
+            .filter(|domain| !domain.is_empty())
+            .map(|domain| match domain.strip_prefix('~') {
+                Some(domain) => (domain.to_ascii_lowercase(), false),
+                None => (domain.to_ascii_lowercase(), true),
+            })
+            .collect::<Vec<_>>();
+
+        let rule = match exception {
+            true => Self::Exception {
+                domains: domains
+                    .into_iter()
+                    .filter(|(_, include)| *include)
+                    .map(|(domain, _)| domain)
+                    .collect(),
+                selector: selector.to_string(),
+            },
+            false => Self::Hide {
+                domains,
+                selector: selector.to_string(),
+            },
+        };
+        Ok(Some(rule))
+    }
+
+    /// `@@||example.com^$generichide` or `$elemhide`.
+    fn parse_disable(rule: &str) -> Option<Self> {
+        let (pattern, options) = rule.split_once('$')?;
+        let domain = pattern.strip_suffix('^').unwrap_or(pattern);
+        if domain.is_empty() || domain.contains(['/', '*', '^', '|']) {
+            return None;
+        }
+
+        let mut generic_only = None;
+        for option in options.split(',') {
+            match option.trim() {
+                "generichide" | "ghide" => {
+                    generic_only.get_or_insert(true);
+                }
+                "elemhide" | "ehide" => generic_only = Some(false),
+                _ => return None,
+            }
+        }
+
+        Some(Self::Disable {
+            domain: domain.to_ascii_lowercase(),
+            generic_only: generic_only?,
+        })
+    }
+}
+
+/// Whether a line is a supported cosmetic rule, `Ok(false)` when it is not a cosmetic rule.
+pub fn check_cosmetic_rule(line: &str) -> Result<bool, String> {
+    CosmeticRule::parse(line).map(|rule| rule.is_some())
+}
+
+#[derive(Debug)]
+struct HideRule {
+    selector: Box<str>,
+
+    /// Sites the rule does not apply to
+    excluded: Vec<Box<str>>,
+}
+
+impl HideRule {
+    fn applies_to(&self, host: &str) -> bool {
+        !self
+            .excluded
+            .iter()
+            .any(|domain| domain_matches(host, domain))
+    }
+}
+
+/// Compiled element hiding rules.
+#[derive(Debug, Default)]
+pub struct CosmeticFilter {
+    generic: Vec<HideRule>,
+
+    /// Generic rules by the class or id they require, see `selector_key`
+    generic_by_key: HashMap<Box<str>, Vec<u32>>,
+
+    /// Generic rules without such a key, applied to every page
+    generic_unkeyed: Vec<u32>,
+
+    by_domain: HashMap<Box<str>, Vec<HideRule>>,
+
+    generic_exceptions: HashSet<Box<str>>,
+    domain_exceptions: HashMap<Box<str>, HashSet<Box<str>>>,
+
+    /// Sites with `$generichide` and `$elemhide` exceptions
+    generic_disabled: HashSet<Box<str>>,
+    disabled: HashSet<Box<str>>,
+}
+
+impl CosmeticFilter {
+    pub fn len(&self) -> usize {
+        self.generic.len()
+            + self.by_domain.values().map(Vec::len).sum::<usize>()
+            + self.generic_exceptions.len()
+            + self
+                .domain_exceptions
+                .values()
+                .map(HashSet::len)
+                .sum::<usize>()
+            + self.generic_disabled.len()
+            + self.disabled.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn insert(&mut self, rule: CosmeticRule) {
+        match rule {
+            CosmeticRule::Hide { domains, selector } => {
+                let excluded = domains
+                    .iter()
+                    .filter(|(_, include)| !include)
+                    .map(|(domain, _)| domain.as_str().into())
+                    .collect::<Vec<_>>();
+                let included = domains
+                    .iter()
+                    .filter(|(_, include)| *include)
+                    .map(|(domain, _)| domain.as_str())
+                    .collect::<Vec<_>>();
+
+                if included.is_empty() {
+                    let id = self.generic.len() as u32;
+                    match selector_key(&selector) {
+                        Some(key) => self.generic_by_key.entry(key.into()).or_default().push(id),
+                        None => self.generic_unkeyed.push(id),
+                    }
+                    self.generic.push(HideRule {
+                        selector: selector.into(),
+                        excluded,
+                    });
+                    return;
+                }
+
+                for domain in included {
+                    self.by_domain
+                        .entry(domain.into())
+                        .or_default()
+                        .push(HideRule {
+                            selector: selector.as_str().into(),
+                            excluded: excluded.clone(),
+                        });
+                }
+            }
+            CosmeticRule::Exception { domains, selector } => {
+                if domains.is_empty() {
+                    self.generic_exceptions.insert(selector.as_str().into());
+                }
+                for domain in domains {
+                    self.domain_exceptions
+                        .entry(domain.into())
+                        .or_default()
+                        .insert(selector.as_str().into());
+                }
+            }
+            CosmeticRule::Disable {
+                domain,
+                generic_only,
+            } => {
+                match generic_only {
+                    true => self.generic_disabled.insert(domain.into()),
+                    false => self.disabled.insert(domain.into()),
+                };
+            }
+        }
+    }
+
+    /// Add the rules of a list, returning the number of rules that were skipped.
+    pub fn add_rules(&mut self, source: &str, content: &str) -> usize {
+        let mut skipped = 0;
+        for (index, line) in content.lines().enumerate() {
+            match CosmeticRule::parse(line) {
+                Ok(Some(rule)) => self.insert(rule),
+                Ok(None) => {}
+                Err(reason) => {
+                    tracing::debug!(
+                        "Skipping cosmetic rule {}:{} {:?}: {}",
+                        source,
+                        index + 1,
+                        line,
+                        reason
+                    );
+                    skipped += 1;
+                }
+            }
+        }
+        skipped
+    }
+
+    /// Selectors of the elements to hide on a page of `host`.
+    pub fn selectors(&self, host: &str, html: &str) -> Vec<&str> {
+        let host = host.to_ascii_lowercase();
+        let chain = domain_chain(&host).collect::<Vec<_>>();
+        if chain.iter().any(|domain| self.disabled.contains(*domain)) {
+            return Vec::new();
+        }
+
+        let mut selectors = Vec::new();
+        for domain in &chain {
+            if let Some(rules) = self.by_domain.get(*domain) {
+                selectors.extend(rules.iter().filter(|rule| rule.applies_to(&host)));
+            }
+        }
+
+        if !chain
+            .iter()
+            .any(|domain| self.generic_disabled.contains(*domain))
+        {
+            let keys = page_keys(html);
+            let keyed = keys
+                .iter()
+                .filter_map(|key| self.generic_by_key.get(key.as_str()))
+                .flatten();
+            selectors.extend(
+                keyed
+                    .chain(&self.generic_unkeyed)
+                    .map(|&id| &self.generic[id as usize])
+                    .filter(|rule| rule.applies_to(&host)),
+            );
+        }
+
+        let is_excepted = |selector: &str| {
+            self.generic_exceptions.contains(selector)
+                || chain.iter().any(|domain| {
+                    self.domain_exceptions
+                        .get(*domain)
+                        .is_some_and(|exceptions| exceptions.contains(selector))
+                })
+        };
+
+        let mut seen = HashSet::new();
+        selectors
+            .into_iter()
+            .map(|rule| rule.selector.as_ref())
+            .filter(|selector| !is_excepted(selector) && seen.insert(*selector))
+            .collect()
+    }
+
+    /// Style sheet hiding the elements of a page of `host`, `None` when there are none.
+    pub fn style_sheet(&self, host: &str, html: &str) -> Option<String> {
+        // One rule per selector, a selector the browser does not support only drops its own rule
+        let css = self
+            .selectors(host, html)
+            .into_iter()
+            .map(|selector| format!("{} {{ display: none !important; }}\n", selector))
+            .collect::<String>();
+
+        (!css.is_empty()).then_some(css)
+    }
+}
+
+/// The active element hiding rules, swapped by `reload_cosmetic_filter`.
+pub static COSMETIC_FILTER: LazyLock<ArcSwap<CosmeticFilter>> =
+    LazyLock::new(|| ArcSwap::from_pointee(build_cosmetic_filter()));
+
+fn build_cosmetic_filter() -> CosmeticFilter {
+    let mut filter = CosmeticFilter::default();
+
+    let mut files = vec![COSMETIC_FILTERS_PATH.clone()];
+    files.extend(
+        DOMAIN_FILTER
+            .load()
+            .subscriptions
+            .iter()
+            .map(cosmetic_rules_file),
+    );
+
+    for file in files.into_iter().filter(|file| file.exists()) {
+        match std::fs::read_to_string(&file) {
+            Ok(content) => {
+                let skipped = filter.add_rules(&file.display().to_string(), &content);
+                if skipped > 0 {
+                    tracing::info!(
+                        "Skipped {} unsupported cosmetic rules of {:?}",
+                        skipped,
+                        file
+                    );
+                }
+            }
+            Err(e) => tracing::warn!("Unable to read the cosmetic rules of {:?}: {}", file, e),
+        }
+    }
+
+    tracing::info!("Loaded {} cosmetic rules", filter.len());
+    filter
+}
+
+/// Load the element hiding rules again, after the rules file or a subscription changed.
+pub fn reload_cosmetic_filter() {
+    COSMETIC_FILTER.store(Arc::new(build_cosmetic_filter()));
+}
+
+/// Append rules missing from the rules file and load the rules again, returning the number of rules
+/// added.
+pub fn add_cosmetic_rules(rules: &[String]) -> std::io::Result<usize> {
+    let added = append_rules(&COSMETIC_FILTERS_PATH, rules)?;
+    if added > 0 {
+        reload_cosmetic_filter();
+    }
+    Ok(added)
+}
+
+pub fn get_cosmetic_filter_file() -> PathBuf {
+    COSMETIC_FILTERS_PATH.clone()
+}
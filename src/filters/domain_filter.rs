@@ -2,20 +2,34 @@
 // including blacklisting for ads, and whitelisting domains to avoid TLS interception.
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     path::PathBuf,
-    sync::{Arc, LazyLock, Mutex, RwLock},
+    sync::{Arc, LazyLock, Mutex},
 };
 
+use arc_swap::ArcSwap;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use super::journal::{self, JournalEntry, JournalOp};
+use super::matcher::{CompiledList, wildcard_matches};
+use super::metadata::{AnnotatedEntry, RuleAnnotation, RuleKey, RuleOrigin, RuleRef};
+use super::public_suffix::registrable_domain;
+use super::snapshot::{
+    ExactSet, Fingerprint, ListSections, ListSnapshot, read_snapshot, write_snapshot,
+};
+use super::subscriptions::{Subscription, SubscriptionLists, validate_subscriptions};
 use crate::config::CONFIG_PATH;
+use crate::errors::FilterError;
 
 static FILTER_PATH: LazyLock<PathBuf> = LazyLock::new(|| CONFIG_PATH.join("filter.toml"));
+/// Serializes writers, readers go through the `DOMAIN_FILTER` snapshot and never take it.
 static UPDATE_LOCK: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+/// Journaled edits after which the filter file is rewritten and the journal cleared.
+const JOURNAL_COMPACT_ENTRIES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum ListConfigType {
     Exact,
     Wildcard,
@@ -25,12 +39,51 @@ pub enum ListConfigType {
 // TOML file content
 #[derive(Debug, Default, Serialize, Deserialize)]
 struct FilterConfig {
+    #[serde(default)]
     blacklist: ListConfig,
+
+    #[serde(default)]
     whitelist: ListConfig,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    subscriptions: Vec<Subscription>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<AnnotatedEntry>,
+}
+
+/// The tables of the filter file that the compiled filter stores as TOML.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TablesConfig {
+    #[serde(default)]
+    subscriptions: Vec<Subscription>,
+
+    #[serde(default)]
+    annotations: Vec<AnnotatedEntry>,
+}
+
+/// Imports annotate their new entries up to this many, larger lists would mostly fill the
+/// filter file with annotations.
+const MAX_ANNOTATED_IMPORT_ENTRIES: usize = 10_000;
+
+/// How the exact entries of a list match.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// An entry only matches the name itself
+    #[default]
+    Exact,
+
+    /// An entry also matches its subdomains, `doubleclick.net` covers `ad.doubleclick.net`. Parents
+    /// are looked up to the registrable domain, so `co.uk` never covers `example.co.uk`.
+    Subdomains,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 struct ListConfig {
+    #[serde(default)]
+    match_mode: MatchMode,
+
     #[serde(default)]
     exact: Vec<String>,
 
@@ -41,127 +94,446 @@ struct ListConfig {
     regex: Vec<String>,
 }
 
-// Internal representation for efficient domain filtering
+/// An entry of the filter matching a domain, see [`DomainFilter::explain`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ListMatch {
+    pub is_blacklist: bool,
+    pub config_type: ListConfigType,
+
+    /// The entry as listed, a parent of the domain for exact entries of a `subdomains` list
+    pub entry: String,
+
+    /// Subscription the entry comes from, `None` for the filter file
+    pub subscription: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<RuleAnnotation>,
+}
+
+fn compile_pattern(pattern: &str) -> Result<Regex, FilterError> {
+    Regex::new(pattern).map_err(|e| FilterError::InvalidPattern {
+        pattern: pattern.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+/// The wildcard entries in a stable order, so rewriting the filter file does not shuffle them.
+fn sorted_wildcards(wildcards: &HashSet<String>) -> Vec<String> {
+    let mut wildcards = wildcards.iter().cloned().collect::<Vec<_>>();
+    wildcards.sort_unstable();
+    wildcards
+}
+
+// Internal representation for efficient domain filtering. The lists are shared between the
+// snapshots: an update clones the filter cheaply and copies only the parts it changes.
 #[derive(Clone, Debug, Default)]
 pub struct DomainFilter {
     pub file: PathBuf,
 
-    pub blacklist_exact: HashSet<String>,
-    pub whitelist_exact: HashSet<String>,
+    pub blacklist_exact: ExactSet,
+    pub whitelist_exact: ExactSet,
+
+    pub blacklist_wildcards: Arc<HashSet<String>>,
+    pub whitelist_wildcards: Arc<HashSet<String>>,
+
+    pub blacklist_regex: Arc<Vec<Regex>>,
+    pub whitelist_regex: Arc<Vec<Regex>>,
+
+    pub blacklist_mode: MatchMode,
+    pub whitelist_mode: MatchMode,
+
+    pub subscriptions: Vec<Subscription>,
 
-    pub blacklist_wildcards: HashSet<String>,
-    pub whitelist_wildcards: HashSet<String>,
+    /// Entries of the subscriptions downloaded so far, matched with the mode of their list
+    subscription_lists: Vec<SubscriptionLists>,
 
-    pub blacklist_regex: Vec<Regex>,
-    pub whitelist_regex: Vec<Regex>,
+    /// Annotations of the entries of the lists, see `metadata.rs`
+    annotations: Arc<HashMap<RuleKey, RuleAnnotation>>,
+
+    // Wildcards and regexes compiled for lookups, rebuilt by `compile` after every change
+    blacklist_matcher: Arc<CompiledList>,
+    whitelist_matcher: Arc<CompiledList>,
+
+    /// Edits in the journal that the filter file does not have yet
+    journal_entries: usize,
 }
 
 impl DomainFilter {
-    fn wildcard_matches(domain: &str, pattern: &str) -> bool {
-        let pattern = pattern.trim();
-        if pattern.is_empty() {
-            return false;
-        }
+    /// Load the filter from the TOML file. A missing file is an empty filter, invalid TOML or
+    /// regular expressions are an error.
+    pub fn load(file: Option<PathBuf>) -> Result<Self, FilterError> {
+        let file = file.unwrap_or(FILTER_PATH.clone());
+        let invalid_file = |reason: String| FilterError::InvalidFile {
+            file: file.clone(),
+            reason,
+        };
+        let content = match file.exists() {
+            true => std::fs::read_to_string(&file).map_err(|e| invalid_file(e.to_string()))?,
+            false => String::new(),
+        };
+        let config: FilterConfig =
+            toml::from_str(&content).map_err(|e| invalid_file(e.to_string()))?;
 
-        // Keep exact behavior for non-wildcard entries.
-        if !pattern.contains('*') {
-            return domain == pattern;
-        }
+        let blacklist_exact: ExactSet = config.blacklist.exact.into_iter().collect();
+        let whitelist_exact: ExactSet = config.whitelist.exact.into_iter().collect();
 
-        if pattern == "*" {
-            return true;
-        }
+        let blacklist_wildcards = Arc::new(config.blacklist.wildcard.into_iter().collect());
+        let whitelist_wildcards = Arc::new(config.whitelist.wildcard.into_iter().collect());
 
-        let starts_with_star = pattern.starts_with('*');
-        let ends_with_star = pattern.ends_with('*');
-        let parts: Vec<&str> = pattern.split('*').filter(|p| !p.is_empty()).collect();
+        let compile = |patterns: Vec<String>| {
+            patterns
+                .iter()
+                .map(|r| compile_pattern(r).map_err(|e| invalid_file(e.to_string())))
+                .collect::<Result<Vec<Regex>, FilterError>>()
+        };
+        let blacklist_regex = Arc::new(compile(config.blacklist.regex)?);
+        let whitelist_regex = Arc::new(compile(config.whitelist.regex)?);
 
-        // Pattern was only wildcards like "***".
-        if parts.is_empty() {
-            return true;
-        }
+        validate_subscriptions(&config.subscriptions).map_err(invalid_file)?;
 
-        let mut pos = 0usize;
-        for (index, part) in parts.iter().enumerate() {
-            if index == 0 && !starts_with_star {
-                if !domain[pos..].starts_with(part) {
-                    return false;
-                }
-                pos += part.len();
-                continue;
-            }
+        let mut filter = DomainFilter {
+            file,
+            blacklist_exact,
+            whitelist_exact,
+            blacklist_wildcards,
+            whitelist_wildcards,
+            blacklist_regex,
+            whitelist_regex,
+            blacklist_mode: config.blacklist.match_mode,
+            whitelist_mode: config.whitelist.match_mode,
+            ..Default::default()
+        };
+        filter.set_subscriptions(config.subscriptions);
+        filter.set_annotations(config.annotations);
+        filter.compile();
+
+        Ok(filter)
+    }
 
-            if index == parts.len() - 1 && !ends_with_star {
-                if let Some(found) = domain[pos..].rfind(part) {
-                    let abs = pos + found;
-                    return abs + part.len() == domain.len();
+    /// Open the active filter file: map its compiled form, or compile it when the file changed
+    /// since, then replay the journal of the edits made through the admin API.
+    pub fn open(file: Option<PathBuf>) -> Result<Self, FilterError> {
+        let file = file.unwrap_or(FILTER_PATH.clone());
+        let source = Fingerprint::of(&file);
+
+        let snapshot = source.and_then(|source| {
+            read_snapshot(&file.with_extension("fst"), source).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring the compiled filter of {:?}: {}", file, e);
+                None
+            })
+        });
+
+        let mut filter = match snapshot {
+            Some(snapshot) => {
+                tracing::info!("Mapped the compiled filter of {:?}", file);
+                let mut filter = DomainFilter {
+                    file,
+                    ..Default::default()
+                };
+                filter.set_list(snapshot.blacklist, true)?;
+                filter.set_list(snapshot.whitelist, false)?;
+
+                let config: TablesConfig =
+                    toml::from_str(&snapshot.tables).map_err(|e| FilterError::InvalidFile {
+                        file: filter.file.clone(),
+                        reason: e.to_string(),
+                    })?;
+                filter.set_subscriptions(config.subscriptions);
+                filter.set_annotations(config.annotations);
+                filter
+            }
+            None => {
+                let mut filter = Self::load(Some(file))?;
+                if let Some(source) = source {
+                    tracing::info!("Compiling the filter file {:?}", filter.file);
+                    if let Err(e) = filter.write_compiled(source) {
+                        tracing::warn!("Unable to write the compiled filter: {}", e);
+                    }
                 }
-                return false;
+                filter
             }
+        };
 
-            if let Some(found) = domain[pos..].find(part) {
-                pos += found + part.len();
-            } else {
-                return false;
+        let entries =
+            journal::read(&filter.journal_path()).map_err(|e| FilterError::InvalidFile {
+                file: filter.journal_path(),
+                reason: e.to_string(),
+            })?;
+        for entry in &entries {
+            if let Err(e) = filter.apply(entry) {
+                tracing::warn!("Skipping filter journal entry {:?}: {}", entry, e);
             }
         }
+        filter.journal_entries = entries.len();
+        filter.compile();
 
-        true
+        Ok(filter)
     }
 
-    pub fn load(file: Option<PathBuf>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let file = file.unwrap_or(FILTER_PATH.clone());
-        let content = std::fs::read_to_string(&file).unwrap_or_default();
-        let config: FilterConfig = toml::from_str(&content).unwrap_or_default();
+    fn set_list(&mut self, list: ListSnapshot, is_blacklisted: bool) -> Result<(), FilterError> {
+        let regex = list
+            .regex
+            .iter()
+            .map(|pattern| compile_pattern(pattern))
+            .collect::<Result<Vec<Regex>, FilterError>>()?;
+        let regex = Arc::new(regex);
+        let wildcards = Arc::new(list.wildcards.into_iter().collect());
 
-        let blacklist_exact: HashSet<String> = config.blacklist.exact.into_iter().collect();
-        let whitelist_exact: HashSet<String> = config.whitelist.exact.into_iter().collect();
+        match is_blacklisted {
+            true => {
+                self.blacklist_mode = list.mode;
+                self.blacklist_exact = list.exact;
+                self.blacklist_wildcards = wildcards;
+                self.blacklist_regex = regex;
+            }
+            false => {
+                self.whitelist_mode = list.mode;
+                self.whitelist_exact = list.exact;
+                self.whitelist_wildcards = wildcards;
+                self.whitelist_regex = regex;
+            }
+        }
 
-        let blacklist_wildcards: HashSet<String> = config.blacklist.wildcard.into_iter().collect();
-        let whitelist_wildcards: HashSet<String> = config.whitelist.wildcard.into_iter().collect();
+        Ok(())
+    }
 
-        let blacklist_regex: Vec<Regex> = config
-            .blacklist
-            .regex
-            .into_iter()
-            .filter_map(|r| Regex::new(&r).ok())
-            .collect();
-        let whitelist_regex: Vec<Regex> = config
-            .whitelist
-            .regex
-            .into_iter()
-            .filter_map(|r| Regex::new(&r).ok())
+    /// Use these subscriptions, with the entries they have downloaded so far.
+    fn set_subscriptions(&mut self, subscriptions: Vec<Subscription>) {
+        self.subscription_lists = subscriptions
+            .iter()
+            .filter_map(SubscriptionLists::load)
             .collect();
+        self.subscriptions = subscriptions;
+    }
 
-        Ok(DomainFilter {
-            file,
-            blacklist_exact,
-            whitelist_exact,
-            blacklist_wildcards,
-            whitelist_wildcards,
-            blacklist_regex,
-            whitelist_regex,
-        })
+    /// Use these annotations, dropping those of entries missing from the lists.
+    fn set_annotations(&mut self, annotations: Vec<AnnotatedEntry>) {
+        self.annotations = Arc::new(
+            annotations
+                .into_iter()
+                .filter(|annotated| {
+                    annotated.key.subscription.is_none() && self.contains_rule(&annotated.key)
+                })
+                .map(|annotated| (annotated.key, annotated.annotation))
+                .collect(),
+        );
     }
 
-    /// Dump the current configuration to the TOML file, acquiring the update lock.
-    fn dump_file(
-        &self,
-        save_backup: Option<bool>,
+    fn annotated_entries(&self) -> Vec<AnnotatedEntry> {
+        let mut entries = self
+            .annotations
+            .iter()
+            .map(|(key, annotation)| AnnotatedEntry {
+                key: key.clone(),
+                annotation: annotation.clone(),
+            })
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by(|a, b| {
+            (!a.key.is_blacklist, &a.key.entry).cmp(&(!b.key.is_blacklist, &b.key.entry))
+        });
+        entries
+    }
+
+    /// The annotation of an entry. Entries of subscriptions are not annotated one by one, they
+    /// only tell their origin.
+    pub fn annotation(&self, key: &RuleKey) -> Option<RuleAnnotation> {
+        match &key.subscription {
+            Some(name) => Some(RuleAnnotation {
+                origin: Some(RuleOrigin::Subscription(name.clone())),
+                ..Default::default()
+            }),
+            None => self.annotations.get(key).cloned(),
+        }
+    }
+
+    /// Entries whose expiry date has passed.
+    pub fn expired_rules(&self, now: u64) -> Vec<RuleKey> {
+        self.annotations
+            .iter()
+            .filter(|(_, annotation)| annotation.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Whether the entry is in its list, or in the lists of its subscription.
+    pub fn contains_rule(&self, key: &RuleKey) -> bool {
+        if let Some(name) = &key.subscription {
+            return self.subscription_lists.iter().any(|lists| {
+                let exact = match key.is_blacklist {
+                    true => &lists.blacklist,
+                    false => &lists.whitelist,
+                };
+                &lists.name == name && exact.contains(&key.entry)
+            });
+        }
+
+        match (key.config_type, key.is_blacklist) {
+            (ListConfigType::Exact, true) => self.blacklist_exact.contains(&key.entry),
+            (ListConfigType::Exact, false) => self.whitelist_exact.contains(&key.entry),
+            (ListConfigType::Wildcard, true) => self.blacklist_wildcards.contains(&key.entry),
+            (ListConfigType::Wildcard, false) => self.whitelist_wildcards.contains(&key.entry),
+            (ListConfigType::Regex, true) => self
+                .blacklist_regex
+                .iter()
+                .any(|re| re.as_str() == key.entry),
+            (ListConfigType::Regex, false) => self
+                .whitelist_regex
+                .iter()
+                .any(|re| re.as_str() == key.entry),
+        }
+    }
+
+    /// Install the entries of a refreshed subscription. They are dropped when the subscription was
+    /// removed during the refresh.
+    pub fn set_subscription_lists(&mut self, lists: SubscriptionLists) {
+        if !self.subscriptions.iter().any(|s| s.name == lists.name) {
+            return;
+        }
+
+        self.subscription_lists.retain(|l| l.name != lists.name);
+        self.subscription_lists.push(lists);
+    }
+
+    pub fn add_subscription(&mut self, subscription: Subscription) -> Result<(), FilterError> {
+        subscription
+            .validate()
+            .map_err(FilterError::InvalidSubscription)?;
+        if self
+            .subscriptions
+            .iter()
+            .any(|s| s.name == subscription.name)
+        {
+            return Err(FilterError::InvalidSubscription(format!(
+                "Subscription '{}' already exists",
+                subscription.name
+            )));
+        }
+
+        self.subscription_lists
+            .extend(SubscriptionLists::load(&subscription));
+        self.subscriptions.push(subscription);
+        Ok(())
+    }
+
+    /// Remove a subscription and its entries, returning it.
+    pub fn remove_subscription(&mut self, name: &str) -> Result<Subscription, FilterError> {
+        let index = self
+            .subscriptions
+            .iter()
+            .position(|s| s.name == name)
+            .ok_or_else(|| FilterError::SubscriptionNotFound(name.to_string()))?;
+
+        self.subscription_lists.retain(|l| l.name != name);
+        Ok(self.subscriptions.remove(index))
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        self.file.with_extension("journal")
+    }
+
+    /// Write the compiled form of the filter next to its file, then look the exact entries up in
+    /// the mapped file rather than in memory.
+    fn write_compiled(
+        &mut self,
+        source: Fingerprint,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let _update_guard = UPDATE_LOCK.lock().unwrap();
-        self.dump_file_unsafe(save_backup.unwrap_or(false))
+        fn sections<'a>(
+            mode: MatchMode,
+            exact: &'a ExactSet,
+            wildcards: &'a HashSet<String>,
+            regex: &'a [Regex],
+        ) -> ListSections<'a> {
+            let mut wildcards = wildcards.iter().map(String::as_str).collect::<Vec<_>>();
+            wildcards.sort_unstable();
+            ListSections {
+                mode,
+                exact,
+                wildcards,
+                regex: regex.iter().map(Regex::as_str).collect(),
+            }
+        }
+
+        let tables = toml::to_string(&TablesConfig {
+            subscriptions: self.subscriptions.clone(),
+            annotations: self.annotated_entries(),
+        })?;
+
+        let path = self.file.with_extension("fst");
+        write_snapshot(
+            &path,
+            source,
+            sections(
+                self.blacklist_mode,
+                &self.blacklist_exact,
+                &self.blacklist_wildcards,
+                &self.blacklist_regex,
+            ),
+            sections(
+                self.whitelist_mode,
+                &self.whitelist_exact,
+                &self.whitelist_wildcards,
+                &self.whitelist_regex,
+            ),
+            &tables,
+        )?;
+
+        let snapshot = read_snapshot(&path, source)?.ok_or("compiled filter is stale")?;
+        self.blacklist_exact = snapshot.blacklist.exact;
+        self.whitelist_exact = snapshot.whitelist.exact;
+        Ok(())
+    }
+
+    /// Rebuild the lookup structures from the wildcard and regex lists.
+    fn compile(&mut self) {
+        self.compile_list(true);
+        self.compile_list(false);
+    }
+
+    /// Rebuild the lookup structure of one list, the other keeps sharing its own.
+    fn compile_list(&mut self, is_blacklist: bool) {
+        match is_blacklist {
+            true => {
+                self.blacklist_matcher = Arc::new(CompiledList::build(
+                    self.blacklist_wildcards.iter(),
+                    &self.blacklist_regex,
+                ))
+            }
+            false => {
+                self.whitelist_matcher = Arc::new(CompiledList::build(
+                    self.whitelist_wildcards.iter(),
+                    &self.whitelist_regex,
+                ))
+            }
+        }
     }
 
-    /// Dump the current configuration to the TOML file without acquiring the update lock.
-    fn dump_file_unsafe(
+    /// Dump the filter to the TOML file and compile it, which makes the journal redundant.
+    /// Callers hold the update lock.
+    fn dump_file(&mut self, save_backup: bool) -> Result<(), FilterError> {
+        self.write_file(save_backup)
+            .map_err(|e| FilterError::Persist(e.to_string()))?;
+
+        if let Some(source) = Fingerprint::of(&self.file)
+            && let Err(e) = self.write_compiled(source)
+        {
+            // The filter file is up to date, the next start compiles it again
+            tracing::warn!("Unable to write the compiled filter: {}", e);
+        }
+
+        journal::clear(&self.journal_path()).map_err(|e| FilterError::Persist(e.to_string()))?;
+        self.journal_entries = 0;
+        Ok(())
+    }
+
+    fn write_file(
         &self,
         save_backup: bool,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let filter_config = FilterConfig {
             blacklist: ListConfig {
-                exact: self.blacklist_exact.iter().cloned().collect(),
-                wildcard: self.blacklist_wildcards.iter().cloned().collect(),
+                match_mode: self.blacklist_mode,
+                exact: self.blacklist_exact.to_sorted_vec(),
+                wildcard: sorted_wildcards(&self.blacklist_wildcards),
                 regex: self
                     .blacklist_regex
                     .iter()
@@ -169,14 +541,17 @@ impl DomainFilter {
                     .collect(),
             },
             whitelist: ListConfig {
-                exact: self.whitelist_exact.iter().cloned().collect(),
-                wildcard: self.whitelist_wildcards.iter().cloned().collect(),
+                match_mode: self.whitelist_mode,
+                exact: self.whitelist_exact.to_sorted_vec(),
+                wildcard: sorted_wildcards(&self.whitelist_wildcards),
                 regex: self
                     .whitelist_regex
                     .iter()
                     .map(|re| re.as_str().to_string())
                     .collect(),
             },
+            subscriptions: self.subscriptions.clone(),
+            annotations: self.annotated_entries(),
         };
 
         let toml_str = toml::to_string(&filter_config)?;
@@ -185,7 +560,7 @@ impl DomainFilter {
         }
 
         // Save backup before overwriting if needed
-        if save_backup {
+        if save_backup && self.file.exists() {
             let backup_path = self.file.with_extension("toml.backup");
             std::fs::copy(&self.file, &backup_path)?;
         }
@@ -198,59 +573,133 @@ impl DomainFilter {
         Ok(())
     }
 
-    /// Merge another DomainFilter into this one internally.
-    fn merge_internal(&mut self, other: &DomainFilter) {
-        self.blacklist_exact
-            .extend(other.blacklist_exact.iter().cloned());
-        self.whitelist_exact
-            .extend(other.whitelist_exact.iter().cloned());
+    /// Merge another DomainFilter into this one.
+    pub fn merge(&mut self, other: &DomainFilter) {
+        self.blacklist_exact.extend(&other.blacklist_exact);
+        self.whitelist_exact.extend(&other.whitelist_exact);
 
-        self.blacklist_wildcards
+        Arc::make_mut(&mut self.blacklist_wildcards)
             .extend(other.blacklist_wildcards.iter().cloned());
-        self.whitelist_wildcards
+        Arc::make_mut(&mut self.whitelist_wildcards)
             .extend(other.whitelist_wildcards.iter().cloned());
 
-        self.blacklist_regex
-            .extend(other.blacklist_regex.iter().cloned());
-        self.whitelist_regex
-            .extend(other.whitelist_regex.iter().cloned());
-    }
+        Arc::make_mut(&mut self.blacklist_regex).extend(other.blacklist_regex.iter().cloned());
+        Arc::make_mut(&mut self.whitelist_regex).extend(other.whitelist_regex.iter().cloned());
 
-    /// Replace the current filter with another one internally.
-    fn replace_internal(&mut self, other: &Self) {
-        *self = other.clone();
+        let annotations = Arc::make_mut(&mut self.annotations);
+        for (key, annotation) in other.annotations.iter() {
+            annotations
+                .entry(key.clone())
+                .or_insert_with(|| annotation.clone());
+        }
+
+        for subscription in &other.subscriptions {
+            if !self
+                .subscriptions
+                .iter()
+                .any(|s| s.name == subscription.name)
+            {
+                // Validated when the other filter was loaded
+                let _ = self.add_subscription(subscription.clone());
+            }
+        }
     }
 
-    /// Merge another DomainFilter into this one and dump the updated configuration to the TOML file.
-    pub fn merge(
+    /// Add imported domains to the exact lists, or with `replace` use them instead of the exact
+    /// entries of the lists they are for. Returns the number of new blacklist and whitelist
+    /// entries.
+    pub fn import_exact(
         &mut self,
-        other: &DomainFilter,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let _update_guard = UPDATE_LOCK.lock().unwrap();
-        self.merge_internal(other);
-        self.dump_file_unsafe(true)
+        blacklist: &[String],
+        whitelist: &[String],
+        replace: bool,
+    ) -> (usize, usize) {
+        fn import<'a>(exact: &mut ExactSet, domains: &'a [String], replace: bool) -> Vec<&'a str> {
+            if replace && !domains.is_empty() {
+                *exact = ExactSet::default();
+            }
+
+            let mut added = Vec::new();
+            for domain in domains {
+                if !exact.contains(domain) {
+                    exact.insert(domain.clone());
+                    added.push(domain.as_str());
+                }
+            }
+            added
+        }
+
+        let blacklist_added = import(&mut self.blacklist_exact, blacklist, replace);
+        let whitelist_added = import(&mut self.whitelist_exact, whitelist, replace);
+
+        if replace {
+            let (blacklist_exact, whitelist_exact) = (&self.blacklist_exact, &self.whitelist_exact);
+            Arc::make_mut(&mut self.annotations).retain(|key, _| {
+                match (key.config_type, key.is_blacklist) {
+                    (ListConfigType::Exact, true) => blacklist_exact.contains(&key.entry),
+                    (ListConfigType::Exact, false) => whitelist_exact.contains(&key.entry),
+                    _ => true,
+                }
+            });
+        }
+
+        let total = blacklist_added.len() + whitelist_added.len();
+        if total <= MAX_ANNOTATED_IMPORT_ENTRIES {
+            let annotation = RuleAnnotation::new(RuleOrigin::Import);
+            let annotations = Arc::make_mut(&mut self.annotations);
+            for (is_blacklist, added) in [(true, &blacklist_added), (false, &whitelist_added)] {
+                for domain in added {
+                    annotations.insert(
+                        RuleKey::new(is_blacklist, ListConfigType::Exact, domain),
+                        annotation.clone(),
+                    );
+                }
+            }
+        } else {
+            tracing::info!(
+                "Not annotating the {} imported entries, imports are annotated up to {} entries",
+                total,
+                MAX_ANNOTATED_IMPORT_ENTRIES
+            );
+        }
+
+        (blacklist_added.len(), whitelist_added.len())
     }
 
-    /// Replace the current filter with another one and persist to disk.
-    pub fn replace(
-        &mut self,
-        other: &Self,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let _update_guard = UPDATE_LOCK.lock().unwrap();
-        self.replace_internal(other);
-        self.dump_file_unsafe(true)
+    /// Replace the lists with the ones of another filter, keeping the file.
+    pub fn replace(&mut self, other: &Self) {
+        *self = Self {
+            file: self.file.clone(),
+            ..other.clone()
+        };
     }
 
-    /// Add a domain to the specified list and dump the updated configuration to the TOML file.
-    /// This operation is not meant to be used frequently, because the most common operation is read,
-    /// then, this function is not optimized for perfomance right now, but it's a good idea to implement
-    /// a more efficient way to handle frequent updates in the future.
+    /// Apply an edit of the journal.
+    fn apply(&mut self, entry: &JournalEntry) -> Result<(), FilterError> {
+        match entry.op {
+            JournalOp::Add => {
+                self.add_domain(&entry.value, entry.list_type, entry.is_blacklisted)?;
+                if let Some(annotation) = &entry.annotation {
+                    Arc::make_mut(&mut self.annotations).insert(
+                        RuleKey::new(entry.is_blacklisted, entry.list_type, &entry.value),
+                        annotation.clone(),
+                    );
+                }
+                Ok(())
+            }
+            JournalOp::Remove => {
+                self.remove_domain(&entry.value, entry.list_type, entry.is_blacklisted)
+            }
+        }
+    }
+
+    /// Add a domain to the specified list.
     pub fn add_domain(
         &mut self,
         domain: &str,
         list_type: ListConfigType,
         is_blacklisted: bool,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<(), FilterError> {
         match (list_type, is_blacklisted) {
             (ListConfigType::Exact, true) => {
                 self.blacklist_exact.insert(domain.to_string());
@@ -259,24 +708,25 @@ impl DomainFilter {
                 self.whitelist_exact.insert(domain.to_string());
             }
             (ListConfigType::Wildcard, true) => {
-                self.blacklist_wildcards.insert(domain.to_string());
+                Arc::make_mut(&mut self.blacklist_wildcards).insert(domain.to_string());
             }
             (ListConfigType::Wildcard, false) => {
-                self.whitelist_wildcards.insert(domain.to_string());
+                Arc::make_mut(&mut self.whitelist_wildcards).insert(domain.to_string());
             }
-            (ListConfigType::Regex, true) => {
-                if let Ok(re) = Regex::new(domain) {
-                    self.blacklist_regex.push(re);
-                }
-            }
-            (ListConfigType::Regex, false) => {
-                if let Ok(re) = Regex::new(domain) {
-                    self.whitelist_regex.push(re);
+            (ListConfigType::Regex, is_blacklisted) => {
+                let regex = compile_pattern(domain)?;
+                let list = match is_blacklisted {
+                    true => &mut self.blacklist_regex,
+                    false => &mut self.whitelist_regex,
+                };
+                // Replaying the journal over a file that already has the edit adds nothing
+                if !list.iter().any(|re| re.as_str() == domain) {
+                    Arc::make_mut(list).push(regex);
                 }
             }
         }
 
-        self.dump_file(None)
+        Ok(())
     }
 
     pub fn remove_domain(
@@ -284,7 +734,7 @@ impl DomainFilter {
         domain: &str,
         list_type: ListConfigType,
         is_blacklisted: bool,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<(), FilterError> {
         match (list_type, is_blacklisted) {
             (ListConfigType::Exact, true) => {
                 self.blacklist_exact.remove(domain);
@@ -293,68 +743,228 @@ impl DomainFilter {
                 self.whitelist_exact.remove(domain);
             }
             (ListConfigType::Wildcard, true) => {
-                self.blacklist_wildcards.retain(|d| d != domain);
+                Arc::make_mut(&mut self.blacklist_wildcards).remove(domain);
             }
             (ListConfigType::Wildcard, false) => {
-                self.whitelist_wildcards.retain(|d| d != domain);
+                Arc::make_mut(&mut self.whitelist_wildcards).remove(domain);
             }
             (ListConfigType::Regex, true) => {
-                self.blacklist_regex.retain(|re| re.as_str() != domain);
+                Arc::make_mut(&mut self.blacklist_regex).retain(|re| re.as_str() != domain);
             }
             (ListConfigType::Regex, false) => {
-                self.whitelist_regex.retain(|re| re.as_str() != domain);
+                Arc::make_mut(&mut self.whitelist_regex).retain(|re| re.as_str() != domain);
             }
         }
 
-        // Dump to TOML file
-        self.dump_file(None)
+        let key = RuleKey::new(is_blacklisted, list_type, domain);
+        if self.annotations.contains_key(&key) {
+            Arc::make_mut(&mut self.annotations).remove(&key);
+        }
+
+        Ok(())
     }
 
-    pub fn is_listed(&self, domain: &str, is_blacklisted: bool) -> bool {
-        match is_blacklisted {
-            true => {
-                if self.blacklist_exact.contains(domain) {
-                    return true;
-                }
+    /// The first entry of a list matching the domain, borrowed so a lookup does not allocate.
+    pub fn is_listed<'a>(&'a self, domain: &'a str, is_blacklist: bool) -> Option<RuleRef<'a>> {
+        let (exact, mode, matcher) = match is_blacklist {
+            true => (
+                &self.blacklist_exact,
+                self.blacklist_mode,
+                &self.blacklist_matcher,
+            ),
+            false => (
+                &self.whitelist_exact,
+                self.whitelist_mode,
+                &self.whitelist_matcher,
+            ),
+        };
 
-                if self
-                    .blacklist_wildcards
-                    .iter()
-                    .any(|wc| Self::wildcard_matches(domain, wc))
-                {
-                    return true;
-                }
+        if let Some(entry) = Self::exact_match(exact, mode, domain) {
+            return Some(RuleRef::new(is_blacklist, ListConfigType::Exact, entry));
+        }
+        if let Some((config_type, entry)) = matcher.find(domain) {
+            return Some(RuleRef::new(is_blacklist, config_type, entry));
+        }
+
+        self.subscription_lists.iter().find_map(|lists| {
+            let exact = match is_blacklist {
+                true => &lists.blacklist,
+                false => &lists.whitelist,
+            };
+            Some(RuleRef {
+                subscription: Some(&lists.name),
+                ..RuleRef::new(
+                    is_blacklist,
+                    ListConfigType::Exact,
+                    Self::exact_match(exact, mode, domain)?,
+                )
+            })
+        })
+    }
 
-                if self.blacklist_regex.iter().any(|re| re.is_match(domain)) {
-                    return true;
+    /// Every entry of both lists matching the domain, whitelist first. Slower than `is_listed`,
+    /// which stops at the first match.
+    pub fn explain(&self, domain: &str) -> Vec<ListMatch> {
+        let mut matches = Vec::new();
+
+        for is_blacklist in [false, true] {
+            let (exact, mode, wildcards, regexes) = match is_blacklist {
+                true => (
+                    &self.blacklist_exact,
+                    self.blacklist_mode,
+                    &self.blacklist_wildcards,
+                    &self.blacklist_regex,
+                ),
+                false => (
+                    &self.whitelist_exact,
+                    self.whitelist_mode,
+                    &self.whitelist_wildcards,
+                    &self.whitelist_regex,
+                ),
+            };
+            let entry = |config_type, entry: &str, subscription: Option<&str>| {
+                let key = RuleKey {
+                    subscription: subscription.map(str::to_string),
+                    ..RuleKey::new(is_blacklist, config_type, entry)
+                };
+                ListMatch {
+                    is_blacklist,
+                    config_type,
+                    entry: entry.to_string(),
+                    annotation: self.annotation(&key),
+                    subscription: key.subscription,
                 }
+            };
+
+            if let Some(name) = Self::exact_match(exact, mode, domain) {
+                matches.push(entry(ListConfigType::Exact, name, None));
             }
-            false => {
-                if self.whitelist_exact.contains(domain) {
-                    return true;
+            for lists in &self.subscription_lists {
+                let exact = match is_blacklist {
+                    true => &lists.blacklist,
+                    false => &lists.whitelist,
+                };
+                if let Some(name) = Self::exact_match(exact, mode, domain) {
+                    matches.push(entry(ListConfigType::Exact, name, Some(&lists.name)));
                 }
+            }
 
-                if self
-                    .whitelist_wildcards
-                    .iter()
-                    .any(|wc| Self::wildcard_matches(domain, wc))
-                {
-                    return true;
-                }
+            let mut matching_wildcards = wildcards
+                .iter()
+                .filter(|pattern| wildcard_matches(pattern, domain))
+                .collect::<Vec<_>>();
+            matching_wildcards.sort_unstable();
+            for pattern in matching_wildcards {
+                matches.push(entry(ListConfigType::Wildcard, pattern, None));
+            }
+            for re in regexes.iter().filter(|re| re.is_match(domain)) {
+                matches.push(entry(ListConfigType::Regex, re.as_str(), None));
+            }
+        }
 
-                if self.whitelist_regex.iter().any(|re| re.is_match(domain)) {
-                    return true;
-                }
+        matches
+    }
+
+    /// The blacklist entry blocking the domain, unless it is whitelisted: a whitelist entry wins
+    /// whatever the level of the blacklist entry that matched.
+    pub fn is_blocked<'a>(&'a self, domain: &'a str) -> Option<RuleRef<'a>> {
+        match self.is_listed(domain, false) {
+            Some(_) => None,
+            None => self.is_listed(domain, true),
+        }
+    }
+
+    /// The exact entry matching the domain, the domain itself or one of its parents.
+    fn exact_match<'a>(exact: &ExactSet, mode: MatchMode, domain: &'a str) -> Option<&'a str> {
+        if exact.contains(domain) {
+            return Some(domain);
+        }
+        if mode == MatchMode::Exact {
+            return None;
+        }
+
+        let registrable = registrable_domain(domain)?;
+
+        // ad.tracker.example.co.uk -> tracker.example.co.uk -> example.co.uk
+        let mut rest = domain;
+        while rest.len() > registrable.len() {
+            let Some((_, parent)) = rest.split_once('.') else {
+                break;
+            };
+            if exact.contains(parent) {
+                return Some(parent);
             }
+            rest = parent;
         }
 
-        false
+        None
     }
 }
 
-pub static DOMAIN_FILTER: LazyLock<Arc<RwLock<DomainFilter>>> = LazyLock::new(|| {
-    let filter = DomainFilter::load(None).unwrap_or_else(|e| {
+/// The active filter. Lookups load the current snapshot without locking, updates build a new
+/// filter and swap it in.
+pub static DOMAIN_FILTER: LazyLock<ArcSwap<DomainFilter>> = LazyLock::new(|| {
+    let filter = DomainFilter::open(None).unwrap_or_else(|e| {
         panic!("Failed to load domain filter configuration: {}", e);
     });
-    Arc::new(RwLock::new(filter))
+    ArcSwap::from_pointee(filter)
 });
+
+/// Apply a change to a copy of the active filter, write it to the TOML file and swap it in.
+/// Lookups keep using the previous snapshot meanwhile, and it stays active if the write fails.
+pub fn update_domain_filter<F>(save_backup: bool, update: F) -> Result<(), FilterError>
+where
+    F: FnOnce(&mut DomainFilter) -> Result<(), FilterError>,
+{
+    let _update_guard = UPDATE_LOCK.lock().unwrap();
+    let mut filter = DomainFilter::clone(&DOMAIN_FILTER.load());
+    update(&mut filter)?;
+    filter.compile();
+    filter.dump_file(save_backup)?;
+    DOMAIN_FILTER.store(Arc::new(filter));
+    Ok(())
+}
+
+/// Apply an add or remove to a copy of the active filter, record it in the journal and swap it
+/// in. Only the edited list is copied and compiled again. The filter file is only rewritten once
+/// the journal holds `JOURNAL_COMPACT_ENTRIES` edits.
+pub fn edit_domain_filter(entry: JournalEntry) -> Result<(), FilterError> {
+    let _update_guard = UPDATE_LOCK.lock().unwrap();
+    let mut filter = DomainFilter::clone(&DOMAIN_FILTER.load());
+    filter.apply(&entry)?;
+    if !matches!(entry.list_type, ListConfigType::Exact) {
+        filter.compile_list(entry.is_blacklisted);
+    }
+
+    journal::append(&filter.journal_path(), &entry)
+        .map_err(|e| FilterError::Persist(e.to_string()))?;
+    filter.journal_entries += 1;
+
+    if filter.journal_entries >= JOURNAL_COMPACT_ENTRIES {
+        // The edit is safe in the journal, a failed compaction is retried on the next edit
+        match filter.dump_file(false) {
+            Ok(()) => tracing::info!("Compacted the filter journal into {:?}", filter.file),
+            Err(e) => tracing::warn!("Unable to compact the filter journal: {}", e),
+        }
+    }
+
+    DOMAIN_FILTER.store(Arc::new(filter));
+    Ok(())
+}
+
+/// Open the filter file again and swap it in, without writing it back.
+pub fn reload_domain_filter(file: PathBuf) -> Result<(), FilterError> {
+    let _update_guard = UPDATE_LOCK.lock().unwrap();
+    let filter = DomainFilter::open(Some(file))?;
+    DOMAIN_FILTER.store(Arc::new(filter));
+    Ok(())
+}
+
+/// Swap in the entries of a refreshed subscription. They live in their own files, the filter
+/// file does not change.
+pub fn install_subscription_lists(lists: SubscriptionLists) {
+    let _update_guard = UPDATE_LOCK.lock().unwrap();
+    let mut filter = DomainFilter::clone(&DOMAIN_FILTER.load());
+    filter.set_subscription_lists(lists);
+    DOMAIN_FILTER.store(Arc::new(filter));
+}
@@ -0,0 +1,449 @@
+// Blocklist importer. Parses the list formats published by blocklist maintainers into domain
+// names for the exact lists of the filter:
+//
+// - hosts files: `0.0.0.0 ads.example.com`
+// - plain domain lists: `ads.example.com`
+// - dnsmasq: `address=/ads.example.com/0.0.0.0`, `server=/ads.example.com/`
+// - unbound: `local-zone: "ads.example.com" always_nxdomain`, `local-data: "ads.example.com A 0.0.0.0"`
+// - the domain-level subset of AdBlock Plus: `||ads.example.com^`, `@@||ads.example.com^`
+//
+// Other AdBlock network rules (`/banner/*$image`, `||example.com/ads/*`) cannot go in the domain
+// lists, they are collected for the network filter instead, and element hiding rules
+// (`example.com##.ad`) for the cosmetic filter.
+//
+// With the `auto` format the syntax is recognised line by line, so a directory mixing several
+// lists can be imported at once.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use super::cosmetic::check_cosmetic_rule;
+use super::network::check_network_rule;
+use crate::utils::authority::normalize_hostname;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListFormat {
+    #[default]
+    Auto,
+    Hosts,
+    Plain,
+    Dnsmasq,
+    Unbound,
+    Adblock,
+}
+
+/// Host names every hosts file maps to the loopback, they are not ads.
+const LOCAL_NAMES: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "ip6-allhosts",
+    "0.0.0.0",
+];
+
+/// Addresses blocklists resolve blocked names to.
+const SINKHOLE_ADDRESSES: &[&str] = &["0.0.0.0", "127.0.0.1", "::", "::1"];
+
+/// Unbound zone types that answer without resolving the name.
+const UNBOUND_BLOCKING_ZONES: &[&str] = &[
+    "static",
+    "refuse",
+    "deny",
+    "redirect",
+    "inform_deny",
+    "always_refuse",
+    "always_nxdomain",
+    "always_null",
+];
+
+enum Rule {
+    Block(String),
+    Allow(String),
+    Network(String),
+    Cosmetic(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InvalidLine {
+    pub source: String,
+    pub line: usize,
+    pub content: String,
+    pub reason: String,
+}
+
+/// Domains collected from one or more lists, de-duplicated and sorted.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub lines: usize,
+    pub blacklist: Vec<String>,
+    pub whitelist: Vec<String>,
+    pub duplicates: usize,
+    pub invalid: Vec<InvalidLine>,
+
+    /// Entries from AdBlock, dnsmasq and unbound rules, which also cover the subdomains
+    pub subdomain_rules: usize,
+
+    /// AdBlock rules matching more than a domain, in the order of the lists
+    pub network_rules: Vec<String>,
+
+    /// AdBlock element hiding rules, in the order of the lists
+    pub cosmetic_rules: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Importer {
+    format: ListFormat,
+    lines: usize,
+    blacklist: HashSet<String>,
+    whitelist: HashSet<String>,
+    duplicates: usize,
+    invalid: Vec<InvalidLine>,
+    subdomain_rules: usize,
+    network_rules: Vec<String>,
+    cosmetic_rules: Vec<String>,
+    seen_rules: HashSet<String>,
+}
+
+impl Importer {
+    pub fn new(format: ListFormat) -> Self {
+        Self {
+            format,
+            ..Default::default()
+        }
+    }
+
+    /// Parse the content of a list, `source` names it in the invalid lines.
+    pub fn parse(&mut self, source: &str, content: &str) {
+        for (index, line) in content.lines().enumerate() {
+            self.lines += 1;
+
+            let (format, rules) = match parse_line(line.trim(), self.format) {
+                Ok(parsed) => parsed,
+                Err(reason) => {
+                    self.invalid.push(InvalidLine {
+                        source: source.to_string(),
+                        line: index + 1,
+                        content: line.to_string(),
+                        reason,
+                    });
+                    continue;
+                }
+            };
+
+            for rule in rules {
+                if matches!(
+                    format,
+                    ListFormat::Adblock | ListFormat::Dnsmasq | ListFormat::Unbound
+                ) && matches!(rule, Rule::Block(_) | Rule::Allow(_))
+                {
+                    self.subdomain_rules += 1;
+                }
+
+                let is_new = match rule {
+                    Rule::Block(domain) => self.blacklist.insert(domain),
+                    Rule::Allow(domain) => self.whitelist.insert(domain),
+                    Rule::Network(rule) => {
+                        let is_new = self.seen_rules.insert(rule.clone());
+                        if is_new {
+                            self.network_rules.push(rule);
+                        }
+                        is_new
+                    }
+                    Rule::Cosmetic(rule) => {
+                        let is_new = self.seen_rules.insert(rule.clone());
+                        if is_new {
+                            self.cosmetic_rules.push(rule);
+                        }
+                        is_new
+                    }
+                };
+                if !is_new {
+                    self.duplicates += 1;
+                }
+            }
+        }
+    }
+
+    pub fn finish(self) -> ImportReport {
+        let sorted = |domains: HashSet<String>| {
+            let mut domains = domains.into_iter().collect::<Vec<_>>();
+            domains.sort_unstable();
+            domains
+        };
+
+        ImportReport {
+            lines: self.lines,
+            blacklist: sorted(self.blacklist),
+            whitelist: sorted(self.whitelist),
+            duplicates: self.duplicates,
+            invalid: self.invalid,
+            subdomain_rules: self.subdomain_rules,
+            network_rules: self.network_rules,
+            cosmetic_rules: self.cosmetic_rules,
+        }
+    }
+}
+
+/// Parse a list in one go.
+pub fn parse_list(source: &str, content: &str, format: ListFormat) -> ImportReport {
+    let mut importer = Importer::new(format);
+    importer.parse(source, content);
+    importer.finish()
+}
+
+fn detect_format(line: &str) -> ListFormat {
+    let first = line.split_whitespace().next().unwrap_or_default();
+
+    // Element hiding (`example.com##.ad`) and URL rules (`/banner/*`, `ads.js^$script`) are
+    // AdBlock rules too
+    let is_adblock = ["||", "@@", "!", "/"]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+        || ["##", "#@#", "#?#", "#$#", "^", "$"]
+            .iter()
+            .any(|marker| line.contains(marker));
+
+    if is_adblock {
+        ListFormat::Adblock
+    } else if ["address=", "server=", "local="]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+    {
+        ListFormat::Dnsmasq
+    } else if ["local-zone:", "local-data:", "server:"].contains(&first) {
+        ListFormat::Unbound
+    } else if first.parse::<IpAddr>().is_ok() || glued_address(first).is_some() {
+        ListFormat::Hosts
+    } else {
+        ListFormat::Plain
+    }
+}
+
+/// The rules of a line, with the format it was parsed as.
+fn parse_line(line: &str, format: ListFormat) -> Result<(ListFormat, Vec<Rule>), String> {
+    // `[Adblock Plus 2.0]` headers, and comments of every format but AdBlock Plus, where `#`
+    // starts element hiding rules
+    if line.is_empty() || line.starts_with('[') {
+        return Ok((format, Vec::new()));
+    }
+    if line.starts_with('#') && format != ListFormat::Adblock && !is_generic_hiding_rule(line) {
+        return Ok((format, Vec::new()));
+    }
+
+    let format = match format {
+        ListFormat::Auto => detect_format(line),
+        format => format,
+    };
+    if format == ListFormat::Adblock {
+        let rules = parse_adblock(line)?.into_iter().collect();
+        return Ok((format, rules));
+    }
+
+    let line = line.split('#').next().unwrap_or_default().trim();
+    let rules = match format {
+        ListFormat::Hosts => parse_hosts(line),
+        ListFormat::Dnsmasq => parse_dnsmasq(line),
+        ListFormat::Unbound => parse_unbound(line).map(|rule| rule.into_iter().collect()),
+        _ => parse_plain(line).map(|domain| vec![Rule::Block(domain)]),
+    };
+    Ok((format, rules?))
+}
+
+/// Validated and normalized (lowercase, ASCII) domain name.
+fn parse_domain(name: &str) -> Result<String, &'static str> {
+    let domain = normalize_hostname(name);
+    if domain.parse::<IpAddr>().is_ok() {
+        return Err("an IP address is not a domain name");
+    }
+    if domain.len() > 253 || !domain.contains('.') {
+        return Err("not a domain name");
+    }
+
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    };
+    if !domain.split('.').all(valid_label) {
+        return Err("not a domain name");
+    }
+
+    Ok(domain)
+}
+
+/// A sinkhole address glued to the name, e.g. `0.0.0.0ads.example.com`.
+fn glued_address(token: &str) -> Option<&str> {
+    SINKHOLE_ADDRESSES
+        .iter()
+        .filter_map(|address| token.strip_prefix(address))
+        .find(|name| name.starts_with(|c: char| c.is_ascii_alphabetic()))
+}
+
+fn parse_hosts(line: &str) -> Result<Vec<Rule>, &'static str> {
+    let mut tokens = line.split_whitespace();
+    let first = tokens.next().unwrap_or_default();
+
+    let names = match glued_address(first) {
+        Some(name) => vec![name],
+        None if first.parse::<IpAddr>().is_ok() => tokens.collect(),
+        None => return Err("expected an IP address followed by host names"),
+    };
+    if names.is_empty() {
+        return Err("missing host name");
+    }
+
+    names
+        .into_iter()
+        .filter(|name| !LOCAL_NAMES.contains(&name.to_ascii_lowercase().as_str()))
+        .map(|name| parse_domain(name).map(Rule::Block))
+        .collect()
+}
+
+fn parse_plain(line: &str) -> Result<String, &'static str> {
+    let mut tokens = line.split_whitespace();
+    let domain = parse_domain(tokens.next().unwrap_or_default())?;
+    match tokens.next() {
+        Some(_) => Err("unexpected text after the domain name"),
+        None => Ok(domain),
+    }
+}
+
+fn parse_dnsmasq(line: &str) -> Result<Vec<Rule>, &'static str> {
+    let (key, value) = line.split_once('=').ok_or("expected a dnsmasq option")?;
+    let value = value
+        .strip_prefix('/')
+        .ok_or("expected /domain/ after the option")?;
+    let (domains, target) = value
+        .rsplit_once('/')
+        .ok_or("expected /domain/ after the option")?;
+    let target = target.trim();
+
+    let is_blocking = match key.trim() {
+        "address" => target.is_empty() || target == "#" || SINKHOLE_ADDRESSES.contains(&target),
+        "server" | "local" => target.is_empty(),
+        _ => return Err("unsupported dnsmasq option"),
+    };
+    if !is_blocking {
+        return Err("not a blocking entry");
+    }
+
+    domains
+        .split('/')
+        .map(|domain| parse_domain(domain).map(Rule::Block))
+        .collect()
+}
+
+fn parse_unbound(line: &str) -> Result<Option<Rule>, &'static str> {
+    let (key, value) = line.split_once(':').ok_or("expected an unbound option")?;
+    let value = value.trim();
+
+    match key.trim() {
+        "server" if value.is_empty() => Ok(None),
+        "local-zone" => {
+            let (zone, zone_type) = value
+                .rsplit_once(char::is_whitespace)
+                .ok_or("expected a zone and a type")?;
+            let domain = parse_domain(zone.trim().trim_matches('"'))?;
+            match zone_type.trim() {
+                "transparent" | "always_transparent" => Ok(Some(Rule::Allow(domain))),
+                zone_type if UNBOUND_BLOCKING_ZONES.contains(&zone_type) => {
+                    Ok(Some(Rule::Block(domain)))
+                }
+                _ => Err("unsupported local-zone type"),
+            }
+        }
+        "local-data" => {
+            // "ads.example.com. IN A 0.0.0.0", the class is optional
+            let record = value
+                .trim_matches('"')
+                .split_whitespace()
+                .collect::<Vec<_>>();
+            let (name, address) = match record.as_slice() {
+                [name, "A" | "AAAA", address] | [name, "IN", "A" | "AAAA", address] => {
+                    (*name, *address)
+                }
+                _ => return Err("unsupported local-data record"),
+            };
+            if !SINKHOLE_ADDRESSES.contains(&address) {
+                return Err("not a blocking entry");
+            }
+            parse_domain(name).map(|domain| Some(Rule::Block(domain)))
+        }
+        _ => Err("unsupported unbound option"),
+    }
+}
+
+/// `##.ad` and `#@#.ad` rules, as opposed to the `## Section` comments of other lists.
+fn is_generic_hiding_rule(line: &str) -> bool {
+    let selector = match line.strip_prefix("##").or_else(|| line.strip_prefix("#@#")) {
+        Some(selector) => selector,
+        None => return false,
+    };
+    match selector.as_bytes() {
+        [b'#', next, ..] => next.is_ascii_alphanumeric() || *next == b'-' || *next == b'_',
+        [first, ..] => !first.is_ascii_whitespace(),
+        [] => false,
+    }
+}
+
+fn parse_adblock(line: &str) -> Result<Option<Rule>, String> {
+    if line.starts_with('!') {
+        return Ok(None);
+    }
+
+    match parse_adblock_domain(line) {
+        Ok(rule) => Ok(Some(rule)),
+        Err(domain_error) => {
+            match check_cosmetic_rule(line) {
+                Ok(true) => return Ok(Some(Rule::Cosmetic(line.to_string()))),
+                Ok(false) => {}
+                Err(reason) => return Err(format!("unsupported element hiding rule, {}", reason)),
+            }
+            match check_network_rule(line) {
+                Ok(true) => Ok(Some(Rule::Network(line.to_string()))),
+                Ok(false) => Err(domain_error.to_string()),
+                Err(reason) => Err(format!("unsupported network rule, {}", reason)),
+            }
+        }
+    }
+}
+
+/// A rule blocking or allowing a whole domain, `||example.com^`.
+fn parse_adblock_domain(line: &str) -> Result<Rule, &'static str> {
+    let (is_exception, rule) = match line.strip_prefix("@@") {
+        Some(rule) => (true, rule),
+        None => (false, line),
+    };
+
+    // Only `$important` keeps the rule domain-wide, other options narrow it to some requests
+    let (rule, options) = rule.split_once('$').unwrap_or((rule, ""));
+    if !options.is_empty() && options != "important" {
+        return Err("unsupported AdBlock rule options");
+    }
+
+    let domain = rule
+        .strip_prefix("||")
+        .and_then(|rule| rule.strip_suffix('^'))
+        .ok_or("not a domain rule")?;
+    let domain = parse_domain(domain)?;
+
+    match is_exception {
+        true => Ok(Rule::Allow(domain)),
+        false => Ok(Rule::Block(domain)),
+    }
+}
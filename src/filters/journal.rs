@@ -0,0 +1,132 @@
+// Journal of the entries added and removed through the admin API (`filter.journal`, next to the
+// filter file). Edits are appended to it instead of rewriting the whole filter file, and replayed
+// on top of the file when it is loaded. Once it grows past a threshold the filter file is written
+// with the edits applied and the journal is cleared.
+//
+// One edit per line: `<add|remove> <blacklist|whitelist> <exact|wildcard|regex> <entry>`. Edits
+// that set an annotation (comment, origin, expiry) are written as a JSON object instead.
+
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::domain_filter::ListConfigType;
+use super::metadata::RuleAnnotation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalOp {
+    Add,
+    Remove,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JournalEntry {
+    pub op: JournalOp,
+    pub list_type: ListConfigType,
+    pub is_blacklisted: bool,
+    pub value: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<RuleAnnotation>,
+}
+
+impl JournalEntry {
+    fn to_line(&self) -> String {
+        if self.annotation.is_some()
+            && let Ok(json) = serde_json::to_string(self)
+        {
+            return format!("{}\n", json);
+        }
+
+        let op = match self.op {
+            JournalOp::Add => "add",
+            JournalOp::Remove => "remove",
+        };
+        let list = match self.is_blacklisted {
+            true => "blacklist",
+            false => "whitelist",
+        };
+        let list_type = match self.list_type {
+            ListConfigType::Exact => "exact",
+            ListConfigType::Wildcard => "wildcard",
+            ListConfigType::Regex => "regex",
+        };
+
+        format!("{} {} {} {}\n", op, list, list_type, self.value)
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        if line.starts_with('{') {
+            return serde_json::from_str(line).ok();
+        }
+
+        let mut fields = line.splitn(4, ' ');
+        let op = match fields.next()? {
+            "add" => JournalOp::Add,
+            "remove" => JournalOp::Remove,
+            _ => return None,
+        };
+        let is_blacklisted = match fields.next()? {
+            "blacklist" => true,
+            "whitelist" => false,
+            _ => return None,
+        };
+        let list_type = match fields.next()? {
+            "exact" => ListConfigType::Exact,
+            "wildcard" => ListConfigType::Wildcard,
+            "regex" => ListConfigType::Regex,
+            _ => return None,
+        };
+        let value = fields.next().filter(|value| !value.is_empty())?;
+
+        Some(Self {
+            op,
+            list_type,
+            is_blacklisted,
+            value: value.to_string(),
+            annotation: None,
+        })
+    }
+}
+
+/// Append an edit to the journal and flush it to disk.
+pub fn append(path: &Path, entry: &JournalEntry) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(entry.to_line().as_bytes())?;
+    file.sync_data()
+}
+
+/// The edits of the journal, in order. A missing journal has none, invalid lines are skipped.
+pub fn read(path: &Path) -> std::io::Result<Vec<JournalEntry>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let entries = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let entry = JournalEntry::parse(line);
+            if entry.is_none() {
+                tracing::warn!("Skipping invalid filter journal line {:?}", line);
+            }
+            entry
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+pub fn clear(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,142 @@
+// Compiled form of the wildcard and regex lists of the domain filter, built once per snapshot.
+// Wildcards of the form `*.example.com` go to a suffix index probed label by label, every other
+// pattern joins the regular expressions in a single `RegexSet`, so a lookup costs a few hash
+// probes and one automaton run whatever the size of the lists.
+
+use std::collections::{HashMap, HashSet};
+
+use regex::{Regex, RegexSet, RegexSetBuilder};
+
+use super::domain_filter::ListConfigType;
+
+/// The default limit of the regex crate is sized for a handful of patterns.
+const REGEX_SET_SIZE_LIMIT: usize = 512 * (1 << 20);
+
+#[derive(Clone, Debug, Default)]
+pub struct CompiledList {
+    /// Wildcard entries without `*`, they only match the name itself
+    names: HashSet<String>,
+
+    /// `example.com` for `*.example.com`, which matches the subdomains but not the domain, to
+    /// the entry as listed
+    suffixes: HashMap<String, String>,
+
+    patterns: Option<RegexSet>,
+
+    /// Entry each of the patterns was built from
+    sources: Vec<(ListConfigType, String)>,
+
+    /// Used instead of `patterns` when they do not fit in a single `RegexSet`, with the index of
+    /// their source
+    fallback: Vec<(usize, Regex)>,
+}
+
+/// Anchored regular expression equivalent to a wildcard pattern, `*` matching any characters.
+fn wildcard_to_regex(pattern: &str) -> String {
+    let parts = pattern.split('*').map(regex::escape).collect::<Vec<_>>();
+    format!("^{}$", parts.join(".*"))
+}
+
+/// Whether one wildcard entry matches the domain, the way its compiled form in a `CompiledList`
+/// does. Used to tell which entry matched, lookups go through the compiled list.
+pub fn wildcard_matches(pattern: &str, domain: &str) -> bool {
+    let pattern = pattern.trim();
+    match pattern.strip_prefix("*.") {
+        _ if pattern.is_empty() => false,
+        _ if !pattern.contains('*') => pattern == domain,
+        Some(suffix) if !suffix.is_empty() && !suffix.contains('*') => domain
+            .strip_suffix(suffix)
+            .is_some_and(|rest| rest.len() > 1 && rest.ends_with('.')),
+        _ => Regex::new(&wildcard_to_regex(pattern)).is_ok_and(|re| re.is_match(domain)),
+    }
+}
+
+impl CompiledList {
+    pub fn build<'a>(wildcards: impl IntoIterator<Item = &'a String>, regexes: &[Regex]) -> Self {
+        let mut list = CompiledList::default();
+        let mut patterns = regexes
+            .iter()
+            .map(|re| re.as_str().to_string())
+            .collect::<Vec<_>>();
+        list.sources = patterns
+            .iter()
+            .map(|pattern| (ListConfigType::Regex, pattern.clone()))
+            .collect();
+
+        for pattern in wildcards {
+            let pattern = pattern.trim();
+            if pattern.is_empty() {
+                continue;
+            }
+
+            match pattern.strip_prefix("*.") {
+                _ if !pattern.contains('*') => {
+                    list.names.insert(pattern.to_string());
+                }
+                Some(suffix) if !suffix.is_empty() && !suffix.contains('*') => {
+                    list.suffixes
+                        .insert(suffix.to_string(), pattern.to_string());
+                }
+                _ => {
+                    patterns.push(wildcard_to_regex(pattern));
+                    list.sources
+                        .push((ListConfigType::Wildcard, pattern.to_string()));
+                }
+            }
+        }
+
+        if patterns.is_empty() {
+            return list;
+        }
+
+        match RegexSetBuilder::new(&patterns)
+            .size_limit(REGEX_SET_SIZE_LIMIT)
+            .build()
+        {
+            Ok(set) => list.patterns = Some(set),
+            Err(e) => {
+                // Every pattern compiled on its own when loaded, only the combined size can fail
+                tracing::warn!(
+                    "Unable to combine {} filter patterns, matching them one by one: {}",
+                    patterns.len(),
+                    e
+                );
+                list.fallback = patterns
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, pattern)| Some((index, Regex::new(pattern).ok()?)))
+                    .collect();
+            }
+        }
+
+        list
+    }
+
+    /// The entry matching the domain, if any.
+    pub fn find(&self, domain: &str) -> Option<(ListConfigType, &str)> {
+        if let Some(name) = self.names.get(domain) {
+            return Some((ListConfigType::Wildcard, name));
+        }
+
+        // Probe every parent domain: a.b.example.com -> b.example.com -> example.com -> com
+        let mut rest = domain;
+        while let Some((_, parent)) = rest.split_once('.') {
+            if let Some(pattern) = self.suffixes.get(parent) {
+                return Some((ListConfigType::Wildcard, pattern));
+            }
+            rest = parent;
+        }
+
+        let index = match &self.patterns {
+            Some(set) => set.matches(domain).iter().next(),
+            None => self
+                .fallback
+                .iter()
+                .find(|(_, re)| re.is_match(domain))
+                .map(|(index, _)| *index),
+        }?;
+        self.sources
+            .get(index)
+            .map(|(config_type, entry)| (*config_type, entry.as_str()))
+    }
+}
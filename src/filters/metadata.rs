@@ -0,0 +1,402 @@
+// Metadata of the filter entries.
+//
+// Annotations (comment, origin, creation and expiry dates) are part of the filter: they are
+// written to the `[[annotations]]` of the filter file, kept in its compiled form, and journaled
+// with the edits that set them. Entries edited by hand in the file have none. Expired entries are
+// removed by `run_rule_maintenance`.
+//
+// Hit counts and last matches are statistics, counted in memory when an entry matches a lookup of
+// the proxy and written to `filter.stats.toml` every few minutes and at shutdown. The counters are
+// atomics in a sharded map, so a hit only takes a shared lock once the entry has been seen.
+
+use std::{
+    hash::BuildHasher,
+    path::PathBuf,
+    sync::{
+        LazyLock, RwLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use hashbrown::{DefaultHashBuilder, Equivalent, HashMap};
+use serde::{Deserialize, Serialize};
+use tokio::time::{self as TokioTime, Duration};
+
+use super::domain_filter::{DOMAIN_FILTER, ListConfigType};
+use super::temporary::prune_temporary_rules;
+use crate::shutdown::shutdown_token;
+use crate::utils::now_secs;
+
+/// Expired entries are looked for this often.
+const EXPIRY_CHECK_SECS: u64 = 60;
+
+/// Statistics are written to their file this often, when they changed.
+const STATS_FLUSH_SECS: u64 = 300;
+
+/// Maps the counters are spread over, so concurrent hits rarely wait on the same lock.
+const STATS_SHARDS: usize = 16;
+
+/// An entry of a list of the filter file, or of a subscription.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct RuleKey {
+    pub is_blacklist: bool,
+    pub config_type: ListConfigType,
+    pub entry: String,
+
+    /// `None` for the entries of the filter file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subscription: Option<String>,
+}
+
+impl RuleKey {
+    /// An entry of the filter file.
+    pub fn new(is_blacklist: bool, config_type: ListConfigType, entry: &str) -> Self {
+        Self {
+            is_blacklist,
+            config_type,
+            entry: entry.to_string(),
+            subscription: None,
+        }
+    }
+}
+
+/// An entry matching a lookup, borrowed from the filter or from the domain looked up. Hashes and
+/// compares like the `RuleKey` it stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RuleRef<'a> {
+    pub is_blacklist: bool,
+    pub config_type: ListConfigType,
+    pub entry: &'a str,
+    pub subscription: Option<&'a str>,
+}
+
+impl<'a> RuleRef<'a> {
+    /// An entry of the filter file.
+    pub fn new(is_blacklist: bool, config_type: ListConfigType, entry: &'a str) -> Self {
+        Self {
+            is_blacklist,
+            config_type,
+            entry,
+            subscription: None,
+        }
+    }
+
+    pub fn to_key(self) -> RuleKey {
+        RuleKey {
+            is_blacklist: self.is_blacklist,
+            config_type: self.config_type,
+            entry: self.entry.to_string(),
+            subscription: self.subscription.map(str::to_string),
+        }
+    }
+}
+
+impl Equivalent<RuleKey> for RuleRef<'_> {
+    fn equivalent(&self, key: &RuleKey) -> bool {
+        self.is_blacklist == key.is_blacklist
+            && self.config_type == key.config_type
+            && self.entry == key.entry
+            && self.subscription == key.subscription.as_deref()
+    }
+}
+
+/// Where an entry comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleOrigin {
+    /// Added through the admin API
+    Manual,
+
+    /// Added by a blocklist import
+    Import,
+
+    /// Downloaded by the named subscription
+    Subscription(String),
+}
+
+/// What is known of an entry beyond its value, set when it is added.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RuleAnnotation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<RuleOrigin>,
+
+    /// Unix timestamp in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+
+    /// Unix timestamp in seconds after which the entry is removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl RuleAnnotation {
+    pub fn new(origin: RuleOrigin) -> Self {
+        Self {
+            origin: Some(origin),
+            created_at: Some(now_secs()),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// An annotation in the filter file, next to the entry it is for.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AnnotatedEntry {
+    #[serde(flatten)]
+    pub key: RuleKey,
+
+    #[serde(flatten)]
+    pub annotation: RuleAnnotation,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub struct RuleStats {
+    pub hits: u64,
+
+    /// Unix timestamp in seconds
+    pub last_matched: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct StatsFile {
+    #[serde(default)]
+    rules: Vec<StatsEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct StatsEntry {
+    #[serde(flatten)]
+    key: RuleKey,
+
+    #[serde(flatten)]
+    stats: RuleStats,
+}
+
+/// Counts of an entry, updated without a lock. A last match of 0 means none.
+#[derive(Debug, Default)]
+struct RuleCounter {
+    hits: AtomicU64,
+    last_matched: AtomicU64,
+}
+
+impl RuleCounter {
+    fn from_stats(stats: RuleStats) -> Self {
+        Self {
+            hits: AtomicU64::new(stats.hits),
+            last_matched: AtomicU64::new(stats.last_matched.unwrap_or_default()),
+        }
+    }
+
+    fn record(&self, now: u64) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        self.last_matched.store(now, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> RuleStats {
+        RuleStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            last_matched: Some(self.last_matched.load(Ordering::Relaxed)).filter(|&t| t > 0),
+        }
+    }
+}
+
+type StatsShard = RwLock<HashMap<RuleKey, RuleCounter>>;
+
+fn stats_path() -> PathBuf {
+    DOMAIN_FILTER.load().file.with_extension("stats.toml")
+}
+
+fn load_stats() -> Vec<(RuleKey, RuleStats)> {
+    let path = stats_path();
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+
+    match toml::from_str::<StatsFile>(&content) {
+        Ok(file) => file
+            .rules
+            .into_iter()
+            .map(|entry| (entry.key, entry.stats))
+            .collect(),
+        Err(e) => {
+            tracing::warn!("Ignoring the rule statistics of {:?}: {}", path, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Picks the shard of an entry, a `RuleRef` lands on the same one as its `RuleKey`.
+static SHARD_HASHER: LazyLock<DefaultHashBuilder> = LazyLock::new(DefaultHashBuilder::default);
+
+static RULE_STATS: LazyLock<Vec<StatsShard>> = LazyLock::new(|| {
+    let shards = (0..STATS_SHARDS)
+        .map(|_| RwLock::new(HashMap::new()))
+        .collect::<Vec<StatsShard>>();
+    for (key, stats) in load_stats() {
+        shards[shard_index(&key)]
+            .write()
+            .unwrap()
+            .insert(key, RuleCounter::from_stats(stats));
+    }
+    shards
+});
+
+fn shard_index(key: &impl std::hash::Hash) -> usize {
+    SHARD_HASHER.hash_one(key) as usize % STATS_SHARDS
+}
+
+fn shard(key: &impl std::hash::Hash) -> &'static StatsShard {
+    &RULE_STATS[shard_index(key)]
+}
+
+/// Set when hits were counted since the statistics were written.
+static STATS_DIRTY: AtomicBool = AtomicBool::new(false);
+
+/// Count a match of an entry. Only the first hit of an entry allocates.
+pub fn record_hit(rule: RuleRef<'_>) {
+    let now = now_secs();
+    let shard = shard(&rule);
+
+    let counted = match shard.read().unwrap().get(&rule) {
+        Some(counter) => {
+            counter.record(now);
+            true
+        }
+        None => false,
+    };
+    if !counted {
+        shard
+            .write()
+            .unwrap()
+            .entry(rule.to_key())
+            .or_default()
+            .record(now);
+    }
+
+    // Checked first so hits do not keep writing the same cache line
+    if !STATS_DIRTY.load(Ordering::Relaxed) {
+        STATS_DIRTY.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn get_rule_stats(key: &RuleKey) -> RuleStats {
+    shard(key)
+        .read()
+        .unwrap()
+        .get(key)
+        .map(RuleCounter::stats)
+        .unwrap_or_default()
+}
+
+/// Write the statistics of the entries that still exist, when they changed.
+pub fn save_rule_stats() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !STATS_DIRTY.swap(false, Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    let filter = DOMAIN_FILTER.load();
+    let mut rules = Vec::new();
+    for shard in RULE_STATS.iter() {
+        let mut counters = shard.write().unwrap();
+        counters.retain(|key, _| filter.contains_rule(key));
+        rules.extend(counters.iter().map(|(key, counter)| StatsEntry {
+            key: key.clone(),
+            stats: counter.stats(),
+        }));
+    }
+    rules.sort_unstable_by_key(|entry| std::cmp::Reverse(entry.stats.hits));
+
+    let path = stats_path();
+    let temp_path = path.with_extension("tmp");
+    std::fs::write(&temp_path, toml::to_string(&StatsFile { rules })?)?;
+    std::fs::rename(&temp_path, &path)?;
+    Ok(())
+}
+
+/// Remove the expired entries and temporary rules and write the statistics as they fall due,
+/// until shutdown.
+/// `remove` removes an entry from the active filter.
+pub async fn run_rule_maintenance<F>(remove: F)
+where
+    F: Fn(&RuleKey) -> Result<(), Box<dyn std::error::Error + Send + Sync>>,
+{
+    let shutdown = shutdown_token();
+    let mut last_flush = now_secs();
+
+    loop {
+        let now = now_secs();
+        for key in DOMAIN_FILTER.load().expired_rules(now) {
+            match remove(&key) {
+                Ok(()) => tracing::info!(
+                    "Removed the expired {:?} entry '{}' from the {}",
+                    key.config_type,
+                    key.entry,
+                    if key.is_blacklist {
+                        "blacklist"
+                    } else {
+                        "whitelist"
+                    }
+                ),
+                Err(e) => {
+                    tracing::warn!("Unable to remove the expired entry '{}': {}", key.entry, e)
+                }
+            }
+        }
+
+        for rule in prune_temporary_rules(now) {
+            tracing::info!("Temporary rule {} ({:?}) expired", rule.id, rule.target);
+        }
+
+        if now >= last_flush + STATS_FLUSH_SECS {
+            if let Err(e) = save_rule_stats() {
+                tracing::warn!("Unable to write the rule statistics: {}", e);
+            }
+            last_flush = now;
+        }
+
+        tokio::select! {
+            _ = TokioTime::sleep(Duration::from_secs(EXPIRY_CHECK_SECS)) => {}
+            _ = shutdown.cancelled() => {
+                if let Err(e) = save_rule_stats() {
+                    tracing::warn!("Unable to write the rule statistics: {}", e);
+                }
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn borrowed_entries_find_the_counters_of_their_key() {
+        let rule = RuleRef {
+            subscription: Some("ads"),
+            ..RuleRef::new(true, ListConfigType::Exact, "hits.example")
+        };
+        let key = rule.to_key();
+        assert_eq!(shard_index(&rule), shard_index(&key));
+
+        let mut counters = HashMap::<RuleKey, RuleCounter>::new();
+        counters.entry(key).or_default().record(1);
+        counters.get(&rule).unwrap().record(2);
+
+        let stats = counters.get(&rule).unwrap().stats();
+        assert_eq!((stats.hits, stats.last_matched), (2, Some(2)));
+        assert!(
+            counters
+                .get(&RuleRef::new(true, ListConfigType::Exact, "hits.example"))
+                .is_none()
+        );
+    }
+}
@@ -1,8 +1,32 @@
 // All operations related to filter domain management are handled in this module.
 // including blacklisting for ads, and whitelisting domains to avoid TLS interception.
 
+mod cosmetic;
 mod domain_filter;
+mod importer;
+mod journal;
+mod matcher;
+mod metadata;
+mod network;
+mod public_suffix;
+mod snapshot;
+mod subscriptions;
+mod temporary;
 pub mod utils;
 
-pub use domain_filter::ListConfigType;
+pub use cosmetic::{COSMETIC_FILTER, get_cosmetic_filter_file, reload_cosmetic_filter};
+pub use domain_filter::{ListConfigType, ListMatch, MatchMode};
+pub use importer::{ImportReport, Importer, InvalidLine, ListFormat, parse_list};
+pub use metadata::{RuleAnnotation, RuleKey, RuleOrigin, RuleRef, RuleStats};
+pub use network::{
+    MatchedRule, NETWORK_FILTER, NetworkDecision, RequestContext, ResourceType,
+    get_network_filter_file, is_third_party, reload_network_filter,
+};
+pub use public_suffix::PUBLIC_SUFFIX_LIST;
+pub use subscriptions::{Subscription, SubscriptionState};
+pub use temporary::{
+    DEFAULT_TEMPORARY_RULE_SECS, MAX_TEMPORARY_RULE_SECS, TemporaryRule, TemporaryTarget,
+    add_temporary_rule, blocking_pause, get_temporary_rules, remove_temporary_rule,
+    temporary_allow,
+};
 pub use utils::*;
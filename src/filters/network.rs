@@ -0,0 +1,755 @@
+// Network filter engine: AdBlock Plus / uBlock Origin rules that match the full URL of a request,
+// where the domain lists only see its host.
+//
+// Supported syntax:
+//
+// - patterns with `*` wildcards, `^` separators, `|` start and end anchors and `||` domain anchors,
+//   or regular expressions between slashes
+// - `@@` exceptions, and `$important` rules that exceptions do not override
+// - `$third-party` / `$first-party` (`3p` / `1p`), negated with `~`
+// - resource types: `script`, `image`, `stylesheet`, `xmlhttprequest`, `subdocument`, `font`,
+//   `media`, `object`, `ping`, `websocket`, `other` and `document`, negated with `~`
+// - `$domain=` (`from=`) restricting a rule to the pages of some domains
+// - `$match-case`
+//
+// Rules with other options (`redirect`, `csp`, `removeparam`...) are skipped rather than applied
+// partially. Rules are indexed by one of their tokens, a URL is only checked against the rules
+// indexed by its own tokens and the few rules without a usable token.
+//
+// Rules come from `network_filters.txt` next to the filter file and from the AdBlock lists of the
+// subscriptions.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+};
+
+use arc_swap::ArcSwap;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use super::domain_filter::DOMAIN_FILTER;
+use super::public_suffix::registrable_domain;
+use super::subscriptions::network_rules_file;
+use crate::config::CONFIG_PATH;
+
+static NETWORK_FILTERS_PATH: LazyLock<PathBuf> =
+    LazyLock::new(|| CONFIG_PATH.join("network_filters.txt"));
+
+/// Tokens found in most URLs, indexing a rule by them would check it against every request.
+const COMMON_TOKENS: &[&str] = &["http", "https", "www", "com", "js", "html"];
+
+/// Kind of resource a request loads, as a bit of a rule's type mask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResourceType {
+    Document = 1 << 0,
+    Subdocument = 1 << 1,
+    Script = 1 << 2,
+    Image = 1 << 3,
+    Stylesheet = 1 << 4,
+    Font = 1 << 5,
+    Media = 1 << 6,
+    Object = 1 << 7,
+    XmlHttpRequest = 1 << 8,
+    Ping = 1 << 9,
+    Websocket = 1 << 10,
+    Other = 1 << 11,
+}
+
+/// Types a rule without type options applies to, every one but `document`.
+const DEFAULT_TYPES: u16 = (1 << 12) - 1 - ResourceType::Document as u16;
+
+impl ResourceType {
+    fn from_option(name: &str) -> Option<Self> {
+        let resource_type = match name {
+            "document" | "doc" => Self::Document,
+            "subdocument" | "frame" => Self::Subdocument,
+            "script" => Self::Script,
+            "image" => Self::Image,
+            "stylesheet" | "css" => Self::Stylesheet,
+            "font" => Self::Font,
+            "media" => Self::Media,
+            "object" => Self::Object,
+            "xmlhttprequest" | "xhr" => Self::XmlHttpRequest,
+            "ping" | "beacon" => Self::Ping,
+            "websocket" => Self::Websocket,
+            "other" => Self::Other,
+            _ => return None,
+        };
+        Some(resource_type)
+    }
+}
+
+/// What is known of a request when it is checked against the rules.
+#[derive(Debug, Clone)]
+pub struct RequestContext<'a> {
+    pub url: &'a str,
+    pub resource_type: ResourceType,
+
+    /// Host of the page that made the request, from its Referer or Origin
+    pub source_host: Option<&'a str>,
+
+    /// URL of the page that made the request, for `@@...$document` exceptions
+    pub source_url: Option<&'a str>,
+
+    /// Whether the request goes to another site than the page, `None` when it is unknown
+    pub third_party: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+    Glob {
+        /// Pattern without its anchors, lowercase unless the rule is `$match-case`
+        glob: Box<str>,
+        domain_anchor: bool,
+        start_anchor: bool,
+        end_anchor: bool,
+    },
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone)]
+struct NetworkRule {
+    text: Box<str>,
+    pattern: Pattern,
+    exception: bool,
+    important: bool,
+    match_case: bool,
+
+    /// `Some(true)` for `$third-party`, `Some(false)` for `$first-party`
+    third_party: Option<bool>,
+    types: u16,
+
+    /// `$domain=` entries, `false` for the excluded ones
+    domains: Vec<(Box<str>, bool)>,
+
+    /// Index of the list the rule comes from in `NetworkFilter::lists`
+    list: u16,
+}
+
+fn is_separator(byte: u8) -> bool {
+    !(byte.is_ascii_alphanumeric() || matches!(byte, b'_' | b'-' | b'.' | b'%'))
+}
+
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'%'
+}
+
+fn url_tokens(url: &str) -> HashSet<&str> {
+    url.split(|c: char| !(c.is_ascii_alphanumeric() || c == '%'))
+        .filter(|token| token.len() >= 2)
+        .collect()
+}
+
+/// Whether the glob matches `text` from its start, `*` spanning any run of characters and `^` one
+/// separator or the end of the text.
+fn glob_matches(glob: &[u8], text: &[u8], end_anchor: bool) -> bool {
+    let (mut gi, mut ti) = (0, 0);
+    // Last `*` seen and the text position it currently stands for
+    let mut star: Option<(usize, usize)> = None;
+
+    loop {
+        if gi == glob.len() {
+            if !end_anchor || ti == text.len() {
+                return true;
+            }
+        } else {
+            match glob[gi] {
+                b'*' => {
+                    star = Some((gi, ti));
+                    gi += 1;
+                    continue;
+                }
+                b'^' if ti == text.len() => {
+                    gi += 1;
+                    continue;
+                }
+                b'^' if is_separator(text[ti]) => {
+                    gi += 1;
+                    ti += 1;
+                    continue;
+                }
+                byte if ti < text.len() && byte == text[ti] => {
+                    gi += 1;
+                    ti += 1;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        match star {
+            Some((star_gi, star_ti)) if star_ti < text.len() => {
+                star = Some((star_gi, star_ti + 1));
+                gi = star_gi + 1;
+                ti = star_ti + 1;
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Host part of a URL, as a byte range.
+fn host_range(url: &str) -> (usize, usize) {
+    let start = url.find("://").map_or(0, |i| i + 3);
+    let end = url[start..]
+        .find(['/', '?', '#', ':'])
+        .map_or(url.len(), |i| start + i);
+    (start, end)
+}
+
+pub(super) fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.len() > domain.len()
+            && host.ends_with(domain)
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.')
+}
+
+impl NetworkRule {
+    /// Parse a network rule. Comments and element hiding rules are `Ok(None)`, rules using
+    /// unsupported syntax are an error.
+    fn parse(line: &str) -> Result<Option<Self>, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+            return Ok(None);
+        }
+        if ["##", "#@#", "#?#", "#$#", "#@?#", "#@$#"]
+            .iter()
+            .any(|marker| line.contains(marker))
+        {
+            return Ok(None);
+        }
+
+        let (exception, rule) = match line.strip_prefix("@@") {
+            Some(rule) => (true, rule),
+            None => (false, line),
+        };
+
+        // Options follow the last `$`, which a regular expression may contain too
+        let (pattern, options) = match rule.rfind('$') {
+            Some(i) if !(rule.starts_with('/') && rule.ends_with('/')) => {
+                (&rule[..i], Some(&rule[i + 1..]))
+            }
+            _ => (rule, None),
+        };
+
+        let (mut important, mut match_case, mut third_party) = (false, false, None);
+        let mut domains = Vec::new();
+        let (mut included_types, mut excluded_types) = (0u16, 0u16);
+        for option in options.into_iter().flat_map(|options| options.split(',')) {
+            let option = option.trim();
+            let (negated, name) = match option.strip_prefix('~') {
+                Some(name) => (true, name),
+                None => (false, option),
+            };
+
+            if let Some(resource_type) = ResourceType::from_option(name) {
+                match negated {
+                    true => excluded_types |= resource_type as u16,
+                    false => included_types |= resource_type as u16,
+                }
+                continue;
+            }
+
+            match name {
+                "third-party" | "3p" => third_party = Some(!negated),
+                "first-party" | "1p" => third_party = Some(negated),
+                "important" if !negated => important = true,
+                "match-case" if !negated => match_case = true,
+                _ => {
+                    let Some(list) = option
+                        .strip_prefix("domain=")
+                        .or_else(|| option.strip_prefix("from="))
+                    else {
+                        return Err(format!("unsupported option '{}'", option));
+                    };
+                    domains = list
+                        .split('|')
+                        .filter(|domain| !domain.is_empty())
+                        .map(|domain| match domain.strip_prefix('~') {
+                            Some(domain) => (domain.to_ascii_lowercase().into(), false),
+                            None => (domain.to_ascii_lowercase().into(), true),
+                        })
+                        .collect();
+                }
+            }
+        }
+        let types = match included_types {
+            0 => DEFAULT_TYPES,
+            included_types => included_types,
+        } & !excluded_types;
+        if types == 0 {
+            return Err("the rule applies to no resource type".to_string());
+        }
+
+        let pattern = match pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/')
+        {
+            true => Pattern::Regex(
+                RegexBuilder::new(&pattern[1..pattern.len() - 1])
+                    .case_insensitive(!match_case)
+                    .build()
+                    .map_err(|e| e.to_string())?,
+            ),
+            false => {
+                let (domain_anchor, pattern) = match pattern.strip_prefix("||") {
+                    Some(pattern) => (true, pattern),
+                    None => (false, pattern),
+                };
+                let (start_anchor, pattern) = match pattern.strip_prefix('|') {
+                    Some(pattern) if !domain_anchor => (true, pattern),
+                    _ => (false, pattern),
+                };
+                let (end_anchor, pattern) = match pattern.strip_suffix('|') {
+                    Some(pattern) => (true, pattern),
+                    None => (false, pattern),
+                };
+
+                // Leading and trailing wildcards are implied
+                let trimmed = match domain_anchor || start_anchor {
+                    true => pattern,
+                    false => pattern.trim_start_matches('*'),
+                };
+                let trimmed = match end_anchor {
+                    true => trimmed,
+                    false => trimmed.trim_end_matches('*'),
+                };
+                if trimmed.is_empty() && !exception {
+                    return Err("the rule matches every request".to_string());
+                }
+
+                Pattern::Glob {
+                    glob: match match_case {
+                        true => trimmed.into(),
+                        false => trimmed.to_ascii_lowercase().into(),
+                    },
+                    domain_anchor,
+                    start_anchor,
+                    end_anchor,
+                }
+            }
+        };
+
+        Ok(Some(NetworkRule {
+            text: line.into(),
+            pattern,
+            exception,
+            important,
+            match_case,
+            third_party,
+            types,
+            list: 0,
+            domains,
+        }))
+    }
+
+    /// The token the rule is indexed by: the longest run of letters and digits that any matching
+    /// URL contains in full.
+    fn token(&self) -> Option<String> {
+        let Pattern::Glob {
+            glob,
+            domain_anchor,
+            start_anchor,
+            end_anchor,
+        } = &self.pattern
+        else {
+            return None;
+        };
+        let glob = glob.to_ascii_lowercase();
+        let bytes = glob.as_bytes();
+
+        let mut best: Option<&str> = None;
+        let mut start = 0;
+        while start < bytes.len() {
+            if !is_token_byte(bytes[start]) {
+                start += 1;
+                continue;
+            }
+            let mut end = start;
+            while end < bytes.len() && is_token_byte(bytes[end]) {
+                end += 1;
+            }
+
+            // A run touching a wildcard, or an unanchored end of the pattern, may be part of a
+            // longer token in the URL
+            let bounded_start = match start {
+                0 => *domain_anchor || *start_anchor,
+                _ => bytes[start - 1] != b'*',
+            };
+            let bounded_end = match end == bytes.len() {
+                true => *end_anchor,
+                false => bytes[end] != b'*',
+            };
+
+            let token = &glob[start..end];
+            if bounded_start
+                && bounded_end
+                && token.len() >= 2
+                && !COMMON_TOKENS.contains(&token)
+                && best.is_none_or(|best| token.len() > best.len())
+            {
+                best = Some(token);
+            }
+            start = end;
+        }
+
+        best.map(str::to_string)
+    }
+
+    fn matches_url(&self, url: &str, lowercase_url: &str) -> bool {
+        let text = match self.match_case {
+            true => url,
+            false => lowercase_url,
+        };
+
+        match &self.pattern {
+            Pattern::Regex(regex) => regex.is_match(url),
+            Pattern::Glob {
+                glob,
+                domain_anchor,
+                start_anchor,
+                end_anchor,
+            } => {
+                let glob = glob.as_bytes();
+                let bytes = text.as_bytes();
+
+                if *start_anchor {
+                    return glob_matches(glob, bytes, *end_anchor);
+                }
+
+                if *domain_anchor {
+                    // At the start of the host or of one of its labels
+                    let (host_start, host_end) = host_range(text);
+                    return (host_start..host_end)
+                        .filter(|&i| i == host_start || bytes[i - 1] == b'.')
+                        .any(|i| glob_matches(glob, &bytes[i..], *end_anchor));
+                }
+
+                let first = glob.first().copied();
+                (0..=bytes.len())
+                    .filter(|&i| match first {
+                        Some(b'*' | b'^') | None => true,
+                        Some(byte) => bytes.get(i) == Some(&byte),
+                    })
+                    .any(|i| glob_matches(glob, &bytes[i..], *end_anchor))
+            }
+        }
+    }
+
+    fn matches(&self, request: &RequestContext, lowercase_url: &str) -> bool {
+        if self.types & request.resource_type as u16 == 0 {
+            return false;
+        }
+
+        if let Some(third_party) = self.third_party
+            && request.third_party != Some(third_party)
+        {
+            return false;
+        }
+
+        if !self.domains.is_empty() {
+            let has_includes = self.domains.iter().any(|(_, include)| *include);
+            let Some(source) = request.source_host else {
+                return !has_includes && self.matches_url(request.url, lowercase_url);
+            };
+
+            let mut included = false;
+            for (domain, include) in &self.domains {
+                if domain_matches(source, domain) {
+                    match include {
+                        true => included = true,
+                        false => return false,
+                    }
+                }
+            }
+            if has_includes && !included {
+                return false;
+            }
+        }
+
+        self.matches_url(request.url, lowercase_url)
+    }
+}
+
+/// Whether a line is a supported network rule, `Ok(false)` for comments and element hiding rules.
+pub fn check_network_rule(line: &str) -> Result<bool, String> {
+    NetworkRule::parse(line).map(|rule| rule.is_some())
+}
+
+/// Rules of one kind, indexed by token.
+#[derive(Debug, Default)]
+struct RuleIndex {
+    rules: Vec<NetworkRule>,
+    by_token: HashMap<Box<str>, Vec<u32>>,
+
+    /// Rules without a usable token, checked against every request
+    untokenized: Vec<u32>,
+}
+
+impl RuleIndex {
+    fn insert(&mut self, rule: NetworkRule) {
+        let id = self.rules.len() as u32;
+        match rule.token() {
+            Some(token) => self.by_token.entry(token.into()).or_default().push(id),
+            None => self.untokenized.push(id),
+        }
+        self.rules.push(rule);
+    }
+
+    /// First rule matching the request.
+    fn find(
+        &self,
+        request: &RequestContext,
+        lowercase_url: &str,
+        tokens: &HashSet<&str>,
+    ) -> Option<&NetworkRule> {
+        tokens
+            .iter()
+            .filter_map(|token| self.by_token.get(*token))
+            .flatten()
+            .chain(&self.untokenized)
+            .map(|&id| &self.rules[id as usize])
+            .find(|rule| rule.matches(request, lowercase_url))
+    }
+}
+
+/// Compiled network rules.
+#[derive(Debug, Default)]
+pub struct NetworkFilter {
+    blocking: RuleIndex,
+    important: RuleIndex,
+    exceptions: RuleIndex,
+
+    /// Subscription of each list added, `None` for the rules file
+    lists: Vec<Option<Box<str>>>,
+}
+
+/// A network rule, with the subscription it comes from.
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchedRule {
+    pub rule: String,
+
+    /// `None` for the rules file
+    pub subscription: Option<String>,
+}
+
+/// How the network rules decide on a request, see [`NetworkFilter::explain`].
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkDecision {
+    pub url: String,
+    pub resource_type: ResourceType,
+    pub third_party: Option<bool>,
+    pub blocked: bool,
+
+    /// Blocking rule matching the request
+    pub rule: Option<MatchedRule>,
+
+    /// Exception overriding `rule`, an `@@...$document` one allows the page that made the request
+    pub exception: Option<MatchedRule>,
+}
+
+impl NetworkFilter {
+    pub fn len(&self) -> usize {
+        self.blocking.rules.len() + self.important.rules.len() + self.exceptions.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add the rules of a list, returning the number of rules that were skipped. `subscription`
+    /// names the subscription of the list, `source` names the list in the logs.
+    pub fn add_rules(&mut self, source: &str, subscription: Option<&str>, content: &str) -> usize {
+        let list = self.lists.len() as u16;
+        self.lists.push(subscription.map(Into::into));
+
+        let mut skipped = 0;
+        for (index, line) in content.lines().enumerate() {
+            let rule =
+                NetworkRule::parse(line).map(|rule| rule.map(|rule| NetworkRule { list, ..rule }));
+            match rule {
+                Ok(Some(rule)) if rule.exception => self.exceptions.insert(rule),
+                Ok(Some(rule)) if rule.important => self.important.insert(rule),
+                Ok(Some(rule)) => self.blocking.insert(rule),
+                Ok(None) => {}
+                Err(reason) => {
+                    tracing::debug!(
+                        "Skipping network rule {}:{} {:?}: {}",
+                        source,
+                        index + 1,
+                        line,
+                        reason
+                    );
+                    skipped += 1;
+                }
+            }
+        }
+        skipped
+    }
+
+    /// The rule blocking the request, if any.
+    pub fn blocking_rule(&self, request: &RequestContext) -> Option<&str> {
+        match self.decide(request) {
+            (Some(rule), None) => Some(&rule.text),
+            _ => None,
+        }
+    }
+
+    /// The blocking rule matching the request and the exception overriding it, if any.
+    fn decide(&self, request: &RequestContext) -> (Option<&NetworkRule>, Option<&NetworkRule>) {
+        if self.is_empty() {
+            return (None, None);
+        }
+
+        let lowercase_url = request.url.to_ascii_lowercase();
+        let tokens = url_tokens(&lowercase_url);
+
+        if let Some(rule) = self.important.find(request, &lowercase_url, &tokens) {
+            return (Some(rule), None);
+        }
+
+        let Some(rule) = self.blocking.find(request, &lowercase_url, &tokens) else {
+            return (None, None);
+        };
+        let exception = self
+            .exceptions
+            .find(request, &lowercase_url, &tokens)
+            .or_else(|| self.page_exception(request));
+
+        (Some(rule), exception)
+    }
+
+    /// How the rules decide on the request, with the lists of the rules involved.
+    pub fn explain(&self, request: &RequestContext) -> NetworkDecision {
+        let matched = |rule: &NetworkRule| MatchedRule {
+            rule: rule.text.to_string(),
+            subscription: self.lists[rule.list as usize]
+                .as_deref()
+                .map(str::to_string),
+        };
+        let (rule, exception) = self.decide(request);
+
+        NetworkDecision {
+            url: request.url.to_string(),
+            resource_type: request.resource_type,
+            third_party: request.third_party,
+            blocked: rule.is_some() && exception.is_none(),
+            rule: rule.map(matched),
+            exception: exception.map(matched),
+        }
+    }
+
+    /// An `@@...$document` exception allowing the page that made the request.
+    fn page_exception(&self, request: &RequestContext) -> Option<&NetworkRule> {
+        let source_url = request.source_url?;
+        let page = RequestContext {
+            url: source_url,
+            resource_type: ResourceType::Document,
+            source_host: request.source_host,
+            source_url: None,
+            third_party: Some(false),
+        };
+
+        let lowercase_url = source_url.to_ascii_lowercase();
+        let tokens = url_tokens(&lowercase_url);
+        self.exceptions.find(&page, &lowercase_url, &tokens)
+    }
+}
+
+/// Whether two hosts belong to different sites, compared by registrable domain.
+pub fn is_third_party(host: &str, source_host: &str) -> bool {
+    let site = |host| registrable_domain(host).unwrap_or(host);
+    site(host) != site(source_host)
+}
+
+/// The active network rules, swapped by `reload_network_filter`.
+pub static NETWORK_FILTER: LazyLock<ArcSwap<NetworkFilter>> =
+    LazyLock::new(|| ArcSwap::from_pointee(build_network_filter()));
+
+fn build_network_filter() -> NetworkFilter {
+    let mut filter = NetworkFilter::default();
+
+    let domain_filter = DOMAIN_FILTER.load();
+    let mut files = vec![(NETWORK_FILTERS_PATH.clone(), None)];
+    files.extend(domain_filter.subscriptions.iter().map(|subscription| {
+        (
+            network_rules_file(subscription),
+            Some(subscription.name.as_str()),
+        )
+    }));
+
+    for (file, subscription) in files.into_iter().filter(|(file, _)| file.exists()) {
+        match std::fs::read_to_string(&file) {
+            Ok(content) => {
+                let skipped = filter.add_rules(&file.display().to_string(), subscription, &content);
+                if skipped > 0 {
+                    tracing::info!(
+                        "Skipped {} unsupported network rules of {:?}",
+                        skipped,
+                        file
+                    );
+                }
+            }
+            Err(e) => tracing::warn!("Unable to read the network rules of {:?}: {}", file, e),
+        }
+    }
+
+    tracing::info!("Loaded {} network rules", filter.len());
+    filter
+}
+
+/// Load the network rules again, after the rules file or a subscription changed.
+pub fn reload_network_filter() {
+    NETWORK_FILTER.store(Arc::new(build_network_filter()));
+}
+
+/// Append the rules missing from a rules file, returning the number of rules added.
+pub(super) fn append_rules(file: &Path, rules: &[String]) -> std::io::Result<usize> {
+    let mut content = match std::fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let existing = content.lines().map(str::trim).collect::<HashSet<_>>();
+    let new_rules = rules
+        .iter()
+        .filter(|rule| !existing.contains(rule.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    if new_rules.is_empty() {
+        return Ok(0);
+    }
+
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    for rule in &new_rules {
+        content.push_str(rule);
+        content.push('\n');
+    }
+
+    if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp_path = file.with_extension("tmp");
+    std::fs::write(&temp_path, content)?;
+    std::fs::rename(&temp_path, file)?;
+
+    Ok(new_rules.len())
+}
+
+/// Append rules missing from the rules file and load the rules again, returning the number of rules
+/// added.
+pub fn add_network_rules(rules: &[String]) -> std::io::Result<usize> {
+    let added = append_rules(&NETWORK_FILTERS_PATH, rules)?;
+    if added > 0 {
+        reload_network_filter();
+    }
+    Ok(added)
+}
+
+pub fn get_network_filter_file() -> PathBuf {
+    NETWORK_FILTERS_PATH.clone()
+}
@@ -0,0 +1,52 @@
+// Registrable domains (eTLD+1) from the Public Suffix List, so that parent-domain matching stops
+// at `example.co.uk` instead of blocking a whole `co.uk`. A copy of the list is embedded, newer
+// ones are used when installed.
+
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+use publicsuffix::{List, Psl};
+
+use crate::config::CONFIG_PATH;
+
+/// Copy shipped by most distributions (`publicsuffix` package), used when none is configured.
+const SYSTEM_LIST_PATH: &str = "/usr/share/publicsuffix/public_suffix_list.dat";
+
+/// Snapshot of https://publicsuffix.org/list/public_suffix_list.dat (2023-02-09), used when no
+/// copy is installed.
+const EMBEDDED_LIST: &str = include_str!("public_suffix_list.dat");
+
+pub static PUBLIC_SUFFIX_LIST: LazyLock<List> = LazyLock::new(|| {
+    let paths = [
+        CONFIG_PATH.join("public_suffix_list.dat"),
+        PathBuf::from(SYSTEM_LIST_PATH),
+    ];
+
+    for path in paths.iter().filter(|path| path.exists()) {
+        let list = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| content.parse::<List>().map_err(|e| e.to_string()));
+        match list {
+            Ok(list) => {
+                tracing::info!("Loaded the Public Suffix List from {:?}", path);
+                return list;
+            }
+            Err(e) => tracing::warn!("Invalid Public Suffix List {:?}: {}", path, e),
+        }
+    }
+
+    tracing::info!("Using the embedded Public Suffix List");
+    EMBEDDED_LIST.parse::<List>().unwrap_or_else(|e| {
+        panic!("Failed to parse the embedded Public Suffix List: {}", e);
+    })
+});
+
+/// The registrable part of `domain`, as a suffix of it. `None` for public suffixes themselves and
+/// for names with a single label.
+pub fn registrable_domain(domain: &str) -> Option<&str> {
+    let len = PUBLIC_SUFFIX_LIST
+        .domain(domain.as_bytes())?
+        .as_bytes()
+        .len();
+    domain.get(domain.len() - len..)
+}
@@ -0,0 +1,368 @@
+// Compiled binary form of the filter file, written next to it (`filter.fst`) and memory-mapped at
+// startup instead of parsing the TOML. The exact lists are FST sets, looked up in place without
+// being loaded; the wildcard and regex lists are small and stored as strings. The snapshot records
+// the size and modification time of the TOML file it was compiled from, and is compiled again when
+// they no longer match, e.g. after a manual edit.
+//
+// Subscriptions keep their entries in standalone FST files, see `write_set` and `read_set`.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+    fs::File,
+    path::Path,
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+
+use fst::{Set, SetBuilder, Streamer};
+use memmap2::Mmap;
+
+use super::domain_filter::MatchMode;
+
+const MAGIC_PREFIX: &[u8; 5] = b"NAFLT";
+const MAGIC: &[u8; 8] = b"NAFLT002";
+
+/// Size and modification time of the TOML file a snapshot was compiled from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint {
+    len: u64,
+    secs: u64,
+    nanos: u32,
+}
+
+impl Fingerprint {
+    pub fn of(file: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(file).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            len: metadata.len(),
+            secs: modified.as_secs(),
+            nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+/// Part of the mapped snapshot holding one FST.
+struct MappedSlice {
+    map: Arc<Mmap>,
+    start: usize,
+    end: usize,
+}
+
+impl AsRef<[u8]> for MappedSlice {
+    fn as_ref(&self) -> &[u8] {
+        &self.map[self.start..self.end]
+    }
+}
+
+/// Exact entries of a list: the compiled set of the snapshot, plus the changes made since it was
+/// compiled. Cloning it copies nothing, the changes are copied by the first edit of the clone.
+#[derive(Clone, Default)]
+pub struct ExactSet {
+    base: Option<Arc<Set<MappedSlice>>>,
+
+    /// Entries missing from `base`
+    added: Arc<BTreeSet<String>>,
+
+    /// Entries of `base` that were removed
+    removed: Arc<HashSet<String>>,
+}
+
+impl fmt::Debug for ExactSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExactSet")
+            .field("compiled", &self.base.as_ref().map_or(0, |base| base.len()))
+            .field("added", &self.added.len())
+            .field("removed", &self.removed.len())
+            .finish()
+    }
+}
+
+impl FromIterator<String> for ExactSet {
+    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+        Self {
+            added: Arc::new(iter.into_iter().collect()),
+            ..Default::default()
+        }
+    }
+}
+
+impl ExactSet {
+    fn in_base(&self, domain: &str) -> bool {
+        self.base.as_ref().is_some_and(|base| base.contains(domain))
+    }
+
+    pub fn contains(&self, domain: &str) -> bool {
+        self.added.contains(domain) || (self.in_base(domain) && !self.removed.contains(domain))
+    }
+
+    pub fn insert(&mut self, domain: String) {
+        if self.in_base(&domain) {
+            if self.removed.contains(&domain) {
+                Arc::make_mut(&mut self.removed).remove(&domain);
+            }
+        } else if !self.added.contains(&domain) {
+            Arc::make_mut(&mut self.added).insert(domain);
+        }
+    }
+
+    pub fn remove(&mut self, domain: &str) {
+        if self.in_base(domain) {
+            if !self.removed.contains(domain) {
+                Arc::make_mut(&mut self.removed).insert(domain.to_string());
+            }
+        } else if self.added.contains(domain) {
+            Arc::make_mut(&mut self.added).remove(domain);
+        }
+    }
+
+    pub fn extend(&mut self, other: &ExactSet) {
+        for domain in other.to_sorted_vec() {
+            self.insert(domain);
+        }
+    }
+
+    /// All the entries, in byte order.
+    pub fn to_sorted_vec(&self) -> Vec<String> {
+        let mut entries = Vec::with_capacity(self.added.len());
+        if let Some(base) = &self.base {
+            let mut stream = base.stream();
+            while let Some(key) = stream.next() {
+                let key = String::from_utf8_lossy(key);
+                if !self.removed.contains(key.as_ref()) {
+                    entries.push(key.into_owned());
+                }
+            }
+        }
+
+        // Two sorted runs, which the stable sort merges in linear time
+        entries.extend(self.added.iter().cloned());
+        entries.sort();
+        entries
+    }
+}
+
+/// One list as stored in the snapshot.
+pub struct ListSnapshot {
+    pub mode: MatchMode,
+    pub exact: ExactSet,
+    pub wildcards: Vec<String>,
+    pub regex: Vec<String>,
+}
+
+/// One list to compile into a snapshot.
+pub struct ListSections<'a> {
+    pub mode: MatchMode,
+    pub exact: &'a ExactSet,
+    pub wildcards: Vec<&'a str>,
+    pub regex: Vec<&'a str>,
+}
+
+pub struct Snapshot {
+    pub blacklist: ListSnapshot,
+    pub whitelist: ListSnapshot,
+
+    /// The `[[subscriptions]]` and `[[annotations]]` of the filter file, as TOML
+    pub tables: String,
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: usize) {
+    buffer.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+fn put_string(buffer: &mut Vec<u8>, string: &str) {
+    put_u32(buffer, string.len());
+    buffer.extend_from_slice(string.as_bytes());
+}
+
+fn put_strings<'a>(buffer: &mut Vec<u8>, strings: impl ExactSizeIterator<Item = &'a str>) {
+    put_u32(buffer, strings.len());
+    for string in strings {
+        put_string(buffer, string);
+    }
+}
+
+fn build_set(domains: &[String]) -> Result<Vec<u8>, fst::Error> {
+    let mut builder = SetBuilder::memory();
+    for domain in domains {
+        builder.insert(domain)?;
+    }
+    builder.into_inner()
+}
+
+fn put_list(buffer: &mut Vec<u8>, list: &ListSections) -> Result<(), fst::Error> {
+    buffer.push(match list.mode {
+        MatchMode::Exact => 0,
+        MatchMode::Subdomains => 1,
+    });
+
+    let fst = build_set(&list.exact.to_sorted_vec())?;
+    buffer.extend_from_slice(&(fst.len() as u64).to_le_bytes());
+    buffer.extend_from_slice(&fst);
+
+    put_strings(buffer, list.wildcards.iter().copied());
+    put_strings(buffer, list.regex.iter().copied());
+    Ok(())
+}
+
+/// Compile the lists into a snapshot file, replacing it atomically.
+pub fn write_snapshot(
+    path: &Path,
+    source: Fingerprint,
+    blacklist: ListSections,
+    whitelist: ListSections,
+    tables: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut buffer = MAGIC.to_vec();
+    buffer.extend_from_slice(&source.len.to_le_bytes());
+    buffer.extend_from_slice(&source.secs.to_le_bytes());
+    buffer.extend_from_slice(&source.nanos.to_le_bytes());
+
+    put_list(&mut buffer, &blacklist)?;
+    put_list(&mut buffer, &whitelist)?;
+    put_string(&mut buffer, tables);
+
+    write_atomic(path, &buffer)
+}
+
+fn write_atomic(
+    path: &Path,
+    content: &[u8],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_path = path.with_extension("fst.tmp");
+    std::fs::write(&temp_path, content)?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Compile sorted, de-duplicated domains into a standalone set file, replacing it atomically.
+pub fn write_set(
+    path: &Path,
+    domains: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    write_atomic(path, &build_set(domains)?)
+}
+
+fn map_file(path: &Path) -> Result<Arc<Mmap>, Box<dyn std::error::Error + Send + Sync>> {
+    let file = File::open(path)?;
+    // SAFETY: compiled files are only ever replaced by a rename, never modified in place, so the
+    // mapped file does not change under us
+    let map = unsafe { Mmap::map(&file)? };
+    Ok(Arc::new(map))
+}
+
+/// Map a set file written by [`write_set`].
+pub fn read_set(path: &Path) -> Result<ExactSet, Box<dyn std::error::Error + Send + Sync>> {
+    let map = map_file(path)?;
+    let end = map.len();
+    let set = Set::new(MappedSlice { map, start: 0, end })?;
+
+    Ok(ExactSet {
+        base: Some(Arc::new(set)),
+        ..Default::default()
+    })
+}
+
+/// Reads the sections of a mapped snapshot in order.
+struct Reader {
+    map: Arc<Mmap>,
+    pos: usize,
+}
+
+impl Reader {
+    fn take(&mut self, len: usize) -> Result<(usize, usize), &'static str> {
+        let start = self.pos;
+        let end = start.checked_add(len).filter(|end| *end <= self.map.len());
+        self.pos = end.ok_or("truncated snapshot")?;
+        Ok((start, self.pos))
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], &'static str> {
+        let (start, end) = self.take(N)?;
+        Ok(self.map[start..end].try_into().unwrap_or([0; N]))
+    }
+
+    fn u32(&mut self) -> Result<usize, &'static str> {
+        Ok(u32::from_le_bytes(self.bytes()?) as usize)
+    }
+
+    fn u64(&mut self) -> Result<u64, &'static str> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn string(&mut self) -> Result<String, &'static str> {
+        let len = self.u32()?;
+        let (start, end) = self.take(len)?;
+        String::from_utf8(self.map[start..end].to_vec()).map_err(|_| "invalid string")
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>, &'static str> {
+        let count = self.u32()?;
+        (0..count).map(|_| self.string()).collect()
+    }
+
+    fn list(&mut self) -> Result<ListSnapshot, Box<dyn std::error::Error + Send + Sync>> {
+        let mode = match self.bytes::<1>()? {
+            [0] => MatchMode::Exact,
+            [1] => MatchMode::Subdomains,
+            _ => return Err("invalid match mode".into()),
+        };
+
+        let len = self.u64()? as usize;
+        let (start, end) = self.take(len)?;
+        let set = Set::new(MappedSlice {
+            map: self.map.clone(),
+            start,
+            end,
+        })?;
+
+        Ok(ListSnapshot {
+            mode,
+            exact: ExactSet {
+                base: Some(Arc::new(set)),
+                ..Default::default()
+            },
+            wildcards: self.strings()?,
+            regex: self.strings()?,
+        })
+    }
+}
+
+/// Map a snapshot file. `None` when it is missing, was compiled from another version of the
+/// TOML file or by another version of the proxy.
+pub fn read_snapshot(
+    path: &Path,
+    source: Fingerprint,
+) -> Result<Option<Snapshot>, Box<dyn std::error::Error + Send + Sync>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let mut reader = Reader {
+        map: map_file(path)?,
+        pos: 0,
+    };
+
+    let magic = reader.bytes::<8>()?;
+    if &magic != MAGIC {
+        return match magic.starts_with(MAGIC_PREFIX) {
+            true => Ok(None),
+            false => Err("not a filter snapshot".into()),
+        };
+    }
+    let compiled_from = Fingerprint {
+        len: reader.u64()?,
+        secs: reader.u64()?,
+        nanos: u32::from_le_bytes(reader.bytes()?),
+    };
+    if compiled_from != source {
+        return Ok(None);
+    }
+
+    Ok(Some(Snapshot {
+        blacklist: reader.list()?,
+        whitelist: reader.list()?,
+        tables: reader.string()?,
+    }))
+}
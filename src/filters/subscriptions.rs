@@ -0,0 +1,583 @@
+// Filter list subscriptions: remote blocklists declared in `filter.toml` and refreshed on a
+// schedule. Every subscription keeps its entries in its own compiled files under
+// `.config/subscriptions/`, next to the state of its last refresh, so lookups can tell the sources
+// apart and removing a subscription only drops its own entries. Downloads are conditional (ETag
+// and If-Modified-Since), a list that did not change costs a 304.
+
+use std::{path::PathBuf, sync::LazyLock};
+
+use reqwest::{
+    StatusCode,
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::time::{self as TokioTime, Duration};
+
+use super::importer::{ListFormat, parse_list};
+use super::snapshot::{ExactSet, read_set, write_set};
+use crate::config::CONFIG_PATH;
+use crate::shutdown::shutdown_token;
+use crate::utils::now_secs;
+
+static SUBSCRIPTIONS_PATH: LazyLock<PathBuf> = LazyLock::new(|| CONFIG_PATH.join("subscriptions"));
+
+/// One refresh at a time, the scheduled ones and those requested through the admin API.
+static REFRESH_LOCK: LazyLock<AsyncMutex<()>> = LazyLock::new(|| AsyncMutex::new(()));
+
+const DEFAULT_INTERVAL_HOURS: u64 = 24;
+/// How often the schedule is checked.
+const SCHEDULE_CHECK_SECS: u64 = 60;
+/// Delay before a failed refresh is tried again, when shorter than the interval.
+const RETRY_SECS: u64 = 15 * 60;
+const DOWNLOAD_TIMEOUT_SECS: u64 = 120;
+
+fn default_interval_hours() -> u64 {
+    DEFAULT_INTERVAL_HOURS
+}
+
+/// A `[[subscriptions]]` entry of the filter file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subscription {
+    /// Identifies the subscription in the admin API and names its files
+    pub name: String,
+
+    pub url: String,
+
+    #[serde(default)]
+    pub format: ListFormat,
+
+    #[serde(default = "default_interval_hours")]
+    pub interval_hours: u64,
+}
+
+impl Subscription {
+    pub fn validate(&self) -> Result<(), String> {
+        let valid_name = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            return Err(format!(
+                "Invalid subscription name '{}', use letters, digits, '-' and '_'",
+                self.name
+            ));
+        }
+
+        match reqwest::Url::parse(&self.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => {
+                return Err(format!(
+                    "Invalid URL '{}' for subscription '{}'",
+                    self.url, self.name
+                ));
+            }
+        }
+
+        if self.interval_hours == 0 {
+            return Err(format!(
+                "The interval of subscription '{}' must be at least one hour",
+                self.name
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn file(&self, suffix: &str) -> PathBuf {
+        SUBSCRIPTIONS_PATH.join(format!("{}.{}", self.name, suffix))
+    }
+
+    /// Whether the schedule calls for a refresh, failed refreshes are retried sooner.
+    fn is_due(&self, state: &SubscriptionState, now: u64) -> bool {
+        let mut interval = self.interval_hours * 3600;
+        if state.last_error.is_some() {
+            interval = interval.min(RETRY_SECS);
+        }
+
+        state
+            .last_checked
+            .is_none_or(|checked| now >= checked + interval)
+    }
+}
+
+/// Validate the subscriptions of a filter file, names must be unique.
+pub fn validate_subscriptions(subscriptions: &[Subscription]) -> Result<(), String> {
+    for (index, subscription) in subscriptions.iter().enumerate() {
+        subscription.validate()?;
+        if subscriptions[..index]
+            .iter()
+            .any(|other| other.name == subscription.name)
+        {
+            return Err(format!("Duplicate subscription '{}'", subscription.name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Outcome of the refreshes of a subscription, kept in `<name>.state.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SubscriptionState {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+
+    /// Unix timestamps in seconds
+    pub last_checked: Option<u64>,
+    pub last_updated: Option<u64>,
+
+    /// Set while the last refresh failed, the entries of the previous download stay active
+    pub last_error: Option<String>,
+
+    pub blacklist_entries: usize,
+    pub whitelist_entries: usize,
+
+    /// AdBlock rules matching more than a domain, see `network.rs`
+    #[serde(default)]
+    pub network_rules: usize,
+
+    /// Element hiding rules, see `cosmetic.rs`
+    #[serde(default)]
+    pub cosmetic_rules: usize,
+
+    pub invalid_lines: usize,
+}
+
+impl SubscriptionState {
+    pub fn load(subscription: &Subscription) -> Self {
+        let file = subscription.file("state.toml");
+        let content = match std::fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(_) => return Self::default(),
+        };
+
+        toml::from_str(&content).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring invalid subscription state {:?}: {}", file, e);
+            Self::default()
+        })
+    }
+
+    fn save(
+        &self,
+        subscription: &Subscription,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let file = subscription.file("state.toml");
+        let temp_path = file.with_extension("tmp");
+        std::fs::create_dir_all(SUBSCRIPTIONS_PATH.as_path())?;
+        std::fs::write(&temp_path, toml::to_string(self)?)?;
+        std::fs::rename(&temp_path, &file)?;
+        Ok(())
+    }
+}
+
+/// Entries of a subscription, as of its last successful download.
+#[derive(Debug, Clone)]
+pub struct SubscriptionLists {
+    pub name: String,
+    pub blacklist: ExactSet,
+    pub whitelist: ExactSet,
+}
+
+impl SubscriptionLists {
+    /// Map the entries of a subscription, `None` before its first download.
+    pub fn load(subscription: &Subscription) -> Option<Self> {
+        let blacklist = subscription.file("blacklist.fst");
+        let whitelist = subscription.file("whitelist.fst");
+        if !blacklist.exists() || !whitelist.exists() {
+            return None;
+        }
+
+        match (read_set(&blacklist), read_set(&whitelist)) {
+            (Ok(blacklist), Ok(whitelist)) => Some(Self {
+                name: subscription.name.clone(),
+                blacklist,
+                whitelist,
+            }),
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::warn!(
+                    "Ignoring the entries of subscription '{}': {}",
+                    subscription.name,
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+/// File of the network rules of a subscription.
+pub fn network_rules_file(subscription: &Subscription) -> PathBuf {
+    subscription.file("network.txt")
+}
+
+/// File of the element hiding rules of a subscription.
+pub fn cosmetic_rules_file(subscription: &Subscription) -> PathBuf {
+    subscription.file("cosmetic.txt")
+}
+
+/// Delete the files of a subscription.
+pub fn remove_subscription_files(subscription: &Subscription) {
+    for suffix in [
+        "blacklist.fst",
+        "whitelist.fst",
+        "network.txt",
+        "cosmetic.txt",
+        "state.toml",
+    ] {
+        let file = subscription.file(suffix);
+        if let Err(e) = std::fs::remove_file(&file)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            tracing::warn!("Unable to remove {:?}: {}", file, e);
+        }
+    }
+}
+
+enum Download {
+    NotModified,
+    Updated {
+        content: String,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+}
+
+async fn download(
+    subscription: &Subscription,
+    state: &SubscriptionState,
+) -> Result<Download, Box<dyn std::error::Error + Send + Sync>> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(DOWNLOAD_TIMEOUT_SECS))
+        .user_agent(concat!("network-administrator/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+
+    let mut request = client.get(&subscription.url);
+    if let Some(etag) = &state.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &state.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let response = request.send().await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Download::NotModified);
+    }
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()).into());
+    }
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+    let content = String::from_utf8_lossy(&response.bytes().await?).into_owned();
+
+    Ok(Download::Updated {
+        content,
+        etag,
+        last_modified,
+    })
+}
+
+/// Parse a downloaded list and compile its entries into the files of the subscription.
+fn store_lists(
+    subscription: &Subscription,
+    state: &mut SubscriptionState,
+    content: &str,
+) -> Result<SubscriptionLists, Box<dyn std::error::Error + Send + Sync>> {
+    let report = parse_list(&subscription.url, content, subscription.format);
+
+    // An error page served with a 200 would otherwise silently unblock everything
+    if report.blacklist.is_empty()
+        && report.whitelist.is_empty()
+        && report.network_rules.is_empty()
+        && report.cosmetic_rules.is_empty()
+        && state.blacklist_entries
+            + state.whitelist_entries
+            + state.network_rules
+            + state.cosmetic_rules
+            > 0
+    {
+        return Err(format!(
+            "The list has no entries anymore ({} invalid lines), keeping the previous version",
+            report.invalid.len()
+        )
+        .into());
+    }
+
+    std::fs::create_dir_all(SUBSCRIPTIONS_PATH.as_path())?;
+    write_set(&subscription.file("blacklist.fst"), &report.blacklist)?;
+    write_set(&subscription.file("whitelist.fst"), &report.whitelist)?;
+
+    for (file, rules) in [
+        (network_rules_file(subscription), &report.network_rules),
+        (cosmetic_rules_file(subscription), &report.cosmetic_rules),
+    ] {
+        let temp_path = file.with_extension("tmp");
+        std::fs::write(&temp_path, rules.join("\n"))?;
+        std::fs::rename(&temp_path, &file)?;
+    }
+
+    state.blacklist_entries = report.blacklist.len();
+    state.whitelist_entries = report.whitelist.len();
+    state.network_rules = report.network_rules.len();
+    state.cosmetic_rules = report.cosmetic_rules.len();
+    state.invalid_lines = report.invalid.len();
+
+    SubscriptionLists::load(subscription).ok_or_else(|| "Unable to map the stored entries".into())
+}
+
+/// Refresh a subscription now. The new entries are handed to `install`, the returned state
+/// tells how the refresh went.
+pub async fn refresh_subscription<F>(subscription: &Subscription, install: F) -> SubscriptionState
+where
+    F: FnOnce(SubscriptionLists),
+{
+    let _refresh_guard = REFRESH_LOCK.lock().await;
+    let mut state = SubscriptionState::load(subscription);
+    state.last_checked = Some(now_secs());
+
+    let result = match download(subscription, &state).await {
+        Ok(Download::NotModified) => {
+            tracing::info!("Subscription '{}' is up to date", subscription.name);
+            Ok(())
+        }
+        Ok(Download::Updated {
+            content,
+            etag,
+            last_modified,
+        }) => {
+            // Parsing and compiling a large list is CPU bound
+            let task_subscription = subscription.clone();
+            let mut task_state = state.clone();
+            let stored = tokio::task::spawn_blocking(move || {
+                store_lists(&task_subscription, &mut task_state, &content)
+                    .map(|lists| (lists, task_state))
+            })
+            .await;
+
+            match stored {
+                Ok(Ok((lists, stored_state))) => {
+                    state = SubscriptionState {
+                        etag,
+                        last_modified,
+                        last_updated: state.last_checked,
+                        ..stored_state
+                    };
+                    tracing::info!(
+                        "Subscription '{}' updated: {} blocked and {} allowed domains, {} network and {} cosmetic rules, {} invalid lines",
+                        subscription.name,
+                        state.blacklist_entries,
+                        state.whitelist_entries,
+                        state.network_rules,
+                        state.cosmetic_rules,
+                        state.invalid_lines
+                    );
+                    install(lists);
+                    Ok(())
+                }
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    };
+
+    state.last_error = result.err();
+    if let Some(e) = &state.last_error {
+        tracing::warn!(
+            "Unable to refresh subscription '{}': {}",
+            subscription.name,
+            e
+        );
+    }
+    if let Err(e) = state.save(subscription) {
+        tracing::warn!(
+            "Unable to save the state of subscription '{}': {}",
+            subscription.name,
+            e
+        );
+    }
+
+    state
+}
+
+/// Refresh the subscriptions that are due until shutdown. `subscriptions` returns the current
+/// ones, so changes to the filter file apply without a restart.
+pub async fn run_subscription_schedule<S, F>(subscriptions: S, install: F)
+where
+    S: Fn() -> Vec<Subscription>,
+    F: Fn(SubscriptionLists),
+{
+    let shutdown = shutdown_token();
+
+    loop {
+        let now = now_secs();
+        for subscription in subscriptions() {
+            if subscription.is_due(&SubscriptionState::load(&subscription), now) {
+                refresh_subscription(&subscription, &install).await;
+            }
+        }
+
+        tokio::select! {
+            _ = TokioTime::sleep(Duration::from_secs(SCHEDULE_CHECK_SECS)) => {}
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+
+    use axum::{
+        Router,
+        extract::State,
+        http::{HeaderMap, StatusCode, header},
+        response::{IntoResponse, Response},
+        routing::get,
+    };
+
+    use super::*;
+    use crate::filters::{
+        add_subscription, domain_filter::DOMAIN_FILTER, refresh_subscription_now,
+        remove_subscription,
+    };
+
+    const LIST: &str = "0.0.0.0 ads.example\n0.0.0.0 tracker.example\n";
+    const LIST_ETAG: &str = "\"v1\"";
+    const LIST_LAST_MODIFIED: &str = "Mon, 12 Oct 2026 08:00:00 GMT";
+
+    /// Stands in for the host of a list.
+    #[derive(Default)]
+    struct ListHost {
+        failing: AtomicBool,
+        downloads: AtomicUsize,
+        not_modified: AtomicUsize,
+        conditional_headers: Mutex<Vec<(Option<String>, Option<String>)>>,
+    }
+
+    async fn serve_list(State(host): State<Arc<ListHost>>, headers: HeaderMap) -> Response {
+        if host.failing.load(Ordering::SeqCst) {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let if_none_match = header(header::IF_NONE_MATCH);
+        let if_modified_since = header(header::IF_MODIFIED_SINCE);
+        host.conditional_headers
+            .lock()
+            .unwrap()
+            .push((if_none_match.clone(), if_modified_since));
+
+        if if_none_match.as_deref() == Some(LIST_ETAG) {
+            host.not_modified.fetch_add(1, Ordering::SeqCst);
+            return StatusCode::NOT_MODIFIED.into_response();
+        }
+
+        host.downloads.fetch_add(1, Ordering::SeqCst);
+        (
+            [
+                (header::ETAG, LIST_ETAG),
+                (header::LAST_MODIFIED, LIST_LAST_MODIFIED),
+            ],
+            LIST,
+        )
+            .into_response()
+    }
+
+    fn is_blacklisted(domain: &str) -> bool {
+        DOMAIN_FILTER.load().is_listed(domain, true).is_some()
+    }
+
+    #[tokio::test]
+    async fn refreshes_a_subscription_from_its_host() {
+        // The filter and the subscription files live under `./.config`
+        let dir = std::env::temp_dir().join(format!("na-subscriptions-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join(".config")).unwrap();
+        std::fs::write(dir.join(".config/filter.toml"), "[blacklist]\n").unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+
+        let host = Arc::new(ListHost::default());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/list.txt", listener.local_addr().unwrap());
+        let app = Router::new()
+            .route("/list.txt", get(serve_list))
+            .with_state(host.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let subscription = Subscription {
+            name: "local".to_string(),
+            url,
+            format: ListFormat::Hosts,
+            interval_hours: 1,
+        };
+        add_subscription(subscription.clone()).unwrap();
+        assert!(!is_blacklisted("ads.example"));
+
+        // First download
+        let status = refresh_subscription_now("local").await.unwrap();
+        assert_eq!(status.state.last_error, None);
+        assert_eq!(status.state.blacklist_entries, 2);
+        assert_eq!(status.state.etag.as_deref(), Some(LIST_ETAG));
+        assert_eq!(
+            status.state.last_modified.as_deref(),
+            Some(LIST_LAST_MODIFIED)
+        );
+        assert!(status.state.last_updated.is_some());
+        assert!(is_blacklisted("ads.example"));
+        assert!(is_blacklisted("tracker.example"));
+        assert_eq!(host.downloads.load(Ordering::SeqCst), 1);
+        let first_update = status.state.last_updated;
+
+        // Unchanged list, answered with a 304
+        let status = refresh_subscription_now("local").await.unwrap();
+        assert_eq!(status.state.last_error, None);
+        assert_eq!(host.not_modified.load(Ordering::SeqCst), 1);
+        assert_eq!(host.downloads.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            host.conditional_headers.lock().unwrap().last(),
+            Some(&(
+                Some(LIST_ETAG.to_string()),
+                Some(LIST_LAST_MODIFIED.to_string())
+            ))
+        );
+        assert_eq!(status.state.last_updated, first_update);
+        assert_eq!(status.state.blacklist_entries, 2);
+        assert!(is_blacklisted("ads.example"));
+
+        // A failed refresh keeps the previous list
+        host.failing.store(true, Ordering::SeqCst);
+        let status = refresh_subscription_now("local").await.unwrap();
+        assert!(status.state.last_error.is_some());
+        assert_eq!(status.state.blacklist_entries, 2);
+        assert_eq!(status.state.last_updated, first_update);
+        assert!(is_blacklisted("ads.example"));
+        assert!(SubscriptionState::load(&subscription).last_error.is_some());
+
+        // Removing the subscription drops its entries and files
+        remove_subscription("local").unwrap();
+        assert!(!is_blacklisted("ads.example"));
+        assert!(!is_blacklisted("tracker.example"));
+        assert!(!subscription.file("blacklist.fst").exists());
+        assert!(!subscription.file("state.toml").exists());
+        assert!(refresh_subscription_now("local").await.is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
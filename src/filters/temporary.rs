@@ -0,0 +1,208 @@
+// Temporary rules: a domain allowed, or ad blocking paused for one client, for a bounded time.
+// They only live in memory, are never written to the filter file and stop applying as soon as
+// they expire. A restart drops them.
+
+use std::{
+    net::IpAddr,
+    sync::{
+        LazyLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::FilterError;
+use crate::utils::{authority::normalize_hostname, now_secs};
+
+/// Used when a rule is added without a duration.
+pub const DEFAULT_TEMPORARY_RULE_SECS: u64 = 600;
+
+/// Longer exceptions belong in the filter file.
+pub const MAX_TEMPORARY_RULE_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TemporaryTarget {
+    /// The domain and its subdomains are treated as whitelisted
+    AllowDomain { domain: String },
+
+    /// Ad blocking is off for every request of the client
+    PauseClient { client: IpAddr },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TemporaryRule {
+    pub id: u64,
+
+    #[serde(flatten)]
+    pub target: TemporaryTarget,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+
+    /// Unix timestamp in seconds when the rule stops applying
+    pub expires_at: u64,
+}
+
+impl TemporaryRule {
+    fn is_active(&self, now: u64) -> bool {
+        self.expires_at > now
+    }
+}
+
+static TEMPORARY_RULES: LazyLock<ArcSwap<Vec<TemporaryRule>>> =
+    LazyLock::new(|| ArcSwap::from_pointee(Vec::new()));
+
+static NEXT_RULE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Add a temporary rule, replacing the one with the same target so a rule can be extended.
+pub fn add_temporary_rule(
+    target: TemporaryTarget,
+    duration_secs: u64,
+    comment: Option<String>,
+) -> Result<TemporaryRule, FilterError> {
+    if duration_secs == 0 || duration_secs > MAX_TEMPORARY_RULE_SECS {
+        return Err(FilterError::InvalidTemporaryRule(format!(
+            "The duration must be between 1 and {} seconds",
+            MAX_TEMPORARY_RULE_SECS
+        )));
+    }
+
+    let target = match target {
+        TemporaryTarget::AllowDomain { domain } => {
+            let domain = normalize_hostname(&domain);
+            if domain.is_empty() || domain.contains(['/', '*', ' ']) {
+                return Err(FilterError::InvalidTemporaryRule(format!(
+                    "'{}' is not a domain",
+                    domain
+                )));
+            }
+            TemporaryTarget::AllowDomain { domain }
+        }
+        TemporaryTarget::PauseClient { client } => TemporaryTarget::PauseClient {
+            client: client.to_canonical(),
+        },
+    };
+
+    let now = now_secs();
+    let rule = TemporaryRule {
+        id: NEXT_RULE_ID.fetch_add(1, Ordering::Relaxed),
+        target,
+        comment: comment.filter(|comment| !comment.trim().is_empty()),
+        created_at: now,
+        expires_at: now + duration_secs,
+    };
+
+    TEMPORARY_RULES.rcu(|rules| {
+        let mut rules = rules
+            .iter()
+            .filter(|other| other.is_active(now) && other.target != rule.target)
+            .cloned()
+            .collect::<Vec<_>>();
+        rules.push(rule.clone());
+        rules
+    });
+
+    tracing::info!(
+        "Added temporary rule {} ({:?}) until {}",
+        rule.id,
+        rule.target,
+        rule.expires_at
+    );
+    Ok(rule)
+}
+
+/// Revert a temporary rule before it expires.
+pub fn remove_temporary_rule(id: u64) -> Result<TemporaryRule, FilterError> {
+    let now = now_secs();
+    let previous = TEMPORARY_RULES.rcu(|rules| {
+        rules
+            .iter()
+            .filter(|rule| rule.is_active(now) && rule.id != id)
+            .cloned()
+            .collect::<Vec<_>>()
+    });
+
+    previous
+        .iter()
+        .find(|rule| rule.id == id && rule.is_active(now))
+        .cloned()
+        .ok_or(FilterError::TemporaryRuleNotFound(id))
+}
+
+/// The rules that have not expired, soonest expiry first.
+pub fn get_temporary_rules() -> Vec<TemporaryRule> {
+    let now = now_secs();
+    let mut rules = TEMPORARY_RULES
+        .load()
+        .iter()
+        .filter(|rule| rule.is_active(now))
+        .cloned()
+        .collect::<Vec<_>>();
+    rules.sort_unstable_by_key(|rule| (rule.expires_at, rule.id));
+    rules
+}
+
+/// Drop the expired rules, returning them. They already stopped applying, this only frees them.
+pub fn prune_temporary_rules(now: u64) -> Vec<TemporaryRule> {
+    if TEMPORARY_RULES
+        .load()
+        .iter()
+        .all(|rule| rule.is_active(now))
+    {
+        return Vec::new();
+    }
+
+    let previous = TEMPORARY_RULES.rcu(|rules| {
+        rules
+            .iter()
+            .filter(|rule| rule.is_active(now))
+            .cloned()
+            .collect::<Vec<_>>()
+    });
+    previous
+        .iter()
+        .filter(|rule| !rule.is_active(now))
+        .cloned()
+        .collect()
+}
+
+fn find_active(matches: impl Fn(&TemporaryTarget) -> bool) -> Option<TemporaryRule> {
+    let rules = TEMPORARY_RULES.load();
+    if rules.is_empty() {
+        return None;
+    }
+
+    let now = now_secs();
+    rules
+        .iter()
+        .find(|rule| rule.is_active(now) && matches(&rule.target))
+        .cloned()
+}
+
+/// The rule allowing the domain, which also covers its subdomains.
+pub fn temporary_allow(domain: &str) -> Option<TemporaryRule> {
+    find_active(|target| match target {
+        TemporaryTarget::AllowDomain { domain: allowed } => {
+            let start = domain.len().saturating_sub(allowed.len());
+            domain
+                .get(start..)
+                .is_some_and(|suffix| suffix.eq_ignore_ascii_case(allowed))
+                && (start == 0 || domain.as_bytes()[start - 1] == b'.')
+        }
+        TemporaryTarget::PauseClient { .. } => false,
+    })
+}
+
+/// The rule pausing ad blocking for the client.
+pub fn blocking_pause(client: IpAddr) -> Option<TemporaryRule> {
+    let client = client.to_canonical();
+    find_active(
+        |target| matches!(target, TemporaryTarget::PauseClient { client: paused } if *paused == client),
+    )
+}
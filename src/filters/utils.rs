@@ -1,53 +1,167 @@
-use std::path::PathBuf;
+use std::{net::IpAddr, path::PathBuf};
 
-use super::domain_filter::{DOMAIN_FILTER, DomainFilter, ListConfigType};
+use serde::Serialize;
+
+use crate::errors::FilterError;
+
+use super::cosmetic::{add_cosmetic_rules, reload_cosmetic_filter};
+use super::domain_filter::{
+    DOMAIN_FILTER, DomainFilter, ListConfigType, ListMatch, MatchMode, edit_domain_filter,
+    install_subscription_lists, reload_domain_filter, update_domain_filter,
+};
+use super::importer::ImportReport;
+use super::journal::{JournalEntry, JournalOp};
+use super::metadata::{
+    RuleAnnotation, RuleKey, RuleRef, RuleStats, get_rule_stats, record_hit, run_rule_maintenance,
+};
+use super::network::{add_network_rules, reload_network_filter};
+use super::subscriptions::{
+    Subscription, SubscriptionLists, SubscriptionState, refresh_subscription,
+    remove_subscription_files, run_subscription_schedule,
+};
+use super::temporary::{blocking_pause, temporary_allow};
+
+fn edit(
+    op: JournalOp,
+    domain: &str,
+    list_type: ListConfigType,
+    is_blacklisted: bool,
+    annotation: Option<RuleAnnotation>,
+) -> Result<(), FilterError> {
+    edit_domain_filter(JournalEntry {
+        op,
+        list_type,
+        is_blacklisted,
+        value: domain.to_string(),
+        annotation,
+    })
+}
 
 pub fn add_domain_to_blacklist(
     domain: &str,
     list_type: ListConfigType,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut filter = DOMAIN_FILTER.write().unwrap();
-    filter.add_domain(domain, list_type, true)
+    annotation: Option<RuleAnnotation>,
+) -> Result<(), FilterError> {
+    edit(JournalOp::Add, domain, list_type, true, annotation)
 }
 
 pub fn add_domain_to_whitelist(
     domain: &str,
     list_type: ListConfigType,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut filter = DOMAIN_FILTER.write().unwrap();
-    filter.add_domain(domain, list_type, false)
+    annotation: Option<RuleAnnotation>,
+) -> Result<(), FilterError> {
+    edit(JournalOp::Add, domain, list_type, false, annotation)
+}
+
+/// Count a hit of the entry a lookup matched, telling whether one did.
+fn record_match(rule: Option<RuleRef<'_>>) -> bool {
+    match rule {
+        Some(rule) => {
+            record_hit(rule);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Whether ad blocking is paused for the client by a temporary rule.
+pub fn is_blocking_paused(client: IpAddr) -> bool {
+    blocking_pause(client).is_some()
 }
 
 pub fn is_domain_blacklisted(domain: &str) -> bool {
-    let filter = DOMAIN_FILTER.read().unwrap();
-    filter.is_listed(domain, true)
+    record_match(DOMAIN_FILTER.load().is_listed(domain, true))
+}
+
+/// Whether requests to the domain are blocked, taking the whitelist into account.
+pub fn is_domain_blocked(domain: &str) -> bool {
+    if temporary_allow(domain).is_some() {
+        return false;
+    }
+
+    record_match(DOMAIN_FILTER.load().is_blocked(domain))
 }
 
+/// Whether the domain is whitelisted, by the filter or a temporary rule.
 pub fn is_domain_whitelisted(domain: &str) -> bool {
-    let filter = DOMAIN_FILTER.read().unwrap();
-    filter.is_listed(domain, false)
+    if temporary_allow(domain).is_some() {
+        return true;
+    }
+
+    record_match(DOMAIN_FILTER.load().is_listed(domain, false))
+}
+
+/// Entries of both lists matching the domain, see [`DomainFilter::explain`].
+pub fn explain_domain(domain: &str) -> Vec<ListMatch> {
+    DOMAIN_FILTER.load().explain(domain)
 }
 
 pub fn remove_domain_from_blacklist(
     domain: &str,
     list_type: ListConfigType,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut filter = DOMAIN_FILTER.write().unwrap();
-    filter.remove_domain(domain, list_type, true)
+) -> Result<(), FilterError> {
+    edit(JournalOp::Remove, domain, list_type, true, None)
 }
 
 pub fn remove_domain_from_whitelist(
     domain: &str,
     list_type: ListConfigType,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut filter = DOMAIN_FILTER.write().unwrap();
-    filter.remove_domain(domain, list_type, false)
+) -> Result<(), FilterError> {
+    edit(JournalOp::Remove, domain, list_type, false, None)
+}
+
+/// An entry of a list with its annotation and statistics.
+#[derive(Debug, Serialize)]
+pub struct ListEntryDetails {
+    pub value: String,
+
+    #[serde(flatten)]
+    pub annotation: Option<RuleAnnotation>,
+
+    #[serde(flatten)]
+    pub stats: RuleStats,
+}
+
+/// The entries of a list with their metadata, see [`get_blacklist`] and [`get_whitelist`].
+pub fn get_list_details(is_blacklist: bool, config_type: ListConfigType) -> Vec<ListEntryDetails> {
+    let entries = match is_blacklist {
+        true => get_blacklist(config_type),
+        false => get_whitelist(config_type),
+    };
+    let filter = DOMAIN_FILTER.load();
+
+    entries
+        .into_iter()
+        .map(|value| {
+            let key = RuleKey::new(is_blacklist, config_type, &value);
+            ListEntryDetails {
+                annotation: filter.annotation(&key),
+                stats: get_rule_stats(&key),
+                value,
+            }
+        })
+        .collect()
+}
+
+/// Remove the expired entries of the filter and write the hit statistics until shutdown.
+pub async fn run_filter_maintenance() {
+    run_rule_maintenance(|key| {
+        edit(
+            JournalOp::Remove,
+            &key.entry,
+            key.config_type,
+            key.is_blacklist,
+            None,
+        )
+        .map_err(Into::into)
+    })
+    .await
 }
 
 pub fn get_blacklist(config_type: ListConfigType) -> Vec<String> {
-    let filter = DOMAIN_FILTER.read().unwrap();
+    let filter = DOMAIN_FILTER.load();
     match config_type {
-        ListConfigType::Exact => filter.blacklist_exact.iter().cloned().collect(),
+        ListConfigType::Exact => filter.blacklist_exact.to_sorted_vec(),
         ListConfigType::Wildcard => filter.blacklist_wildcards.iter().cloned().collect(),
         ListConfigType::Regex => filter
             .blacklist_regex
@@ -58,9 +172,9 @@ pub fn get_blacklist(config_type: ListConfigType) -> Vec<String> {
 }
 
 pub fn get_whitelist(config_type: ListConfigType) -> Vec<String> {
-    let filter = DOMAIN_FILTER.read().unwrap();
+    let filter = DOMAIN_FILTER.load();
     match config_type {
-        ListConfigType::Exact => filter.whitelist_exact.iter().cloned().collect(),
+        ListConfigType::Exact => filter.whitelist_exact.to_sorted_vec(),
         ListConfigType::Wildcard => filter.whitelist_wildcards.iter().cloned().collect(),
         ListConfigType::Regex => filter
             .whitelist_regex
@@ -70,22 +184,160 @@ pub fn get_whitelist(config_type: ListConfigType) -> Vec<String> {
     }
 }
 
+/// Load the network and element hiding rules again, after the subscriptions changed.
+fn reload_adblock_rules() {
+    reload_network_filter();
+    reload_cosmetic_filter();
+}
+
 /// Merge entries from an external file into the current filter.
-pub fn merge_from_file(file: PathBuf) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+pub fn merge_from_file(file: PathBuf) -> Result<(), FilterError> {
     // Load and validate the file first (fails fast if invalid)
     let other_filter = DomainFilter::load(Some(file))?;
 
-    // Apply changes with write lock
-    let mut filter = DOMAIN_FILTER.write().unwrap();
-    filter.merge(&other_filter)
+    update_domain_filter(true, |filter| {
+        filter.merge(&other_filter);
+        Ok(())
+    })?;
+    reload_adblock_rules();
+    Ok(())
 }
 
 /// Replace the entire filter from an external file.
-pub fn replace_from_file(file: PathBuf) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+pub fn replace_from_file(file: PathBuf) -> Result<(), FilterError> {
     // Load and validate the file first
     let new_filter = DomainFilter::load(Some(file))?;
 
-    // Replace with write lock
-    let mut filter = DOMAIN_FILTER.write().unwrap();
-    filter.replace(&new_filter)
+    update_domain_filter(true, |filter| {
+        filter.replace(&new_filter);
+        Ok(())
+    })?;
+    reload_adblock_rules();
+    Ok(())
+}
+
+/// Entries an import added to the filter.
+#[derive(Debug, Serialize)]
+pub struct ImportResult {
+    pub blacklist_added: usize,
+    pub whitelist_added: usize,
+    pub network_rules_added: usize,
+    pub cosmetic_rules_added: usize,
+
+    /// Whether the blacklist matches subdomains, which AdBlock, dnsmasq and unbound rules expect
+    pub blacklist_mode: MatchMode,
+}
+
+/// Add the domains of an import to the exact lists, see [`DomainFilter::import_exact`], and its
+/// network and element hiding rules to their rules files.
+pub fn import_domains(report: &ImportReport, replace: bool) -> Result<ImportResult, FilterError> {
+    let mut added = (0, 0);
+    if !report.blacklist.is_empty() || !report.whitelist.is_empty() {
+        update_domain_filter(true, |filter| {
+            added = filter.import_exact(&report.blacklist, &report.whitelist, replace);
+            Ok(())
+        })?;
+    }
+    let network_rules_added = add_network_rules(&report.network_rules)
+        .map_err(|e| FilterError::Persist(e.to_string()))?;
+    let cosmetic_rules_added = add_cosmetic_rules(&report.cosmetic_rules)
+        .map_err(|e| FilterError::Persist(e.to_string()))?;
+
+    Ok(ImportResult {
+        blacklist_added: added.0,
+        whitelist_added: added.1,
+        network_rules_added,
+        cosmetic_rules_added,
+        blacklist_mode: DOMAIN_FILTER.load().blacklist_mode,
+    })
+}
+
+/// Open the filter file without touching the active filter, to fail early on an invalid file.
+/// This compiles it when needed, so the active filter only maps the compiled file afterwards.
+pub fn check_filter_file() -> Result<(), FilterError> {
+    DomainFilter::open(None).map(|_| ())
+}
+
+pub fn get_filter_file() -> PathBuf {
+    DOMAIN_FILTER.load().file.clone()
+}
+
+/// Load the filter file again and swap it in. The active filter stays in place when the file is
+/// invalid.
+pub fn reload_filter_file() -> Result<(), FilterError> {
+    reload_domain_filter(get_filter_file())?;
+    reload_adblock_rules();
+    Ok(())
+}
+
+/// A subscription with the outcome of its refreshes.
+#[derive(Debug, Serialize)]
+pub struct SubscriptionStatus {
+    #[serde(flatten)]
+    pub subscription: Subscription,
+
+    #[serde(flatten)]
+    pub state: SubscriptionState,
+}
+
+pub fn get_subscriptions() -> Vec<SubscriptionStatus> {
+    DOMAIN_FILTER
+        .load()
+        .subscriptions
+        .iter()
+        .map(|subscription| SubscriptionStatus {
+            subscription: subscription.clone(),
+            state: SubscriptionState::load(subscription),
+        })
+        .collect()
+}
+
+pub fn add_subscription(subscription: Subscription) -> Result<(), FilterError> {
+    update_domain_filter(true, |filter| filter.add_subscription(subscription))
+}
+
+/// Remove a subscription, its entries stop matching right away.
+pub fn remove_subscription(name: &str) -> Result<(), FilterError> {
+    let mut removed = None;
+    update_domain_filter(true, |filter| {
+        removed = Some(filter.remove_subscription(name)?);
+        Ok(())
+    })?;
+
+    if let Some(subscription) = removed {
+        remove_subscription_files(&subscription);
+        reload_adblock_rules();
+    }
+    Ok(())
+}
+
+fn install_subscription(lists: SubscriptionLists) {
+    install_subscription_lists(lists);
+    reload_adblock_rules();
+}
+
+/// Download a subscription now, whatever its schedule.
+pub async fn refresh_subscription_now(name: &str) -> Result<SubscriptionStatus, FilterError> {
+    let subscription = DOMAIN_FILTER
+        .load()
+        .subscriptions
+        .iter()
+        .find(|s| s.name == name)
+        .cloned()
+        .ok_or_else(|| FilterError::SubscriptionNotFound(name.to_string()))?;
+
+    let state = refresh_subscription(&subscription, install_subscription).await;
+    Ok(SubscriptionStatus {
+        subscription,
+        state,
+    })
+}
+
+/// Refresh the subscriptions of the active filter as they fall due, until shutdown.
+pub async fn run_subscription_refresh() {
+    run_subscription_schedule(
+        || DOMAIN_FILTER.load().subscriptions.clone(),
+        install_subscription,
+    )
+    .await
 }
@@ -1,12 +1,20 @@
+pub mod acl;
 pub mod admin;
 pub mod ads;
+pub mod auth;
 pub mod cli;
 pub mod client;
 pub mod config;
+pub mod dns;
+pub mod errors;
 pub mod filters;
+pub mod limits;
 pub mod logging;
 pub mod proxy;
+pub mod reload;
+pub mod reverse;
 pub mod scan;
 pub mod schemas;
 pub mod server;
+pub mod shutdown;
 pub mod utils;
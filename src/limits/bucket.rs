@@ -0,0 +1,103 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::settings::get_global_limits;
+
+const MIN_CHUNK_BYTES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficDirection {
+    /// Client to server
+    Upload,
+    /// Server to client
+    Download,
+}
+
+/// Token bucket allowing up to one second worth of burst. Consuming more tokens than
+/// available leaves the bucket in debt, and callers wait until it is paid back.
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new() -> Self {
+        Self {
+            tokens: 0.0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, rate: u64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+        self.last_refill = now;
+    }
+
+    fn consume(&mut self, bytes: usize, rate: Option<u64>) {
+        if let Some(rate) = rate {
+            self.refill(rate);
+            self.tokens -= bytes as f64;
+        }
+    }
+
+    fn wait_time(&mut self, rate: Option<u64>) -> Option<Duration> {
+        let rate = rate?;
+        self.refill(rate);
+
+        match self.tokens >= 0.0 {
+            true => None,
+            false => Some(Duration::from_secs_f64(-self.tokens / rate as f64)),
+        }
+    }
+}
+
+/// Upload and download buckets shared by every connection of a client IP.
+#[derive(Debug)]
+pub struct ClientLimiter {
+    upload: Mutex<TokenBucket>,
+    download: Mutex<TokenBucket>,
+}
+
+impl ClientLimiter {
+    pub fn new() -> Self {
+        Self {
+            upload: Mutex::new(TokenBucket::new()),
+            download: Mutex::new(TokenBucket::new()),
+        }
+    }
+
+    fn bucket_and_rate(&self, direction: TrafficDirection) -> (&Mutex<TokenBucket>, Option<u64>) {
+        let limits = get_global_limits();
+        match direction {
+            TrafficDirection::Upload => (&self.upload, limits.upload_bytes_per_sec),
+            TrafficDirection::Download => (&self.download, limits.download_bytes_per_sec),
+        }
+    }
+
+    pub fn consume(&self, direction: TrafficDirection, bytes: usize) {
+        let (bucket, rate) = self.bucket_and_rate(direction);
+        bucket.lock().unwrap().consume(bytes, rate);
+    }
+
+    /// Largest transfer allowed in a single read or write (100ms worth of bandwidth), so one
+    /// large write can't send a whole response before the bucket gets a chance to throttle it.
+    pub fn max_chunk(&self, direction: TrafficDirection) -> Option<usize> {
+        let (_, rate) = self.bucket_and_rate(direction);
+        rate.map(|rate| ((rate / 10) as usize).max(MIN_CHUNK_BYTES))
+    }
+
+    /// How long to wait before transferring more bytes, `None` if it can proceed right away.
+    pub fn wait_time(&self, direction: TrafficDirection) -> Option<Duration> {
+        let (bucket, rate) = self.bucket_and_rate(direction);
+        bucket.lock().unwrap().wait_time(rate)
+    }
+}
+
+impl Default for ClientLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
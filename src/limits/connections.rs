@@ -0,0 +1,101 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use serde::Serialize;
+
+use super::bucket::ClientLimiter;
+use super::settings::get_global_limits;
+
+#[derive(Debug, Default)]
+struct ClientEntry {
+    connections: usize,
+    limiter: Arc<ClientLimiter>,
+}
+
+#[derive(Debug, Default)]
+struct ConnectionRegistry {
+    clients: HashMap<IpAddr, ClientEntry>,
+    total: usize,
+}
+
+static CONNECTIONS: LazyLock<Mutex<ConnectionRegistry>> =
+    LazyLock::new(|| Mutex::new(ConnectionRegistry::default()));
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionStats {
+    pub total: usize,
+    pub per_client: HashMap<String, usize>,
+}
+
+/// Keeps a connection slot reserved for a client until dropped.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut registry = CONNECTIONS.lock().unwrap();
+        registry.total = registry.total.saturating_sub(1);
+
+        if let Some(entry) = registry.clients.get_mut(&self.ip) {
+            entry.connections = entry.connections.saturating_sub(1);
+            if entry.connections == 0 {
+                registry.clients.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Reserve a connection slot for the client, failing if the global or per-client limit is reached.
+pub fn try_acquire_connection(ip: IpAddr) -> Result<ConnectionGuard, String> {
+    let ip = ip.to_canonical();
+    let limits = get_global_limits();
+    let mut registry = CONNECTIONS.lock().unwrap();
+
+    if let Some(max) = limits.max_connections
+        && registry.total >= max
+    {
+        return Err(format!("Global connection limit of {} reached", max));
+    }
+
+    let entry = registry.clients.entry(ip).or_default();
+    if let Some(max) = limits.max_connections_per_client
+        && entry.connections >= max
+    {
+        return Err(format!("Connection limit of {} reached for {}", max, ip));
+    }
+
+    entry.connections += 1;
+    registry.total += 1;
+
+    Ok(ConnectionGuard { ip })
+}
+
+/// Bandwidth limiter shared by every open connection of the client.
+pub fn client_limiter(ip: IpAddr) -> Arc<ClientLimiter> {
+    let ip = ip.to_canonical();
+    let registry = CONNECTIONS.lock().unwrap();
+
+    registry
+        .clients
+        .get(&ip)
+        .map(|entry| entry.limiter.clone())
+        .unwrap_or_default()
+}
+
+pub fn get_connection_stats() -> ConnectionStats {
+    let registry = CONNECTIONS.lock().unwrap();
+
+    ConnectionStats {
+        total: registry.total,
+        per_client: registry
+            .clients
+            .iter()
+            .map(|(ip, entry)| (ip.to_string(), entry.connections))
+            .collect(),
+    }
+}
@@ -0,0 +1,13 @@
+// Per-client bandwidth throttling (token buckets per client IP) and concurrent connection limits.
+
+mod bucket;
+mod connections;
+mod settings;
+mod stream;
+
+pub use bucket::{ClientLimiter, TrafficDirection};
+pub use connections::{
+    ConnectionGuard, ConnectionStats, client_limiter, get_connection_stats, try_acquire_connection,
+};
+pub use settings::{LimitsConfig, get_global_limits, set_global_limits};
+pub use stream::ThrottledStream;
@@ -0,0 +1,51 @@
+use std::sync::{LazyLock, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+/// Bandwidth and connection limits, `None` meaning unlimited. They are read on every
+/// throttling decision, so updates apply to connections that are already open.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    /// Maximum client to server bytes per second, per client IP.
+    #[serde(default)]
+    pub upload_bytes_per_sec: Option<u64>,
+
+    /// Maximum server to client bytes per second, per client IP.
+    #[serde(default)]
+    pub download_bytes_per_sec: Option<u64>,
+
+    /// Maximum concurrent connections per client IP.
+    #[serde(default)]
+    pub max_connections_per_client: Option<usize>,
+
+    /// Maximum concurrent connections across all clients.
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+}
+
+impl LimitsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.upload_bytes_per_sec == Some(0) || self.download_bytes_per_sec == Some(0) {
+            return Err("Bandwidth limits must be greater than zero".to_string());
+        }
+
+        if self.max_connections_per_client == Some(0) || self.max_connections == Some(0) {
+            return Err("Connection limits must be greater than zero".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+static GLOBAL_LIMITS: LazyLock<RwLock<LimitsConfig>> =
+    LazyLock::new(|| RwLock::new(LimitsConfig::default()));
+
+pub fn set_global_limits(limits: LimitsConfig) {
+    let mut global = GLOBAL_LIMITS.write().unwrap();
+    *global = limits;
+}
+
+pub fn get_global_limits() -> LimitsConfig {
+    let global = GLOBAL_LIMITS.read().unwrap();
+    *global
+}
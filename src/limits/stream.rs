@@ -0,0 +1,129 @@
+use std::{
+    future::Future,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, ready},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+use super::bucket::{ClientLimiter, TrafficDirection};
+use super::connections::client_limiter;
+
+/// Client-side stream wrapper applying the client's bandwidth limits. Reads from the client
+/// count as upload and writes to it as download.
+pub struct ThrottledStream<S> {
+    inner: S,
+    limiter: Arc<ClientLimiter>,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+    read_chunk: Vec<u8>,
+}
+
+impl<S> ThrottledStream<S> {
+    pub fn new(inner: S, limiter: Arc<ClientLimiter>) -> Self {
+        Self {
+            inner,
+            limiter,
+            read_delay: None,
+            write_delay: None,
+            read_chunk: Vec::new(),
+        }
+    }
+
+    pub fn for_client(inner: S, ip: IpAddr) -> Self {
+        Self::new(inner, client_limiter(ip))
+    }
+}
+
+fn poll_delay(
+    delay: &mut Option<Pin<Box<Sleep>>>,
+    limiter: &ClientLimiter,
+    direction: TrafficDirection,
+    cx: &mut Context<'_>,
+) -> Poll<()> {
+    loop {
+        if let Some(sleep) = delay.as_mut() {
+            ready!(sleep.as_mut().poll(cx));
+            *delay = None;
+        }
+
+        match limiter.wait_time(direction) {
+            Some(wait) => *delay = Some(Box::pin(tokio::time::sleep(wait))),
+            None => return Poll::Ready(()),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ThrottledStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(poll_delay(
+            &mut this.read_delay,
+            &this.limiter,
+            TrafficDirection::Upload,
+            cx
+        ));
+
+        let max_chunk = this.limiter.max_chunk(TrafficDirection::Upload);
+        let n = match max_chunk {
+            Some(max_chunk) if max_chunk < buf.remaining() => {
+                this.read_chunk.resize(max_chunk, 0);
+                let mut chunk_buf = ReadBuf::new(&mut this.read_chunk);
+                ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+                buf.put_slice(chunk_buf.filled());
+                chunk_buf.filled().len()
+            }
+            _ => {
+                let filled_before = buf.filled().len();
+                ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+                buf.filled().len() - filled_before
+            }
+        };
+
+        this.limiter.consume(TrafficDirection::Upload, n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ThrottledStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(poll_delay(
+            &mut this.write_delay,
+            &this.limiter,
+            TrafficDirection::Download,
+            cx
+        ));
+
+        let max_chunk = this.limiter.max_chunk(TrafficDirection::Download);
+        let buf = match max_chunk {
+            Some(max_chunk) if max_chunk < buf.len() => &buf[..max_chunk],
+            _ => buf,
+        };
+
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.limiter.consume(TrafficDirection::Download, n);
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -1,7 +1,10 @@
 use std::io;
 
 use time::macros::format_description;
-use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
 use tracing_subscriber::{
     EnvFilter,
     fmt::{self, time::LocalTime},
@@ -18,7 +21,9 @@ pub struct LogConfig {
     pub max_log_files: Option<usize>,
 }
 
-pub fn configure_global_tracing(config: LogConfig) {
+/// Install the global subscriber. The returned guard flushes the log file when dropped, so it must
+/// be kept alive until the process exits.
+pub fn configure_global_tracing(config: LogConfig) -> Option<WorkerGuard> {
     let timer = LocalTime::new(format_description!(
         "[year]-[month]-[day] [hour]:[minute]:[second]"
     ));
@@ -53,7 +58,7 @@ pub fn configure_global_tracing(config: LogConfig) {
                     .max_log_files(max_files)
                     .build("./logs")
                     .expect("Failed to created rolling file appender");
-                let (non_blocking_file, _guard) = tracing_appender::non_blocking(file_appender);
+                let (non_blocking_file, guard) = tracing_appender::non_blocking(file_appender);
 
                 let file_layer = fmt::layer()
                     .with_thread_ids(true)
@@ -63,9 +68,10 @@ pub fn configure_global_tracing(config: LogConfig) {
 
                 registry.with(console_layer).with(file_layer).init();
 
-                std::mem::forget(_guard);
+                Some(guard)
             } else {
                 registry.with(console_layer).init();
+                None
             }
         }
         LogFormat::Json => {
@@ -82,7 +88,7 @@ pub fn configure_global_tracing(config: LogConfig) {
                     .max_log_files(max_files)
                     .build("./logs")
                     .expect("Failed to created rolling file appender");
-                let (non_blocking_file, _guard) = tracing_appender::non_blocking(file_appender);
+                let (non_blocking_file, guard) = tracing_appender::non_blocking(file_appender);
 
                 let file_layer = fmt::layer()
                     .json()
@@ -91,9 +97,10 @@ pub fn configure_global_tracing(config: LogConfig) {
 
                 registry.with(console_layer).with(file_layer).init();
 
-                std::mem::forget(_guard);
+                Some(guard)
             } else {
                 registry.with(console_layer).init();
+                None
             }
         }
         LogFormat::Compact => {
@@ -110,7 +117,7 @@ pub fn configure_global_tracing(config: LogConfig) {
                     .max_log_files(max_files)
                     .build("./logs")
                     .expect("Failed to created rolling file appender");
-                let (non_blocking_file, _guard) = tracing_appender::non_blocking(file_appender);
+                let (non_blocking_file, guard) = tracing_appender::non_blocking(file_appender);
 
                 let file_layer = fmt::layer()
                     .compact()
@@ -120,9 +127,10 @@ pub fn configure_global_tracing(config: LogConfig) {
 
                 registry.with(console_layer).with(file_layer).init();
 
-                std::mem::forget(_guard);
+                Some(guard)
             } else {
                 registry.with(console_layer).init();
+                None
             }
         }
     }
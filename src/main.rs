@@ -1,19 +1,22 @@
 use clap::Parser;
 
 use network_administrator::cli::{Cli, Commands};
+use network_administrator::errors::error_code;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn main() {
     let cli = Cli::parse();
 
-    match cli.command {
-        Commands::Proxy(proxy_cmd) => {
-            proxy_cmd.execute().await?;
-        }
-        Commands::Scan(scan_cmd) => {
-            scan_cmd.execute().await?;
-        }
-    }
+    let result = match cli.command {
+        Commands::Proxy(proxy_cmd) => proxy_cmd.execute().await,
+        Commands::Scan(scan_cmd) => scan_cmd.execute().await,
+        Commands::Config(config_cmd) => config_cmd.execute().await,
+        Commands::Import(import_cmd) => import_cmd.execute().await,
+        Commands::Allow(allow_cmd) => allow_cmd.execute().await,
+    };
 
-    Ok(())
+    if let Err(e) = result {
+        eprintln!("Error [{}]: {}", error_code(&*e), e);
+        std::process::exit(1);
+    }
 }
@@ -6,14 +6,19 @@ use http_body_util::{BodyExt, Full};
 use hyper::body::Incoming;
 use uuid::Uuid;
 
+use crate::acl::{AclScope, is_client_allowed};
+use crate::ads::blocking_rule;
 use crate::client::forward_http_request;
 use crate::config::get_global_config;
-use crate::filters::is_domain_blacklisted;
-use crate::schemas::HttpRequest;
+use crate::errors::{FilterError, error_response};
+use crate::filters::{is_blocking_paused, is_domain_blacklisted, is_domain_whitelisted};
+use crate::schemas::{ClientContext, HttpRequest};
+use crate::utils::http::normalize_host;
 
 #[tracing::instrument(level = "info", name = "ProcessHTTPRequest")]
 pub async fn process_http_request(
     req: Request<Incoming>,
+    client: ClientContext,
 ) -> Result<Response<Full<Bytes>>, Infallible> {
     let config = get_global_config();
 
@@ -29,16 +34,48 @@ pub async fn process_http_request(
         None => Bytes::new(),
     };
 
-    if config.block_ads {
+    if config.block_ads
+        && is_client_allowed(client.peer_addr.ip(), AclScope::BlockAds)
+        && !is_blocking_paused(client.peer_addr.ip())
+    {
+        // Without the port, lowercased and in ASCII form, as the filters store their domains
         let host = headers
             .get("host")
             .and_then(|h| h.to_str().ok())
+            .or(uri.host())
+            .map(normalize_host)
             .unwrap_or_default();
-        if is_domain_blacklisted(host) {
-            tracing::info!("The host {} is blacklisted, returning 403 Forbidden", host);
-            let mut forbidden_response = Response::new(Full::new(Bytes::from("403 Forbidden")));
-            *forbidden_response.status_mut() = http::StatusCode::FORBIDDEN;
-            return Ok(forbidden_response);
+        let whitelisted = is_domain_whitelisted(&host);
+        if !whitelisted && is_domain_blacklisted(&host) {
+            tracing::info!(
+                error_code = "FILTER_BLOCKED",
+                "The host {} is blacklisted, returning 403 Forbidden",
+                host
+            );
+            return Ok(error_response(&FilterError::Blocked { host }));
+        }
+
+        if !whitelisted {
+            let header_values = headers
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        k.as_str().to_string(),
+                        v.to_str().unwrap_or_default().to_string(),
+                    )
+                })
+                .collect();
+            if let Some(rule) = blocking_rule(&uri.to_string(), &header_values, "http", &host) {
+                tracing::info!(
+                    error_code = "FILTER_BLOCKED_REQUEST",
+                    "The request to {} is blocked by network rule {}, returning 403 Forbidden",
+                    uri,
+                    rule
+                );
+                return Ok(error_response(&FilterError::BlockedRequest {
+                    url: uri.to_string(),
+                }));
+            }
         }
     }
 
@@ -49,13 +86,6 @@ pub async fn process_http_request(
         headers,
         body: Some(body),
     };
-    match forward_http_request(req_id, http_request_schema).await {
-        Ok(resp) => Ok(resp),
-        Err(e) => {
-            tracing::error!(error = %e, "Error forwarding HTTP request for request ID {}", req_id);
-            Ok(Response::new(Full::new(Bytes::from(
-                "Error forwarding HTTP request",
-            ))))
-        }
-    }
+    // Upstream failures are already turned into error pages
+    forward_http_request(req_id, http_request_schema).await
 }
@@ -1,23 +1,24 @@
-use std::net::SocketAddr;
-
-use tokio::{
-    io::AsyncWriteExt,
-    net::TcpStream,
-    time::{self as TokioTime, Duration},
-};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
 use tokio_native_tls::TlsAcceptor;
 use uuid::Uuid;
 
 use crate::client::{forward_https_request_no_tunnel, forward_https_request_tunnel};
-use crate::schemas::HttpsRequest;
+use crate::errors::{ErrorCode, ParseError, ProxyError, TlsError, https_error_response};
+use crate::limits::ThrottledStream;
+use crate::schemas::{ClientContext, HttpsRequest};
 use crate::utils::{
-    DNS_RESOLVER, http::parse_headers, read_headers_buffer, stream::parse_stream,
+    authority::Authority,
+    connect::connect_to_host,
+    http::{parse_headers, write_response},
+    read_headers_buffer,
+    stream::parse_stream,
     tls::generate_cert_for_domain,
 };
 
 #[tracing::instrument(level = "info", name = "ProcessHTTPSRequest")]
 pub async fn process_https_request(
     client_stream: &mut TcpStream,
+    client: &ClientContext,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let req_id = Uuid::new_v4();
 
@@ -43,8 +44,13 @@ pub async fn process_https_request(
         .collect::<Vec<&str>>();
 
     let [_method, _authority, _version] = first_line.split(' ').collect::<Vec<&str>>()[..] else {
-        tracing::error!("Malformed HTTPS request line: {}", first_line);
-        return Err("Malformed HTTPS request line".into());
+        let error = ParseError::RequestLine(first_line.to_string());
+        tracing::error!(error_code = error.code(), "{}", error);
+        let response = https_error_response(&error, "HTTP/1.1");
+        if let Err(e) = write_response(client_stream, &response).await {
+            tracing::debug!("Unable to send the error page: {}", e);
+        }
+        return Err(error.into());
     };
 
     tracing::debug!(
@@ -66,7 +72,7 @@ pub async fn process_https_request(
         headers: parse_headers(_header_lines.as_ref()),
         body: None,
     };
-    match forward_https_request_tunnel(req_id, client_stream, https_request_schema).await {
+    match forward_https_request_tunnel(req_id, client_stream, https_request_schema, client).await {
         Ok(_) => Ok(()),
         Err(e) => {
             tracing::error!(error = %e, "Error forwarding HTTPS request for request ID {}", req_id);
@@ -78,6 +84,7 @@ pub async fn process_https_request(
 #[tracing::instrument(level = "info", name = "ProcessHTTPSRequestWithInterception")]
 pub async fn process_https_request_with_interception(
     client_stream: &mut TcpStream,
+    client: &ClientContext,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let req_id = Uuid::new_v4();
     tracing::info!("Received request ID {}", req_id);
@@ -100,16 +107,67 @@ pub async fn process_https_request_with_interception(
 
     tracing::info!("Sent 200 Connection Established for request ID {}", req_id);
 
+    let Authority { host, port } = Authority::parse(&https_stream_parser.authority, 443)?;
+    intercept_tls_connection(
+        req_id,
+        client_stream,
+        client,
+        &host,
+        port,
+        https_stream_parser.version.as_str(),
+    )
+    .await
+}
+
+async fn connect_tls_upstream(
+    host: &str,
+    port: u16,
+) -> Result<tokio_native_tls::TlsStream<TcpStream>, ProxyError> {
+    let handshake_error = |reason: String| TlsError::UpstreamHandshake {
+        host: host.to_string(),
+        reason,
+    };
+
+    let dest_tcp_stream = connect_to_host(host, port).await?;
+    let native_connector = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(false)
+        .build()
+        .map_err(|e| handshake_error(e.to_string()))?;
+    let tls_connector = tokio_native_tls::TlsConnector::from(native_connector);
+    let dest_tls_stream = tls_connector
+        .connect(host, dest_tcp_stream)
+        .await
+        .map_err(|e| handshake_error(e.to_string()))?;
+
+    Ok(dest_tls_stream)
+}
+
+/// Terminate the client TLS connection with a certificate for `host` issued by our CA and relay
+/// the decrypted traffic to the destination. The client stream must be positioned at the start
+/// of the TLS handshake, after the CONNECT exchange or straight away for transparent clients.
+pub async fn intercept_tls_connection(
+    req_id: Uuid,
+    client_stream: &mut TcpStream,
+    client: &ClientContext,
+    host: &str,
+    port: u16,
+    version: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // 1. Perform TLS handshake with client using our CA (accept)
-    let (host, port_str) = https_stream_parser
-        .authority
-        .split_once(':')
-        .ok_or("Invalid authority")?;
-    let port: u16 = port_str.parse()?;
-
-    let (cert_pem, key_pem) = generate_cert_for_domain(&host)?;
-    let identity = native_tls::Identity::from_pkcs8(&cert_pem.as_bytes(), &key_pem.as_bytes())?;
-    let native_acceptor = native_tls::TlsAcceptor::new(identity)?;
+
+    let certificate_error = |reason: String| TlsError::Certificate {
+        host: host.to_string(),
+        reason,
+    };
+    let (cert_pem, key_pem) =
+        generate_cert_for_domain(host).map_err(|e| match e.downcast::<TlsError>() {
+            Ok(e) => *e,
+            Err(e) => certificate_error(e.to_string()),
+        })?;
+    let identity = native_tls::Identity::from_pkcs8(&cert_pem.as_bytes(), &key_pem.as_bytes())
+        .map_err(|e| certificate_error(e.to_string()))?;
+    let native_acceptor =
+        native_tls::TlsAcceptor::new(identity).map_err(|e| certificate_error(e.to_string()))?;
     let tls_acceptor = TlsAcceptor::from(native_acceptor);
 
     tracing::info!(
@@ -118,14 +176,12 @@ pub async fn process_https_request_with_interception(
     );
 
     // If the TLS handshake fails, could means that the client does not trust our CA
-    let mut client_tls_stream = tls_acceptor.accept(client_stream).await.map_err(|e| {
-        tracing::error!(
-            "TLS handshake with client failed for request ID {}: {}",
-            req_id,
-            e
-        );
-        e
-    })?;
+    // Throttle below TLS so the limits apply to the bytes actually sent on the wire
+    let throttled_stream = ThrottledStream::for_client(client_stream, client.peer_addr.ip());
+    let mut client_tls_stream = tls_acceptor
+        .accept(throttled_stream)
+        .await
+        .map_err(|e| TlsError::ClientHandshake(e.to_string()))?;
 
     tracing::info!(
         "TLS handshake with client succeeded for request ID {}",
@@ -133,24 +189,31 @@ pub async fn process_https_request_with_interception(
     );
 
     // 2. Now connect to the destination server and perform TLS handshake (connect)
-    let lookup = DNS_RESOLVER.lookup_ip(host).await?;
-    let ip = lookup.iter().next().ok_or("No IP address found")?;
-    let dest_addr = SocketAddr::new(ip, port);
-
     tracing::info!(
-        "Connecting to destination server {} for request ID {}",
-        https_stream_parser.authority,
+        "Connecting to destination server {}:{} for request ID {}",
+        host,
+        port,
         req_id
     );
 
-    let mut dest_tcp_stream =
-        TokioTime::timeout(Duration::from_secs(5), TcpStream::connect(dest_addr)).await??;
-
-    let native_connector = native_tls::TlsConnector::builder()
-        .danger_accept_invalid_certs(false)
-        .build()?;
-    let tls_connector = tokio_native_tls::TlsConnector::from(native_connector);
-    let mut dest_tls_stream = tls_connector.connect(host, &mut dest_tcp_stream).await?;
+    // The client already trusts the connection, so failures are reported with an error page
+    let mut dest_tls_stream = match connect_tls_upstream(host, port).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            tracing::error!(
+                error_code = e.code(),
+                "Unable to reach {} for request ID {}: {}",
+                host,
+                req_id,
+                e
+            );
+            let response = https_error_response(&e, version);
+            if let Err(write_error) = write_response(&mut client_tls_stream, &response).await {
+                tracing::debug!("Unable to send the error page: {}", write_error);
+            }
+            return Err(e.into());
+        }
+    };
 
     tracing::info!(
         "TLS handshake with destination server {} succeeded for request ID {}",
@@ -165,7 +228,8 @@ pub async fn process_https_request_with_interception(
         req_id,
         &mut client_tls_stream,
         &mut dest_tls_stream,
-        https_stream_parser.version.as_str(),
+        version,
+        client,
     )
     .await?;
 
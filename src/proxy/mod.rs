@@ -2,4 +2,6 @@ mod http;
 mod https;
 
 pub use http::process_http_request;
-pub use https::{process_https_request, process_https_request_with_interception};
+pub use https::{
+    intercept_tls_connection, process_https_request, process_https_request_with_interception,
+};
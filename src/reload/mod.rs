@@ -0,0 +1,290 @@
+// Hot reload: the directories of the config, filter and network rules files are watched (inotify on Linux) and
+// the files are loaded again when they change. A file that fails to parse or validate is
+// rejected with a logged reason and the previous version stays active.
+
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tokio::time::{self as TokioTime, Duration};
+
+use crate::cli::ProxyCommand;
+use crate::config::{
+    AppConfig, ProxyConfig, ProxyConfigPatch, app::ServerSettings, get_global_config,
+    is_config_file_written, set_global_config,
+};
+use crate::filters::{
+    get_cosmetic_filter_file, get_filter_file, get_network_filter_file, reload_cosmetic_filter,
+    reload_filter_file, reload_network_filter,
+};
+use crate::limits::{get_global_limits, set_global_limits};
+use crate::shutdown::shutdown_token;
+
+/// Editors and atomic renames produce bursts of events, the reload waits for the burst to end.
+const RELOAD_DEBOUNCE_MSECS: u64 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum WatchedFile {
+    Config,
+    Filter,
+    NetworkFilter,
+    CosmeticFilter,
+}
+
+/// A file identified by its canonical directory and name, as notify reports absolute paths.
+struct WatchTarget {
+    kind: WatchedFile,
+    dir: PathBuf,
+    name: OsString,
+}
+
+impl WatchTarget {
+    fn new(kind: WatchedFile, file: &Path) -> Option<Self> {
+        let dir = match file.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        Some(Self {
+            kind,
+            dir: dir.canonicalize().ok()?,
+            name: file.file_name()?.to_os_string(),
+        })
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        path.parent() == Some(self.dir.as_path()) && path.file_name() == Some(self.name.as_os_str())
+    }
+}
+
+/// Watch the config and filter files until shutdown, reloading them when they change.
+pub async fn watch_config_files(
+    cli: ProxyCommand,
+    config_file: PathBuf,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let targets = [
+        (WatchedFile::Config, config_file.clone()),
+        (WatchedFile::Filter, get_filter_file()),
+        (WatchedFile::NetworkFilter, get_network_filter_file()),
+        (WatchedFile::CosmeticFilter, get_cosmetic_filter_file()),
+    ]
+    .into_iter()
+    .filter_map(|(kind, file)| {
+        let target = WatchTarget::new(kind, &file);
+        if target.is_none() {
+            tracing::warn!("Not watching {:?}, its directory does not exist", file);
+        }
+        target
+    })
+    .collect::<Vec<_>>();
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = tx.send(event);
+    })?;
+
+    // The directories are watched rather than the files, an atomic rename replaces the inode
+    let dirs = targets
+        .iter()
+        .map(|target| target.dir.clone())
+        .collect::<HashSet<_>>();
+    for dir in &dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        tracing::info!("Watching {:?} for config changes", dir);
+    }
+
+    // The file as last loaded, to apply only the settings that change in it
+    let mut file_settings = AppConfig::load(Some(config_file))?;
+    let cli = Arc::new(cli);
+    let shutdown = shutdown_token();
+
+    loop {
+        let event = tokio::select! {
+            event = rx.recv() => event,
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+        let Some(event) = event else {
+            return Ok(());
+        };
+
+        let mut changed = HashSet::new();
+        collect_changes(event, &targets, &mut changed);
+        if changed.is_empty() {
+            continue;
+        }
+
+        tokio::select! {
+            _ = TokioTime::sleep(Duration::from_millis(RELOAD_DEBOUNCE_MSECS)) => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+        while let Ok(event) = rx.try_recv() {
+            collect_changes(event, &targets, &mut changed);
+        }
+
+        // Parsing and compiling the files blocks, so it runs off the async workers
+        let cli = cli.clone();
+        file_settings = tokio::task::spawn_blocking(move || {
+            reload_changed_files(&cli, file_settings, &changed)
+        })
+        .await?;
+    }
+}
+
+/// Load the changed files again, returning the config file settings as last loaded.
+fn reload_changed_files(
+    cli: &ProxyCommand,
+    mut file_settings: AppConfig,
+    changed: &HashSet<WatchedFile>,
+) -> AppConfig {
+    if changed.contains(&WatchedFile::Config) {
+        match reload_app_config(cli, &mut file_settings) {
+            Ok(true) => tracing::info!("Reloaded {:?}", file_settings.file),
+            Ok(false) => tracing::debug!(
+                "Skipped reloading {:?}, the admin API wrote it",
+                file_settings.file
+            ),
+            Err(e) => tracing::error!(
+                "Rejected config file change, keeping the previous configuration: {}",
+                e
+            ),
+        }
+    }
+
+    if changed.contains(&WatchedFile::Filter) {
+        match reload_filter_file() {
+            Ok(()) => tracing::info!("Reloaded {:?}", get_filter_file()),
+            Err(e) => tracing::error!(
+                "Rejected filter file change, keeping the previous filter: {}",
+                e
+            ),
+        }
+    }
+
+    // Invalid rules are skipped one by one, the file itself is never rejected
+    if changed.contains(&WatchedFile::NetworkFilter) && !changed.contains(&WatchedFile::Filter) {
+        reload_network_filter();
+        tracing::info!("Reloaded {:?}", get_network_filter_file());
+    }
+    if changed.contains(&WatchedFile::CosmeticFilter) && !changed.contains(&WatchedFile::Filter) {
+        reload_cosmetic_filter();
+        tracing::info!("Reloaded {:?}", get_cosmetic_filter_file());
+    }
+
+    file_settings
+}
+
+fn collect_changes(
+    event: notify::Result<Event>,
+    targets: &[WatchTarget],
+    changed: &mut HashSet<WatchedFile>,
+) {
+    let event = match event {
+        Ok(event) => event,
+        Err(e) => {
+            tracing::warn!("Config watcher error: {}", e);
+            return;
+        }
+    };
+
+    // A removed file keeps the active version, it is loaded again once it is written back
+    if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+        return;
+    }
+
+    for path in &event.paths {
+        for target in targets.iter().filter(|target| target.matches(path)) {
+            changed.insert(target.kind);
+        }
+    }
+}
+
+/// Load the config file again and apply the runtime settings that changed in it. Settings given
+/// on the command line keep their value unless the file changes them. Returns false when the
+/// change is the admin API saving the settings it already applied.
+fn reload_app_config(
+    cli: &ProxyCommand,
+    file_settings: &mut AppConfig,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let new_settings = AppConfig::load(Some(file_settings.file.clone()))?;
+
+    let content = std::fs::read_to_string(&file_settings.file).unwrap_or_default();
+    if is_config_file_written(&content) {
+        *file_settings = new_settings;
+        return Ok(false);
+    }
+
+    let mut merged = new_settings.clone();
+    merged.apply_cli(cli);
+    merged.validate()?;
+
+    // `require_auth` and `cache_enabled` live in [server] but apply at runtime
+    let listening = |settings: &AppConfig| ServerSettings {
+        require_auth: false,
+        cache_enabled: false,
+        ..settings.server.clone()
+    };
+    let restart_sections = [
+        (
+            "server",
+            listening(&new_settings) != listening(file_settings),
+        ),
+        ("admin", new_settings.admin != file_settings.admin),
+        ("logging", new_settings.logging != file_settings.logging),
+        ("reverse", new_settings.reverse != file_settings.reverse),
+        ("dns", new_settings.dns != file_settings.dns),
+        (
+            "timeouts.shutdown_secs",
+            new_settings.timeouts.shutdown_secs != file_settings.timeouts.shutdown_secs,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, changed)| *changed)
+    .map(|(section, _)| section)
+    .collect::<Vec<_>>();
+
+    let patch = ProxyConfigPatch::diff(
+        &ProxyConfig::from_app_config(file_settings),
+        &ProxyConfig::from_app_config(&new_settings),
+    );
+    let config = patch.apply(&get_global_config());
+    config.validate()?;
+
+    let mut limits = get_global_limits();
+    let (old, new) = (file_settings.limits, new_settings.limits);
+    if old.upload_bytes_per_sec != new.upload_bytes_per_sec {
+        limits.upload_bytes_per_sec = new.upload_bytes_per_sec;
+    }
+    if old.download_bytes_per_sec != new.download_bytes_per_sec {
+        limits.download_bytes_per_sec = new.download_bytes_per_sec;
+    }
+    if old.max_connections_per_client != new.max_connections_per_client {
+        limits.max_connections_per_client = new.max_connections_per_client;
+    }
+    if old.max_connections != new.max_connections {
+        limits.max_connections = new.max_connections;
+    }
+    limits.validate()?;
+
+    if !patch.is_empty() {
+        tracing::info!("Applying config changes from file: {:?}", patch);
+        set_global_config(config);
+    }
+    if limits != get_global_limits() {
+        tracing::info!("Applying limits from file: {:?}", limits);
+        set_global_limits(limits);
+    }
+    if !restart_sections.is_empty() {
+        tracing::warn!(
+            "Changes to {} take effect after a restart",
+            restart_sections.join(", ")
+        );
+    }
+
+    *file_settings = new_settings;
+    Ok(true)
+}
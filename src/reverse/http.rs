@@ -0,0 +1,209 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use http::{
+    HeaderValue, Request, Response, StatusCode,
+    header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, HOST},
+};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use uuid::Uuid;
+
+use super::routes::ReverseConfig;
+use crate::ads::{analyze_and_modify_request, analyze_and_modify_response};
+use crate::client::forward_http_request_to_url;
+use crate::config::get_global_config;
+use crate::errors::{ErrorCode, FilterError, error_response};
+use crate::filters::is_domain_blocked;
+use crate::schemas::{HttpRequest, HttpResponse};
+use crate::utils::{decoders::decode_content, http::normalize_host};
+
+fn status_response(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(message.to_string())));
+    *response.status_mut() = status;
+    response
+}
+
+#[tracing::instrument(
+    level = "info",
+    name = "ProcessReverseRequest",
+    skip(req, reverse_config)
+)]
+pub async fn process_reverse_request(
+    req: Request<Incoming>,
+    reverse_config: Arc<ReverseConfig>,
+    peer_addr: SocketAddr,
+    is_tls: bool,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let config = get_global_config();
+
+    let req_id = Uuid::new_v4();
+    tracing::info!("Received reverse proxy request ID {}", req_id);
+
+    let (parts, body) = req.into_parts();
+    let body = match body.collect().await.ok() {
+        Some(b) => b.to_bytes(),
+        None => Bytes::new(),
+    };
+
+    let raw_host = parts
+        .headers
+        .get(HOST)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_string())
+        .or_else(|| parts.uri.authority().map(|a| a.to_string()))
+        .unwrap_or_default();
+    let host = normalize_host(&raw_host);
+
+    if config.block_ads && is_domain_blocked(&host) {
+        tracing::info!(
+            error_code = "FILTER_BLOCKED",
+            "The host {} is blacklisted, returning 403 Forbidden",
+            host
+        );
+        return Ok(error_response(&FilterError::Blocked { host }));
+    }
+
+    let Some(route) = reverse_config.find_route(&host, parts.uri.path()) else {
+        tracing::warn!(
+            "No reverse proxy route for host '{}' and path '{}' (request ID {})",
+            host,
+            parts.uri.path(),
+            req_id
+        );
+        return Ok(status_response(StatusCode::NOT_FOUND, "404 Not Found"));
+    };
+
+    let url = route.target_url(parts.uri.path(), parts.uri.query());
+    tracing::info!(
+        "Routing request ID {} for host '{}' to {}",
+        req_id,
+        host,
+        url
+    );
+
+    let mut headers = parts.headers.clone();
+    if !route.preserve_host {
+        headers.remove(HOST);
+    }
+
+    if let Ok(value) = HeaderValue::from_str(&peer_addr.ip().to_string()) {
+        headers.append("x-forwarded-for", value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&raw_host) {
+        headers.insert("x-forwarded-host", value);
+    }
+    headers.insert(
+        "x-forwarded-proto",
+        HeaderValue::from_static(if is_tls { "https" } else { "http" }),
+    );
+
+    let mut request = HttpRequest {
+        method: parts.method.to_string(),
+        uri: parts.uri.clone(),
+        // Backends are reached over HTTP/1.1 regardless of what the client negotiated
+        version: http::Version::HTTP_11,
+        headers,
+        body: Some(body),
+    };
+
+    if config.block_ads {
+        request = match HttpRequest::try_from(analyze_and_modify_request(&request.into())) {
+            Ok(request) => request,
+            Err(e) => {
+                tracing::warn!(error_code = e.code(), "{}", e);
+                return Ok(error_response(&e));
+            }
+        };
+    }
+
+    let response = forward_http_request_to_url(req_id, url, request).await?;
+
+    match config.block_ads {
+        true => Ok(modify_html_response(req_id, &host, response).await),
+        false => Ok(response),
+    }
+}
+
+/// Run HTML responses from the backend through the same ad-blocking pipeline used when intercepting TLS.
+async fn modify_html_response(
+    req_id: Uuid,
+    host: &str,
+    response: Response<Full<Bytes>>,
+) -> Response<Full<Bytes>> {
+    let is_html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .map(|ct| ct.contains("text/html"))
+        .unwrap_or(false);
+
+    if !is_html {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match body.collect().await {
+        Ok(b) => b.to_bytes(),
+        Err(e) => match e {},
+    };
+
+    let encoding = parts
+        .headers
+        .get(CONTENT_ENCODING)
+        .and_then(|e| e.to_str().ok())
+        .unwrap_or_default();
+    let decoded = match decode_content(&body, encoding) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            tracing::warn!(
+                "Skipping HTML rewrite for request ID {} due to undecodable body: {}",
+                req_id,
+                e
+            );
+            return Response::from_parts(parts, Full::new(body));
+        }
+    };
+
+    let http_response = HttpResponse {
+        version: format!("{:?}", parts.version),
+        status_code: parts.status.as_u16(),
+        status_text: parts
+            .status
+            .canonical_reason()
+            .unwrap_or_default()
+            .to_string(),
+        headers: parts
+            .headers
+            .iter()
+            .map(|(k, v)| {
+                (
+                    k.as_str().to_string(),
+                    v.to_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect(),
+        body: Some(decoded),
+    };
+
+    let modified_response: HttpResponse =
+        analyze_and_modify_response(&http_response.into(), host).into();
+
+    // Drop the headers removed by the pipeline (e.g. CSP), the body is decoded and re-sized now
+    let removed_headers = parts
+        .headers
+        .keys()
+        .filter(|k| !modified_response.headers.contains_key(k.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    for key in removed_headers {
+        parts.headers.remove(key);
+    }
+    parts.headers.remove(CONTENT_ENCODING);
+    parts.headers.remove(CONTENT_LENGTH);
+
+    let body = modified_response.body.unwrap_or_default();
+    Response::from_parts(parts, Full::new(Bytes::from(body)))
+}
@@ -0,0 +1,164 @@
+// Reverse proxy mode: routes requests by Host/SNI and path prefix to configured LAN backends,
+// terminating TLS with certificates issued by our CA (or supplied files).
+
+mod http;
+pub mod routes;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use futures_util::future::try_join_all;
+use hyper::rt::{Read, Write};
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_native_tls::TlsAcceptor;
+
+use crate::shutdown::{shutdown_token, spawn_connection};
+use crate::utils::{
+    listen::{ListenFamily, bind_tcp_listeners},
+    tls::{parse_sni, peek_client_hello},
+};
+use http::process_reverse_request;
+pub use routes::{ReverseConfig, ReverseRoute};
+
+/// Hosts whose TLS acceptor is kept. The SNI is chosen by the client, so the cache is emptied
+/// when it is full rather than growing with every name sent.
+const MAX_CACHED_ACCEPTORS: usize = 1024;
+
+/// TLS acceptors by SNI, so a certificate is issued once per host instead of on every connection.
+static TLS_ACCEPTORS: LazyLock<Mutex<HashMap<String, TlsAcceptor>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn tls_acceptor_for_host(
+    reverse_config: &ReverseConfig,
+    sni: &str,
+) -> Result<TlsAcceptor, Box<dyn std::error::Error + Send + Sync>> {
+    let host = sni.to_ascii_lowercase();
+    if let Some(acceptor) = TLS_ACCEPTORS.lock().unwrap().get(&host) {
+        return Ok(acceptor.clone());
+    }
+
+    let identity = reverse_config.identity_for_host(&host)?;
+    let acceptor = TlsAcceptor::from(native_tls::TlsAcceptor::new(identity)?);
+
+    let mut acceptors = TLS_ACCEPTORS.lock().unwrap();
+    if acceptors.len() >= MAX_CACHED_ACCEPTORS {
+        acceptors.clear();
+    }
+    acceptors.insert(host, acceptor.clone());
+    Ok(acceptor)
+}
+
+/// Serve HTTP on the connection, closing it gracefully once the in-flight request is done if
+/// shutdown starts.
+async fn serve_http<I>(
+    io: I,
+    reverse_config: Arc<ReverseConfig>,
+    peer_addr: SocketAddr,
+    is_tls: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    I: Read + Write + Unpin + Send + 'static,
+{
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let service = service_fn(move |req| {
+        process_reverse_request(req, reverse_config.clone(), peer_addr, is_tls)
+    });
+
+    let connection = builder.serve_connection(io, service);
+    tokio::pin!(connection);
+
+    tokio::select! {
+        result = connection.as_mut() => result,
+        _ = shutdown_token().cancelled_owned() => {
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    }
+}
+
+async fn serve_reverse_connection(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    reverse_config: Arc<ReverseConfig>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match peek_client_hello(&stream).await? {
+        Some(client_hello) => {
+            let sni = parse_sni(&client_hello).ok_or("Missing SNI in TLS ClientHello")?;
+            tracing::info!("Detected TLS connection from {} for {}", peer_addr, sni);
+
+            let tls_acceptor = tls_acceptor_for_host(&reverse_config, &sni)?;
+            let tls_stream = tls_acceptor.accept(stream).await?;
+
+            serve_http(TokioIo::new(tls_stream), reverse_config, peer_addr, true).await
+        }
+        None => {
+            tracing::info!("Detected HTTP connection from {}", peer_addr);
+
+            serve_http(TokioIo::new(stream), reverse_config, peer_addr, false).await
+        }
+    }
+}
+
+#[tracing::instrument(level = "info", name = "Reverse Proxy Server")]
+pub async fn start_reverse_proxy_server(
+    host: String,
+    port: u16,
+    family: ListenFamily,
+    config_file: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let reverse_config = Arc::new(ReverseConfig::load(config_file)?);
+    tracing::info!(
+        "Loaded {} reverse proxy routes",
+        reverse_config.routes.len()
+    );
+
+    let listeners = bind_tcp_listeners(&host, port, family).await?;
+    for listener in &listeners {
+        tracing::info!(
+            "Starting reverse proxy server at {}",
+            listener.local_addr()?
+        );
+    }
+
+    try_join_all(
+        listeners
+            .into_iter()
+            .map(|listener| serve_reverse_listener(listener, reverse_config.clone())),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn serve_reverse_listener(
+    listener: TcpListener,
+    reverse_config: Arc<ReverseConfig>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let shutdown = shutdown_token();
+
+    loop {
+        let (stream, peer_addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.cancelled() => {
+                tracing::info!("Reverse proxy server stopped accepting connections");
+                return Ok(());
+            }
+        };
+        tracing::info!("Accepted reverse proxy connection from {}", peer_addr);
+
+        let reverse_config = reverse_config.clone();
+        spawn_connection(async move {
+            if let Err(e) = serve_reverse_connection(stream, peer_addr, reverse_config).await {
+                tracing::error!(
+                    "Error serving reverse proxy connection from {}: {}",
+                    peer_addr,
+                    e
+                );
+            }
+        });
+    }
+}
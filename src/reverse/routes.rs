@@ -0,0 +1,178 @@
+use std::{path::PathBuf, sync::LazyLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::CONFIG_PATH;
+use crate::utils::tls::generate_cert_for_domain;
+
+pub static REVERSE_CONFIG_PATH: LazyLock<PathBuf> =
+    LazyLock::new(|| CONFIG_PATH.join("reverse.toml"));
+
+fn default_path_prefix() -> String {
+    "/".to_string()
+}
+
+// TOML file content
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReverseConfig {
+    #[serde(default)]
+    pub routes: Vec<ReverseRoute>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReverseRoute {
+    /// Host (or SNI) served by this route. Accepts exact names, `*.suffix` wildcards and `*`.
+    pub host: String,
+
+    /// Only requests whose path starts with this prefix are routed to the backend.
+    #[serde(default = "default_path_prefix")]
+    pub path_prefix: String,
+
+    /// Base URL of the backend, e.g. `http://192.168.1.10:8123`.
+    pub backend: String,
+
+    /// Remove `path_prefix` from the path before forwarding it to the backend.
+    #[serde(default)]
+    pub strip_prefix: bool,
+
+    /// Forward the original `Host` header instead of the backend one.
+    #[serde(default)]
+    pub preserve_host: bool,
+
+    /// Certificate files to use instead of issuing one with our CA.
+    #[serde(default)]
+    pub tls: Option<TlsFiles>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsFiles {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+}
+
+impl ReverseRoute {
+    /// Specificity of the host pattern, used to prefer exact hosts over wildcards.
+    fn host_specificity(&self) -> usize {
+        match self.host.as_str() {
+            "*" => 0,
+            h if h.starts_with("*.") => 1,
+            _ => 2,
+        }
+    }
+
+    pub fn matches_host(&self, host: &str) -> bool {
+        let pattern = self.host.trim().to_ascii_lowercase();
+        let host = host.trim().to_ascii_lowercase();
+
+        if pattern == "*" {
+            return true;
+        }
+
+        match pattern.strip_prefix("*.") {
+            Some(suffix) => host.ends_with(&format!(".{}", suffix)),
+            None => host == pattern,
+        }
+    }
+
+    /// Whether the path is under the prefix: `/api` matches `/api` and `/api/users`, not
+    /// `/apiary`.
+    pub fn matches_path(&self, path: &str) -> bool {
+        let prefix = self.path_prefix.trim_end_matches('/');
+        match path.strip_prefix(prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+
+    /// Build the backend URL for the given path and query.
+    pub fn target_url(&self, path: &str, query: Option<&str>) -> String {
+        let path = match self.strip_prefix {
+            true => path
+                .strip_prefix(self.path_prefix.trim_end_matches('/'))
+                .unwrap_or(path),
+            false => path,
+        };
+
+        let mut url = format!(
+            "{}/{}",
+            self.backend.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        );
+        if let Some(query) = query {
+            url.push('?');
+            url.push_str(query);
+        }
+
+        url
+    }
+}
+
+impl ReverseConfig {
+    pub fn load(file: Option<PathBuf>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let file = file.unwrap_or(REVERSE_CONFIG_PATH.clone());
+        let content = std::fs::read_to_string(&file)
+            .map_err(|e| format!("Error reading reverse proxy config {:?}: {}", file, e))?;
+        let config: ReverseConfig = toml::from_str(&content)?;
+
+        for route in &config.routes {
+            let backend: http::Uri = route
+                .backend
+                .parse()
+                .map_err(|e| format!("Invalid backend '{}': {}", route.backend, e))?;
+
+            if !matches!(backend.scheme_str(), Some("http") | Some("https")) {
+                return Err(format!(
+                    "Backend '{}' must use the http or https scheme",
+                    route.backend
+                )
+                .into());
+            }
+
+            if !route.path_prefix.starts_with('/') {
+                return Err(format!(
+                    "Path prefix '{}' for host '{}' must start with '/'",
+                    route.path_prefix, route.host
+                )
+                .into());
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Find the route for a host and path. Exact hosts win over wildcards, and longer
+    /// path prefixes win over shorter ones.
+    pub fn find_route(&self, host: &str, path: &str) -> Option<&ReverseRoute> {
+        self.routes
+            .iter()
+            .filter(|route| route.matches_host(host) && route.matches_path(path))
+            .max_by_key(|route| (route.host_specificity(), route.path_prefix.len()))
+    }
+
+    /// Build the TLS identity presented for the given SNI. Routes with their own certificate
+    /// files use them, every other host gets a certificate issued by our CA.
+    pub fn identity_for_host(
+        &self,
+        sni: &str,
+    ) -> Result<native_tls::Identity, Box<dyn std::error::Error + Send + Sync>> {
+        let tls_files = self
+            .routes
+            .iter()
+            .filter(|route| route.matches_host(sni) && route.tls.is_some())
+            .max_by_key(|route| route.host_specificity())
+            .and_then(|route| route.tls.as_ref());
+
+        let (cert_pem, key_pem) = match tls_files {
+            Some(files) => (
+                std::fs::read_to_string(&files.cert_file)?,
+                std::fs::read_to_string(&files.key_file)?,
+            ),
+            None => generate_cert_for_domain(sni)?,
+        };
+
+        Ok(native_tls::Identity::from_pkcs8(
+            cert_pem.as_bytes(),
+            key_pem.as_bytes(),
+        )?)
+    }
+}
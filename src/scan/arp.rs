@@ -1,6 +1,6 @@
 use std::net::{IpAddr, Ipv4Addr};
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 
 use pnet::datalink::{self, Channel, Config, NetworkInterface};
 use pnet::packet::arp::{ArpHardwareTypes, ArpOperations, MutableArpPacket};
@@ -8,42 +8,52 @@ use pnet::packet::ethernet::{EtherTypes, MutableEthernetPacket};
 use pnet::util::MacAddr;
 
 use crate::config::{ARP_REQUEST_INTERVAL_MSECS, ARP_RETRIES, ARP_TIMEOUT_SECS};
+use crate::errors::ScanError;
 use crate::schemas::arp::ArpResponse;
 
+/// Find the network interface with the provided name, it must have a MAC and an IPv4 address
+/// to send ARP requests from.
+pub fn find_interface(interface_name: &str) -> Result<NetworkInterface, ScanError> {
+    let interface = datalink::interfaces()
+        .into_iter()
+        .find(|iface| iface.name == interface_name)
+        .ok_or_else(|| ScanError::InterfaceNotFound(interface_name.to_string()))?;
+
+    sender_addresses(&interface)?;
+    Ok(interface)
+}
+
+fn sender_addresses(interface: &NetworkInterface) -> Result<(MacAddr, Ipv4Addr), ScanError> {
+    let missing = |missing| ScanError::InterfaceAddress {
+        interface: interface.name.clone(),
+        missing,
+    };
+
+    let sender_mac = interface.mac.ok_or_else(|| missing("MAC address"))?;
+    let sender_ip = interface
+        .ips
+        .iter()
+        .find_map(|ip| match ip.ip().to_canonical() {
+            IpAddr::V4(ipv4) => Some(ipv4),
+            IpAddr::V6(_) => None,
+        })
+        .ok_or_else(|| missing("IPv4 address"))?;
+
+    Ok((sender_mac, sender_ip))
+}
+
 pub fn send_arp_request(
     target_ip: Ipv4Addr,
-    interface_name: &str,
+    interface: &NetworkInterface,
     timeout_secs: Option<f32>,
     retries: Option<usize>,
-) -> Result<Option<ArpResponse>, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<Option<ArpResponse>, ScanError> {
     let retries = retries.unwrap_or(ARP_RETRIES);
     let timeout_secs = timeout_secs.unwrap_or(ARP_TIMEOUT_SECS);
-    let timeout_mili_secs = (timeout_secs * 1000.0) as u128;
-
-    let interface_names_match = |iface: &NetworkInterface| iface.name == interface_name;
-
-    // Find the network interface with the provided name
-    let interfaces = datalink::interfaces();
-    let interface = interfaces
-        .into_iter()
-        .filter(interface_names_match)
-        .next()
-        .unwrap();
+    let timeout = Duration::from_millis((timeout_secs * 1000.0) as u64);
 
     // Get sender addresses (MAC and IP)
-    let sender_mac = interface.mac.ok_or("Error getting MAC address")?;
-    let sender_ip_canonical = interface
-        .ips
-        .iter()
-        .find(|ip| ip.is_ipv4())
-        .ok_or("Error getting IPv4 address")?
-        .ip()
-        .to_canonical();
-
-    let sender_ip = match sender_ip_canonical {
-        IpAddr::V4(ipv4) => ipv4,
-        _ => return Err("Sender IP is not IPv4".into()),
-    };
+    let (sender_mac, sender_ip) = sender_addresses(interface)?;
 
     if sender_ip == target_ip {
         return Ok(Some(ArpResponse {
@@ -57,8 +67,8 @@ pub fn send_arp_request(
 
     // 1. create arp buffer
     let mut arp_buffer = [0u8; 28];
-    let mut arp_packet =
-        MutableArpPacket::new(&mut arp_buffer).ok_or("Error creating the ARP Packet")?;
+    let mut arp_packet = MutableArpPacket::new(&mut arp_buffer)
+        .ok_or_else(|| ScanError::Send("Error creating the ARP Packet".to_string()))?;
 
     arp_packet.set_hardware_type(ArpHardwareTypes::Ethernet);
     arp_packet.set_protocol_type(EtherTypes::Ipv4);
@@ -72,8 +82,8 @@ pub fn send_arp_request(
 
     // 2. create ethernet frame
     let mut ethernet_buffer = [0u8; 14 + 28]; // 14 bytes for Ethernet header + 28 bytes for ARP packet 
-    let mut ethernet_packet =
-        MutableEthernetPacket::new(&mut ethernet_buffer).ok_or("Error creating Ethernet Packet")?;
+    let mut ethernet_packet = MutableEthernetPacket::new(&mut ethernet_buffer)
+        .ok_or_else(|| ScanError::Send("Error creating Ethernet Packet".to_string()))?;
 
     ethernet_packet.set_destination(target_mac);
     ethernet_packet.set_source(sender_mac);
@@ -81,23 +91,26 @@ pub fn send_arp_request(
     ethernet_packet.set_payload(&arp_buffer);
 
     // 3. send the packet
-    let (mut tx, mut rx) = match datalink::channel(&interface, Config::default())? {
+    let channel = datalink::channel(interface, Config::default())
+        .map_err(|e| ScanError::Channel(e.to_string()))?;
+    let (mut tx, mut rx) = match channel {
         Channel::Ethernet(tx, rx) => (tx, rx),
-        _ => return Err("Error creating datalink channel".into()),
+        _ => return Err(ScanError::Channel("not an Ethernet channel".to_string())),
     };
 
     for i in 0..retries {
         tx.send_to(&ethernet_buffer, None)
-            .ok_or("Failed to send packet")??;
+            .ok_or_else(|| ScanError::Send("Failed to send packet".to_string()))?
+            .map_err(|e| ScanError::Send(e.to_string()))?;
 
         if i < retries - 1 {
             thread::sleep(Duration::from_millis(ARP_REQUEST_INTERVAL_MSECS));
         }
     }
 
-    let start_time = SystemTime::now();
+    let start_time = Instant::now();
     loop {
-        if SystemTime::now().duration_since(start_time)?.as_millis() >= timeout_mili_secs {
+        if start_time.elapsed() >= timeout {
             return Ok(None);
         }
 
@@ -1,13 +1,16 @@
 mod arp;
 mod utils;
 
-use arp::send_arp_request;
+use std::net::Ipv4Addr;
+
+use arp::{find_interface, send_arp_request};
 use utils::{KNOWN_MACS_PATH, configure_progress_bar, load_known_macs};
 
 use rayon::ThreadPoolBuilder;
 use rayon::prelude::*;
 
 use crate::cli::types::OutputFormat;
+use crate::errors::ScanError;
 use crate::schemas::ArpResponse;
 
 /// Scans a given IPv4 address and prints the result.
@@ -20,7 +23,7 @@ pub fn scan_network(
     verbose: bool,
     num_threads: Option<usize>,
     retries: Option<usize>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<(), ScanError> {
     let timeout_secs = match timeout_secs {
         Some(timeout) => {
             if timeout > 0.0 {
@@ -32,21 +35,31 @@ pub fn scan_network(
         None => None,
     };
 
-    let parts: Vec<&str> = network_address_v4.split('/').collect();
-    if parts.len() != 2 {
-        return Err("Invalid IP address format. Expected format: xxx.xxx.xxx.xxx/x".into());
-    }
+    let invalid_network = |reason: &str| ScanError::InvalidNetwork {
+        network: network_address_v4.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let Some((ip, subnet_mask)) = network_address_v4.split_once('/') else {
+        return Err(invalid_network("expected format xxx.xxx.xxx.xxx/x"));
+    };
 
-    let ip = parts[0];
-    let subnet_mask: u8 = parts[1].parse()?;
+    let subnet_mask: u8 = subnet_mask
+        .parse()
+        .map_err(|_| invalid_network("invalid subnet mask"))?;
     if subnet_mask > 32 || subnet_mask < 8 {
-        return Err("Invalid subnet mask. It should be between 8 and 32.".into());
+        return Err(invalid_network(
+            "the subnet mask should be between 8 and 32",
+        ));
     }
 
-    let octets: Vec<u8> = ip.split('.').map(|s| s.parse().unwrap_or(0)).collect();
-    if octets.len() != 4 {
-        return Err("Invalid IP address format.".into());
-    }
+    let octets = ip
+        .parse::<Ipv4Addr>()
+        .map_err(|_| invalid_network("invalid IPv4 address"))?
+        .octets();
+
+    // Fail once here rather than in every ARP request
+    let interface = find_interface(interface_name)?;
 
     let mask = !0u32 << (32 - subnet_mask);
     let network_address = u32::from_be_bytes([octets[0], octets[1], octets[2], octets[3]]) & mask;
@@ -83,11 +96,12 @@ pub fn scan_network(
     let all_combinations = (first_second_octet..=last_second_octet)
         .flat_map(|second| {
             (first_third_octet..=last_third_octet).flat_map(move |third| {
-                (first_fourth_octet..=last_fourth_octet)
-                    .map(move |fourth| format!("{}.{}.{}.{}", first_octet, second, third, fourth))
+                (first_fourth_octet..=last_fourth_octet).map(move |fourth| {
+                    Ipv4Addr::new(first_octet, second as u8, third as u8, fourth as u8)
+                })
             })
         })
-        .collect::<Vec<String>>();
+        .collect::<Vec<Ipv4Addr>>();
 
     if verbose {
         println!(
@@ -103,7 +117,8 @@ pub fn scan_network(
     let pool = ThreadPoolBuilder::new()
         .num_threads(num_threads.unwrap_or_else(num_cpus::get))
         .thread_name(|i| format!("arp-scanner-{}", i))
-        .build()?;
+        .build()
+        .map_err(|e| ScanError::ThreadPool(e.to_string()))?;
 
     if verbose {
         println!(
@@ -118,12 +133,7 @@ pub fn scan_network(
             .filter_map(|target_ip| {
                 pb.set_message(format!("ARP request to {}", target_ip));
 
-                let arp_response = send_arp_request(
-                    target_ip.parse().unwrap(),
-                    interface_name,
-                    timeout_secs,
-                    retries,
-                );
+                let arp_response = send_arp_request(*target_ip, &interface, timeout_secs, retries);
 
                 pb.inc(1);
 
@@ -157,7 +167,5 @@ pub fn scan_network(
         );
     }
 
-    output_format.show_scanning_results(&arp_responses);
-
-    Ok(())
+    output_format.show_scanning_results(&arp_responses)
 }
@@ -0,0 +1,11 @@
+use std::net::SocketAddr;
+
+/// Information about the client behind a proxied connection.
+#[derive(Debug, Clone)]
+pub struct ClientContext {
+    /// Address of the client that opened the connection.
+    pub peer_addr: SocketAddr,
+
+    /// Authenticated proxy user, if any.
+    pub user: Option<String>,
+}
@@ -1,7 +1,9 @@
 pub mod arp;
+pub mod client;
 pub mod request;
 pub mod response;
 
 pub use arp::ArpResponse;
+pub use client::ClientContext;
 pub use request::{HttpRequest, HttpsRequest, Request};
 pub use response::{HttpResponse, HttpsResponse, Response};
@@ -2,6 +2,8 @@ use http::header::{HeaderMap, HeaderName, HeaderValue};
 use std::collections::HashMap;
 use std::str::FromStr;
 
+use crate::errors::ParseError;
+
 #[derive(Debug, Clone)]
 pub struct HttpRequest {
     pub method: String,
@@ -63,10 +65,12 @@ impl From<HttpsRequest> for Request {
     }
 }
 
-impl Into<HttpRequest> for Request {
-    fn into(self) -> HttpRequest {
-        match self {
-            Request::Http(req) => req,
+impl TryFrom<Request> for HttpRequest {
+    type Error = ParseError;
+
+    fn try_from(req: Request) -> Result<Self, Self::Error> {
+        match req {
+            Request::Http(req) => Ok(req),
             Request::Https(req) => {
                 let version = match req.version.as_str() {
                     "HTTP/0.9" => http::Version::HTTP_09,
@@ -77,26 +81,33 @@ impl Into<HttpRequest> for Request {
                     _ => http::Version::HTTP_11,
                 };
 
-                let headers = HeaderMap::try_from(&req.headers).ok().unwrap_or_else(|| {
-                    let mut map = HeaderMap::new();
-                    for (k, v) in req.headers {
-                        let header_name = HeaderName::from_str(&k)
-                            .expect(format!("Error parsing header name ({})", k).as_str());
-                        let header_value = HeaderValue::from_str(&v)
-                            .expect(format!("Error parsing header value ({})", v).as_str());
-                        map.insert(header_name, header_value);
+                // Repeated Set-Cookie lines are joined with CRLF by `parse_headers`
+                let mut headers = HeaderMap::new();
+                for (k, v) in req.headers {
+                    let header_name =
+                        HeaderName::from_str(&k).map_err(|_| ParseError::Header(k.clone()))?;
+                    for value in v.split("\r\nset-cookie: ") {
+                        let header_value = HeaderValue::from_str(value)
+                            .map_err(|_| ParseError::Header(k.clone()))?;
+                        headers.append(header_name.clone(), header_value);
                     }
+                }
 
-                    map
-                });
+                let uri = req
+                    .uri
+                    .parse()
+                    .map_err(|e: http::uri::InvalidUri| ParseError::Uri {
+                        uri: req.uri.clone(),
+                        reason: e.to_string(),
+                    })?;
 
-                HttpRequest {
+                Ok(HttpRequest {
                     method: req.method,
-                    uri: req.uri.parse().unwrap_or_default(),
+                    uri,
                     version,
                     headers,
                     body: req.body.map(bytes::Bytes::from),
-                }
+                })
             }
         }
     }
@@ -0,0 +1,133 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::acl::IpCidr;
+use crate::utils::authority::Authority;
+
+/// How clients reach a proxy listener.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListenerMode {
+    /// Clients are configured to use the proxy and send CONNECT or absolute-form requests.
+    #[default]
+    Explicit,
+    /// Traffic is redirected to the listener by the firewall (e.g. iptables REDIRECT), the
+    /// destination comes from the original socket address, the SNI or the Host header.
+    Transparent,
+}
+
+/// A proxy listener, written `host:port[,transparent][,proxy-protocol,trusted-proxy=CIDR...]` on
+/// the command line and in the config file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ListenerConfig {
+    pub host: String,
+    pub port: u16,
+    pub mode: ListenerMode,
+    /// Expect a PROXY protocol (v1 or v2) header before the client data, sent by a load
+    /// balancer in front of the proxy. The address it carries replaces the peer address.
+    pub proxy_protocol: bool,
+    /// Peers allowed to send the PROXY protocol header, connections from any other peer are
+    /// dropped since the header lets them pick their client address
+    pub trusted_proxies: Vec<IpCidr>,
+}
+
+impl ListenerConfig {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            mode: ListenerMode::default(),
+            proxy_protocol: false,
+            trusted_proxies: Vec::new(),
+        }
+    }
+
+    /// Whether the peer may send a PROXY protocol header on this listener.
+    pub fn is_trusted_proxy(&self, peer: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|cidr| cidr.contains(peer))
+    }
+}
+
+impl FromStr for ListenerConfig {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split(',').map(str::trim);
+
+        let address = parts.next().unwrap_or_default();
+        let Authority { host, port } = Authority::parse(address, 0).map_err(|e| e.to_string())?;
+        if port == 0 {
+            return Err(format!("Missing port in listener '{}'", value));
+        }
+
+        let mut listener = ListenerConfig::new(host, port);
+        for option in parts {
+            match option.split_once('=') {
+                Some(("trusted-proxy", cidr)) => listener.trusted_proxies.push(
+                    cidr.parse()
+                        .map_err(|e| format!("{} in listener '{}'", e, value))?,
+                ),
+                _ if option == "explicit" => listener.mode = ListenerMode::Explicit,
+                _ if option == "transparent" => listener.mode = ListenerMode::Transparent,
+                _ if option == "proxy-protocol" => listener.proxy_protocol = true,
+                _ => {
+                    return Err(format!(
+                        "Unknown option '{}' in listener '{}' (expected transparent, explicit, proxy-protocol or trusted-proxy=CIDR)",
+                        option, value
+                    ));
+                }
+            }
+        }
+
+        match (listener.proxy_protocol, listener.trusted_proxies.is_empty()) {
+            (true, true) => Err(format!(
+                "Listener '{}' uses proxy-protocol without any trusted-proxy=CIDR, every client could pick its address",
+                value
+            )),
+            (false, false) => Err(format!(
+                "Listener '{}' has trusted proxies but does not use proxy-protocol",
+                value
+            )),
+            _ => Ok(listener),
+        }
+    }
+}
+
+impl fmt::Display for ListenerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let authority = Authority {
+            host: self.host.clone(),
+            port: self.port,
+        };
+        write!(f, "{}", authority)?;
+
+        if self.mode == ListenerMode::Transparent {
+            write!(f, ",transparent")?;
+        }
+        if self.proxy_protocol {
+            write!(f, ",proxy-protocol")?;
+        }
+        for cidr in &self.trusted_proxies {
+            write!(f, ",trusted-proxy={}", cidr)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for ListenerConfig {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ListenerConfig> for String {
+    fn from(listener: ListenerConfig) -> Self {
+        listener.to_string()
+    }
+}
@@ -1,109 +1,359 @@
+mod listener;
+mod proxy_protocol;
+mod transparent;
 mod utils;
 
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
+use futures_util::future::try_join_all;
+use http::Request;
+use hyper::body::Incoming;
 use hyper::service::service_fn;
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto;
-use tokio::net::TcpListener;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::Instrument;
 
+use crate::acl::{AclScope, is_client_allowed};
+use crate::auth::authenticate;
 use crate::config::get_global_config;
-use crate::filters::is_domain_blacklisted;
+use crate::errors::{FilterError, error_code, https_error_response};
+use crate::filters::{is_blocking_paused, is_domain_blocked};
+use crate::limits::{ThrottledStream, try_acquire_connection};
 use crate::proxy::{
     process_http_request, process_https_request, process_https_request_with_interception,
 };
+use crate::schemas::ClientContext;
+use crate::shutdown::{shutdown_token, spawn_connection};
 use crate::utils::{
-    DNS_RESOLVER,
-    buffer::{parse_first_line_buffer, read_first_line_buffer},
+    authority::Authority,
+    buffer::{parse_first_line_buffer, read_first_line_buffer, read_headers_buffer},
+    http::write_response,
+    listen::{ListenFamily, bind_tcp_listeners},
 };
-use utils::intercept_https_request;
+pub use listener::{ListenerConfig, ListenerMode};
+use proxy_protocol::read_proxy_header;
+use transparent::{original_destination, serve_transparent_connection};
+use utils::{
+    intercept_https_request, peek_headers_buffer, proxy_auth_required_raw,
+    proxy_auth_required_response, proxy_authorization_from_buffer,
+};
+
+/// Time allowed for a load balancer to send the PROXY protocol header.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Check the `Proxy-Authorization` header of a plain HTTP request before forwarding it.
+async fn authorize_http_request(
+    mut req: Request<Incoming>,
+    peer_addr: SocketAddr,
+) -> Result<http::Response<http_body_util::Full<bytes::Bytes>>, std::convert::Infallible> {
+    let config = get_global_config();
+
+    let proxy_authorization = req
+        .headers_mut()
+        .remove(http::header::PROXY_AUTHORIZATION)
+        .and_then(|value| value.to_str().ok().map(|v| v.to_string()));
+    // Without a header there is nothing to verify, the request is anonymous
+    let user = match proxy_authorization.as_deref() {
+        Some(value) => authenticate(value).await,
+        None => None,
+    };
+
+    if config.require_auth && user.is_none() {
+        tracing::warn!("Rejected unauthenticated HTTP request for {}", req.uri());
+        return Ok(proxy_auth_required_response());
+    }
+
+    let span = tracing::info_span!("ProxyClient", user = user.as_deref().unwrap_or("anonymous"));
+    let client = ClientContext { peer_addr, user };
+    process_http_request(req, client).instrument(span).await
+}
 
-#[tracing::instrument(level = "info", name = "Server")]
+#[tracing::instrument(level = "info", name = "Server", skip_all)]
 pub async fn start_proxy_server(
-    host: String,
-    port: u16,
-    is_v4: Option<bool>,
+    listeners: Vec<ListenerConfig>,
+    family: ListenFamily,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let lookup = DNS_RESOLVER.lookup_ip(host).await?;
-    let ip = match is_v4 {
-        Some(false) => lookup
-            .iter()
-            .find(|ip| ip.is_ipv6())
-            .ok_or("No IPv6 address found for the specified host")?,
-        _ => lookup
-            .iter()
-            .find(|ip| ip.is_ipv4())
-            .ok_or("No IPv4 address found for the specified host")?,
-    };
+    let mut bound = Vec::new();
+    for listener_config in listeners {
+        let listener_config = Arc::new(listener_config);
+        for listener in
+            bind_tcp_listeners(&listener_config.host, listener_config.port, family).await?
+        {
+            tracing::info!(
+                mode = ?listener_config.mode,
+                proxy_protocol = listener_config.proxy_protocol,
+                "Starting proxy server at http://{}",
+                listener.local_addr()?
+            );
+            bound.push((listener, listener_config.clone()));
+        }
+    }
 
-    let addr = SocketAddr::new(ip, port);
-    tracing::info!("Starting proxy server at http://{}", addr);
+    try_join_all(
+        bound
+            .into_iter()
+            .map(|(listener, listener_config)| serve_proxy_listener(listener, listener_config)),
+    )
+    .await?;
+    Ok(())
+}
 
-    let listener = TcpListener::bind(addr).await?;
+async fn serve_proxy_listener(
+    listener: TcpListener,
+    listener_config: Arc<ListenerConfig>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let shutdown = shutdown_token();
 
     loop {
-        let (mut stream, peer_addr) = listener.accept().await?;
+        let (stream, peer_addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.cancelled() => {
+                tracing::info!("Proxy server stopped accepting connections");
+                return Ok(());
+            }
+        };
+
+        spawn_connection(serve_proxy_connection(
+            stream,
+            peer_addr,
+            listener_config.clone(),
+        ));
+    }
+}
 
-        tracing::info!("Accepted connection from {}", peer_addr);
+async fn serve_proxy_connection(
+    mut stream: TcpStream,
+    mut peer_addr: SocketAddr,
+    listener_config: Arc<ListenerConfig>,
+) {
+    let mut destination = None;
+    if listener_config.proxy_protocol {
+        // Anyone else could send a header naming any client address
+        if !listener_config.is_trusted_proxy(peer_addr.ip()) {
+            tracing::warn!(
+                "Dropping connection from {}, not a trusted proxy of the PROXY protocol listener",
+                peer_addr
+            );
+            return;
+        }
 
-        tokio::task::spawn(async move {
-            let config = get_global_config();
+        match tokio::time::timeout(PROXY_HEADER_TIMEOUT, read_proxy_header(&mut stream)).await {
+            Ok(Ok(Some(header))) => {
+                tracing::debug!(
+                    "PROXY protocol header from {}: client {}, destination {}",
+                    peer_addr,
+                    header.source,
+                    header.destination
+                );
+                peer_addr = header.source;
+                destination = Some(header.destination);
+            }
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => {
+                tracing::warn!("Invalid PROXY protocol header from {}: {}", peer_addr, e);
+                return;
+            }
+            Err(_) => {
+                tracing::warn!(
+                    "Timed out waiting for PROXY protocol header from {}",
+                    peer_addr
+                );
+                return;
+            }
+        }
+    }
 
-            let mut buffer = vec![0u8; 1024];
-            match stream.peek(&mut buffer).await {
-                Ok(n) if n > 0 => {
-                    if buffer.starts_with(b"CONNECT") {
-                        tracing::info!("Detected HTTPS connection from {}", peer_addr);
+    // Checked after the PROXY protocol header, so the rules apply to the real client
+    if !is_client_allowed(peer_addr.ip(), AclScope::Proxy) {
+        tracing::warn!("Connection from {} denied by access control", peer_addr);
+        return;
+    }
 
-                        let first_line = read_first_line_buffer(buffer.as_ref())
-                            .await
-                            .unwrap_or_default();
-                        let (_, authority, _) =
-                            parse_first_line_buffer(first_line).unwrap_or_default();
-                        let host = authority.split(':').next().unwrap_or_default().to_string();
+    // Released when the task finishes, whatever path the connection took
+    let _connection_guard = match try_acquire_connection(peer_addr.ip()) {
+        Ok(guard) => guard,
+        Err(e) => {
+            tracing::warn!("Connection from {} rejected: {}", peer_addr, e);
+            return;
+        }
+    };
+
+    tracing::info!("Accepted connection from {}", peer_addr);
+
+    match listener_config.mode {
+        ListenerMode::Explicit => serve_explicit_connection(stream, peer_addr).await,
+        ListenerMode::Transparent => {
+            let destination = match destination {
+                Some(destination) => destination,
+                None => match original_destination(&stream) {
+                    Ok(destination) => destination,
+                    Err(e) => {
+                        tracing::error!(
+                            "Unable to get the original destination of {}: {}",
+                            peer_addr,
+                            e
+                        );
+                        return;
+                    }
+                },
+            };
 
-                        if config.block_ads && is_domain_blacklisted(&host) {
-                            tracing::info!("The host {} is blacklisted, closing connection", host);
-                            return;
+            // Transparent clients cannot answer a 407, access is controlled by the ACL only
+            let client = ClientContext {
+                peer_addr,
+                user: None,
+            };
+            let span = tracing::info_span!("ProxyClient", user = "anonymous");
+            if let Err(e) = serve_transparent_connection(stream, client, destination)
+                .instrument(span)
+                .await
+            {
+                tracing::error!(
+                    error_code = error_code(&*e),
+                    "Error serving transparent connection from {}: {}",
+                    peer_addr,
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Serve a client that is configured to use the proxy: CONNECT tunnels (or interception) and
+/// absolute-form HTTP requests.
+async fn serve_explicit_connection(mut stream: TcpStream, peer_addr: SocketAddr) {
+    let config = get_global_config();
+
+    let mut buffer = vec![0u8; 8 * 1024];
+    match stream.peek(&mut buffer).await {
+        Ok(n) if n > 0 => {
+            if buffer.starts_with(b"CONNECT") {
+                tracing::info!("Detected HTTPS connection from {}", peer_addr);
+
+                // The head may arrive in several segments, the credentials can be in any of them
+                let buffer = match peek_headers_buffer(&stream).await {
+                    Ok(buffer) => buffer,
+                    Err(e) => {
+                        tracing::warn!(
+                            "Unable to read the CONNECT request from {}: {}",
+                            peer_addr,
+                            e
+                        );
+                        return;
+                    }
+                };
+
+                let proxy_authorization = proxy_authorization_from_buffer(&buffer);
+                let user = match proxy_authorization.as_deref() {
+                    Some(value) => authenticate(value).await,
+                    None => None,
+                };
+                if config.require_auth && user.is_none() {
+                    tracing::warn!(
+                        "Rejected unauthenticated CONNECT request from {}",
+                        peer_addr
+                    );
+                    // Read the CONNECT request first, closing with unread data resets the connection
+                    let _ = read_headers_buffer(&mut stream).await;
+                    if let Err(e) = stream.write_all(proxy_auth_required_raw().as_bytes()).await {
+                        tracing::error!("Error sending 407 to {}: {}", peer_addr, e);
+                    }
+                    return;
+                }
+
+                let span = tracing::info_span!(
+                    "ProxyClient",
+                    user = user.as_deref().unwrap_or("anonymous")
+                );
+                let client = ClientContext { peer_addr, user };
+                async {
+                    let first_line = read_first_line_buffer(buffer.as_ref())
+                        .await
+                        .unwrap_or_default();
+                    let (_, authority, version) =
+                        parse_first_line_buffer(first_line).unwrap_or_default();
+                    let host = Authority::parse(&authority, 443)
+                        .map(|authority| authority.host)
+                        .unwrap_or_default();
+
+                    if config.block_ads
+                        && is_client_allowed(peer_addr.ip(), AclScope::BlockAds)
+                        && !is_blocking_paused(peer_addr.ip())
+                        && is_domain_blocked(&host)
+                    {
+                        tracing::info!(
+                            error_code = "FILTER_BLOCKED",
+                            "The host {} is blacklisted, returning 403 Forbidden",
+                            host
+                        );
+                        // Read the CONNECT request first, closing with unread data resets the connection
+                        let _ = read_headers_buffer(&mut stream).await;
+                        let response =
+                            https_error_response(&FilterError::Blocked { host }, &version);
+                        if let Err(e) = write_response(&mut stream, &response).await {
+                            tracing::debug!("Unable to send the error page: {}", e);
                         }
+                        return;
+                    }
 
-                        match intercept_https_request(host.as_str(), Some(config)) {
-                            true => {
-                                if let Err(e) =
-                                    process_https_request_with_interception(&mut stream).await
-                                {
-                                    tracing::error!(
-                                        "Error processing HTTPS request (interception): {e}"
-                                    );
-                                }
-                                // It's not possible to implement a fallback to restart the connection here, because all the
-                                // buffer were consumed previously, so we just return and the client will have to restart the connection
-                            }
-                            false => {
-                                if let Err(e) = process_https_request(&mut stream).await {
-                                    tracing::error!("Error processing HTTPS request (tunnel): {e}");
-                                }
+                    match intercept_https_request(host.as_str(), &client, Some(config)) {
+                        true => {
+                            if let Err(e) =
+                                process_https_request_with_interception(&mut stream, &client).await
+                            {
+                                tracing::error!(
+                                    error_code = error_code(&*e),
+                                    "Error processing HTTPS request (interception): {e}"
+                                );
                             }
+                            // It's not possible to implement a fallback to restart the connection here, because all the
+                            // buffer were consumed previously, so we just return and the client will have to restart the connection
                         }
-                    } else {
-                        tracing::info!("Detected HTTP connection from {}", peer_addr);
-
-                        let io = TokioIo::new(stream);
-                        if let Err(err) = auto::Builder::new(TokioExecutor::new())
-                            .serve_connection(io, service_fn(process_http_request))
-                            .await
-                        {
-                            tracing::error!("Error serving connection: {}", err);
+                        false => {
+                            if let Err(e) = process_https_request(&mut stream, &client).await {
+                                tracing::error!(
+                                    error_code = error_code(&*e),
+                                    "Error processing HTTPS request (tunnel): {e}"
+                                );
+                            }
                         }
                     }
                 }
-                Ok(_) => {
-                    tracing::warn!("No data received from {}", peer_addr);
-                }
-                Err(e) => {
-                    tracing::error!("Error peeking into stream from {}: {}", peer_addr, e);
+                .instrument(span)
+                .await;
+            } else {
+                tracing::info!("Detected HTTP connection from {}", peer_addr);
+
+                let io = TokioIo::new(ThrottledStream::for_client(stream, peer_addr.ip()));
+                let builder = auto::Builder::new(TokioExecutor::new());
+                let connection = builder.serve_connection(
+                    io,
+                    service_fn(move |req| authorize_http_request(req, peer_addr)),
+                );
+                tokio::pin!(connection);
+
+                // On shutdown let the in-flight request finish, then close the keep-alive connection
+                let result = tokio::select! {
+                    result = connection.as_mut() => result,
+                    _ = shutdown_token().cancelled_owned() => {
+                        connection.as_mut().graceful_shutdown();
+                        connection.await
+                    }
+                };
+                if let Err(err) = result {
+                    tracing::error!("Error serving connection: {}", err);
                 }
-            };
-        });
-    }
+            }
+        }
+        Ok(_) => {
+            tracing::warn!("No data received from {}", peer_addr);
+        }
+        Err(e) => {
+            tracing::error!("Error peeking into stream from {}: {}", peer_addr, e);
+        }
+    };
 }
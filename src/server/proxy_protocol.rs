@@ -0,0 +1,122 @@
+// PROXY protocol (https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt): load balancers
+// prepend the original client and destination addresses to the connection, as a text line (v1)
+// or a binary block (v2). Only the header is consumed, the client data stays in the stream.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// Longest v1 line allowed by the specification, CRLF included.
+const V1_MAX_LENGTH: usize = 107;
+
+/// Addresses carried by a PROXY protocol header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyHeader {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+/// Read the PROXY protocol header at the start of the stream. Returns `None` for headers without
+/// addresses (v1 `UNKNOWN`, v2 `LOCAL` health checks), where the peer address stays valid.
+pub async fn read_proxy_header(
+    stream: &mut TcpStream,
+) -> Result<Option<ProxyHeader>, Box<dyn std::error::Error + Send + Sync>> {
+    // Six bytes tell the versions apart and are shorter than any valid header of either
+    let mut prefix = [0u8; 6];
+    stream.read_exact(&mut prefix).await?;
+
+    if V2_SIGNATURE.starts_with(&prefix) {
+        let mut header = [0u8; 16];
+        header[..6].copy_from_slice(&prefix);
+        stream.read_exact(&mut header[6..]).await?;
+        if !header.starts_with(V2_SIGNATURE) {
+            return Err("Invalid PROXY protocol v2 signature".into());
+        }
+
+        let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+        let mut addresses = vec![0u8; length];
+        stream.read_exact(&mut addresses).await?;
+
+        return parse_v2(header[12], header[13], &addresses);
+    }
+
+    if prefix == V1_PREFIX {
+        let mut line = prefix.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err("PROXY protocol v1 header too long".into());
+            }
+            line.push(stream.read_u8().await?);
+        }
+
+        return parse_v1(&line);
+    }
+
+    Err("Missing PROXY protocol header".into())
+}
+
+fn parse_v1(line: &[u8]) -> Result<Option<ProxyHeader>, Box<dyn std::error::Error + Send + Sync>> {
+    let line = std::str::from_utf8(line)?.trim_end_matches("\r\n");
+    let fields = line.split(' ').collect::<Vec<&str>>();
+
+    match fields[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        [
+            "PROXY",
+            "TCP4" | "TCP6",
+            source,
+            destination,
+            source_port,
+            destination_port,
+        ] => Ok(Some(ProxyHeader {
+            source: SocketAddr::new(source.parse()?, source_port.parse()?),
+            destination: SocketAddr::new(destination.parse()?, destination_port.parse()?),
+        })),
+        _ => Err(format!("Malformed PROXY protocol v1 header: {}", line).into()),
+    }
+}
+
+fn parse_v2(
+    version_command: u8,
+    family: u8,
+    addresses: &[u8],
+) -> Result<Option<ProxyHeader>, Box<dyn std::error::Error + Send + Sync>> {
+    if version_command >> 4 != 2 {
+        return Err("Unsupported PROXY protocol version".into());
+    }
+
+    match version_command & 0x0f {
+        // LOCAL: the connection was opened by the load balancer itself
+        0x0 => return Ok(None),
+        0x1 => {}
+        command => return Err(format!("Unknown PROXY protocol command {:#x}", command).into()),
+    }
+
+    let port = |offset: usize| u16::from_be_bytes([addresses[offset], addresses[offset + 1]]);
+
+    // The high nibble is the address family, the low one the transport (only STREAM is proxied)
+    match family >> 4 {
+        0x1 if addresses.len() >= 12 => {
+            let source = <[u8; 4]>::try_from(&addresses[0..4])?;
+            let destination = <[u8; 4]>::try_from(&addresses[4..8])?;
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(IpAddr::V4(Ipv4Addr::from(source)), port(8)),
+                destination: SocketAddr::new(IpAddr::V4(Ipv4Addr::from(destination)), port(10)),
+            }))
+        }
+        0x2 if addresses.len() >= 36 => {
+            let source = <[u8; 16]>::try_from(&addresses[0..16])?;
+            let destination = <[u8; 16]>::try_from(&addresses[16..32])?;
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(IpAddr::V6(Ipv6Addr::from(source)), port(32)),
+                destination: SocketAddr::new(IpAddr::V6(Ipv6Addr::from(destination)), port(34)),
+            }))
+        }
+        // UNSPEC and UNIX sockets carry no usable address
+        0x0 | 0x3 => Ok(None),
+        _ => Err("Truncated PROXY protocol v2 addresses".into()),
+    }
+}
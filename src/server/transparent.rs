@@ -0,0 +1,194 @@
+// Transparent mode: clients do not know about the proxy, the firewall redirects their traffic to
+// the listener. TLS connections are routed by SNI, plain HTTP ones by the Host header, both fall
+// back to the original destination address kept by the redirect.
+
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+
+use bytes::Bytes;
+use http::{Request, Response, Uri};
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use tokio::net::TcpStream;
+use uuid::Uuid;
+
+use crate::acl::{AclScope, is_client_allowed};
+use crate::config::get_global_config;
+use crate::errors::{ErrorCode, ParseError, error_response};
+use crate::filters::{is_blocking_paused, is_domain_blocked};
+use crate::limits::ThrottledStream;
+use crate::proxy::{intercept_tls_connection, process_http_request};
+use crate::schemas::ClientContext;
+use crate::shutdown::shutdown_token;
+use crate::utils::{
+    authority::{Authority, normalize_hostname},
+    connect::connect_to_host,
+    tls::{parse_sni, peek_client_hello},
+};
+
+use super::utils::intercept_https_request;
+
+/// Destination the client connected to before the firewall redirected it (`SO_ORIGINAL_DST`).
+#[cfg(target_os = "linux")]
+pub fn original_destination(stream: &TcpStream) -> io::Result<SocketAddr> {
+    let socket = socket2::SockRef::from(stream);
+    let address = match stream.local_addr()? {
+        SocketAddr::V4(_) => socket.original_dst_v4()?,
+        SocketAddr::V6(_) => socket.original_dst_v6()?,
+    };
+
+    address
+        .as_socket()
+        .ok_or_else(|| io::Error::other("Original destination is not an IP address"))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn original_destination(_stream: &TcpStream) -> io::Result<SocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Transparent mode is only supported on Linux",
+    ))
+}
+
+pub async fn serve_transparent_connection(
+    mut stream: TcpStream,
+    client: ClientContext,
+    destination: SocketAddr,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Without a redirect rule the original destination is the listener itself
+    if destination == stream.local_addr()? {
+        return Err("Connection was not redirected to the transparent listener".into());
+    }
+
+    match peek_client_hello(&stream).await? {
+        Some(client_hello) => {
+            let sni = parse_sni(&client_hello).map(|sni| normalize_hostname(&sni));
+            let host = sni.clone().unwrap_or_else(|| destination.ip().to_string());
+            tracing::info!(
+                "Detected transparent TLS connection from {} for {}",
+                client.peer_addr,
+                host
+            );
+
+            if is_blocked(&host, &client) {
+                tracing::info!(
+                    error_code = "FILTER_BLOCKED",
+                    "The host {} is blacklisted, closing connection",
+                    host
+                );
+                return Ok(());
+            }
+
+            // A certificate can only be issued for a name, connections without SNI are tunneled
+            if sni.is_some() && intercept_https_request(&host, &client, None) {
+                return intercept_tls_connection(
+                    Uuid::new_v4(),
+                    &mut stream,
+                    &client,
+                    &host,
+                    destination.port(),
+                    "HTTP/1.1",
+                )
+                .await;
+            }
+
+            tunnel(stream, &client, destination).await
+        }
+        None => {
+            tracing::info!(
+                "Detected transparent HTTP connection from {}",
+                client.peer_addr
+            );
+
+            let io = TokioIo::new(ThrottledStream::for_client(stream, client.peer_addr.ip()));
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection(
+                io,
+                service_fn(move |req| transparent_http_request(req, client.clone(), destination)),
+            );
+            tokio::pin!(connection);
+
+            tokio::select! {
+                result = connection.as_mut() => result,
+                _ = shutdown_token().cancelled_owned() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            }
+        }
+    }
+}
+
+fn is_blocked(host: &str, client: &ClientContext) -> bool {
+    get_global_config().block_ads
+        && is_client_allowed(client.peer_addr.ip(), AclScope::BlockAds)
+        && !is_blocking_paused(client.peer_addr.ip())
+        && is_domain_blocked(host)
+}
+
+/// Relay the connection untouched to the original destination.
+async fn tunnel(
+    stream: TcpStream,
+    client: &ClientContext,
+    destination: SocketAddr,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut dest_stream =
+        connect_to_host(&destination.ip().to_string(), destination.port()).await?;
+    tracing::info!("Established transparent tunnel to {}", destination);
+
+    let mut client_stream = ThrottledStream::for_client(stream, client.peer_addr.ip());
+    let (bytes_up, bytes_down) =
+        tokio::io::copy_bidirectional(&mut client_stream, &mut dest_stream).await?;
+    tracing::info!(
+        bytes_up,
+        bytes_down,
+        "Closed transparent tunnel to {}",
+        destination
+    );
+
+    Ok(())
+}
+
+/// Turn the origin-form request of a transparent client into the absolute form used by the
+/// explicit proxy, taking the authority from the Host header or the original destination.
+async fn transparent_http_request(
+    mut req: Request<Incoming>,
+    client: ClientContext,
+    destination: SocketAddr,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if req.uri().authority().is_none() {
+        let authority = req
+            .headers()
+            .get(http::header::HOST)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| Authority::parse(value, destination.port()).ok())
+            .unwrap_or(Authority {
+                host: destination.ip().to_string(),
+                port: destination.port(),
+            });
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+
+        let uri = format!("http://{}{}", authority, path);
+        match uri.parse::<Uri>() {
+            Ok(uri) => *req.uri_mut() = uri,
+            Err(e) => {
+                let error = ParseError::Uri {
+                    uri,
+                    reason: e.to_string(),
+                };
+                tracing::warn!(error_code = error.code(), "{}", error);
+                return Ok(error_response(&error));
+            }
+        }
+    }
+
+    process_http_request(req, client).await
+}
@@ -1,13 +1,36 @@
-use crate::config::{ProxyConfig, get_global_config};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::{HeaderValue, Response};
+use http_body_util::Full;
+use tokio::net::TcpStream;
+
+use crate::acl::{AclScope, is_client_allowed};
+use crate::config::{AUTH_REALM, ProxyConfig, get_global_config};
 use crate::filters::is_domain_whitelisted;
+use crate::schemas::ClientContext;
+use crate::utils::http::parse_headers;
 
-pub fn intercept_https_request(host: &str, config: Option<ProxyConfig>) -> bool {
+pub fn intercept_https_request(
+    host: &str,
+    client: &ClientContext,
+    config: Option<Arc<ProxyConfig>>,
+) -> bool {
     let config = config.unwrap_or_else(get_global_config);
 
     if !config.intercept_tls {
         return false;
     }
 
+    if !is_client_allowed(client.peer_addr.ip(), AclScope::Intercept) {
+        tracing::info!(
+            "Client {} is not eligible for interception, not intercepting",
+            client.peer_addr
+        );
+        return false;
+    }
+
     let is_host_whitelisted = is_domain_whitelisted(host);
     if is_host_whitelisted {
         tracing::info!("The host {} is whitelisted, not intercepting", host);
@@ -30,3 +53,77 @@ pub fn intercept_https_request(host: &str, config: Option<ProxyConfig>) -> bool
     // Meanwhile, we intercept the traffic always, but this will be changed
     true
 }
+
+/// Raw 407 response written to CONNECT clients, which are handled before any HTTP framing.
+pub fn proxy_auth_required_raw() -> String {
+    format!(
+        "HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"{}\"\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        AUTH_REALM
+    )
+}
+
+pub fn proxy_auth_required_response() -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from("407 Proxy Authentication Required")));
+    *response.status_mut() = http::StatusCode::PROXY_AUTHENTICATION_REQUIRED;
+    if let Ok(value) = HeaderValue::from_str(&format!("Basic realm=\"{}\"", AUTH_REALM)) {
+        response
+            .headers_mut()
+            .insert(http::header::PROXY_AUTHENTICATE, value);
+    }
+    response
+}
+
+/// Largest request head accepted, the same limit as when the head is read.
+const MAX_HEADERS_SIZE: usize = 64 * 1024;
+
+/// Time allowed for the rest of the request head once its first bytes arrived.
+const HEADERS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Peek a whole request head without consuming it, waiting for the segments it is split into.
+/// The request is still read afterwards by whichever handler serves the connection.
+pub async fn peek_headers_buffer(
+    stream: &TcpStream,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let peek = async {
+        let mut buffer = vec![0u8; 8 * 1024];
+        let mut previous = 0;
+        loop {
+            let n = stream.peek(&mut buffer).await?;
+            if n == 0 {
+                return Err("Connection closed before complete headers".into());
+            }
+
+            let head = &buffer[..n];
+            if head.windows(4).any(|w| w == b"\r\n\r\n") || head.windows(2).any(|w| w == b"\n\n") {
+                buffer.truncate(n);
+                return Ok(buffer);
+            }
+            if n == buffer.len() {
+                if n >= MAX_HEADERS_SIZE {
+                    return Err("Headers too large (possible attack)".into());
+                }
+                buffer.resize((n * 2).min(MAX_HEADERS_SIZE), 0);
+            } else if n == previous {
+                // Peeking does not consume the data, so it returns at once until more arrives
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            previous = n;
+        }
+    };
+
+    tokio::time::timeout(HEADERS_TIMEOUT, peek)
+        .await
+        .map_err(|_| "Timed out waiting for the request headers")?
+}
+
+/// Extract the `Proxy-Authorization` header from the peeked bytes of a CONNECT request.
+pub fn proxy_authorization_from_buffer(buffer: &[u8]) -> Option<String> {
+    let buffer = String::from_utf8_lossy(buffer);
+    let header_lines = buffer
+        .split("\r\n")
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .collect::<Vec<&str>>();
+
+    parse_headers(&header_lines).remove("proxy-authorization")
+}
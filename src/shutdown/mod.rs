@@ -0,0 +1,84 @@
+// Graceful shutdown: SIGTERM/SIGINT stop the listeners, then in-flight connections get until a
+// deadline to finish before the process exits.
+
+use std::future::Future;
+use std::sync::LazyLock;
+
+use tokio::task::JoinHandle;
+use tokio::time::{self as TokioTime, Duration};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+static SHUTDOWN_TOKEN: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+static CONNECTION_TRACKER: LazyLock<TaskTracker> = LazyLock::new(TaskTracker::new);
+
+/// Token cancelled once shutdown starts, listeners stop accepting when it fires.
+pub fn shutdown_token() -> CancellationToken {
+    SHUTDOWN_TOKEN.clone()
+}
+
+pub fn is_shutting_down() -> bool {
+    SHUTDOWN_TOKEN.is_cancelled()
+}
+
+/// Spawn a connection task that shutdown waits for while draining.
+pub fn spawn_connection<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    CONNECTION_TRACKER.spawn(future)
+}
+
+pub fn active_connections() -> usize {
+    CONNECTION_TRACKER.len()
+}
+
+/// Wait for SIGTERM or SIGINT (Ctrl+C), returning the name of the received signal.
+pub async fn wait_for_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut sigterm = match signal(SignalKind::terminate()) {
+            Ok(sigterm) => sigterm,
+            Err(e) => {
+                tracing::error!("Failed to install SIGTERM handler: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return "SIGINT";
+            }
+        };
+
+        tokio::select! {
+            _ = sigterm.recv() => "SIGTERM",
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "SIGINT"
+    }
+}
+
+/// Stop accepting connections and wait up to `timeout` for the open ones to finish.
+/// Returns the number of connections still open when the deadline was reached.
+pub async fn drain_connections(timeout: Duration) -> usize {
+    SHUTDOWN_TOKEN.cancel();
+    CONNECTION_TRACKER.close();
+
+    let pending = active_connections();
+    if pending > 0 {
+        tracing::info!(
+            "Waiting up to {:?} for {} open connections to finish",
+            timeout,
+            pending
+        );
+    }
+
+    match TokioTime::timeout(timeout, CONNECTION_TRACKER.wait()).await {
+        Ok(()) => 0,
+        Err(_) => active_connections(),
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+
+use crate::errors::ParseError;
+
+/// Host and port of a request authority. The host is stored without IPv6 brackets, lowercased
+/// and in its ASCII (IDNA) form, ready for DNS lookups, filtering and certificates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authority {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Authority {
+    /// Parse `host`, `host:port`, `[v6]`, `[v6]:port` or a bare IPv6 literal, falling back to
+    /// `default_port` when the port is omitted.
+    pub fn parse(value: &str, default_port: u16) -> Result<Self, ParseError> {
+        let value = value.trim();
+        let invalid = |reason: &str| ParseError::Authority {
+            authority: value.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let (host, port) = if let Some(rest) = value.strip_prefix('[') {
+            let (host, rest) = rest
+                .split_once(']')
+                .ok_or_else(|| invalid("unclosed IPv6 bracket"))?;
+            host.parse::<Ipv6Addr>()
+                .map_err(|_| invalid("invalid IPv6 address"))?;
+
+            match rest {
+                "" => (host, None),
+                _ => match rest.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(invalid("unexpected characters after the IPv6 address")),
+                },
+            }
+        } else if value.parse::<Ipv6Addr>().is_ok() {
+            (value, None)
+        } else {
+            match value.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (value, None),
+            }
+        };
+
+        let port = match port {
+            Some(port) => port.parse::<u16>().map_err(|_| invalid("invalid port"))?,
+            None => default_port,
+        };
+
+        let host = normalize_hostname(host);
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+
+        Ok(Authority { host, port })
+    }
+
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.host.parse().ok()
+    }
+}
+
+impl fmt::Display for Authority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ip() {
+            Some(IpAddr::V6(_)) => write!(f, "[{}]:{}", self.host, self.port),
+            _ => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// Lowercase a host name, drop the trailing dot and convert internationalized names to their
+/// ASCII form. IP literals are returned in canonical form.
+pub fn normalize_hostname(host: &str) -> String {
+    let host = host.trim().trim_end_matches('.');
+
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return ip.to_string();
+    }
+
+    match idna::domain_to_ascii(host) {
+        Ok(ascii) => ascii,
+        Err(_) => host.to_ascii_lowercase(),
+    }
+}
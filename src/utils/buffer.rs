@@ -1,5 +1,7 @@
 use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
 
+use crate::errors::ParseError;
+
 pub async fn read_first_line_buffer(
     buffer: &[u8],
 ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
@@ -15,11 +17,9 @@ pub async fn read_first_line_buffer(
     Ok(first_line.trim_end_matches(&['\r', '\n'][..]).to_string())
 }
 
-pub fn parse_first_line_buffer(
-    buffer: String,
-) -> Result<(String, String, String), Box<dyn std::error::Error + Send + Sync>> {
+pub fn parse_first_line_buffer(buffer: String) -> Result<(String, String, String), ParseError> {
     let [method, authority, version] = buffer.split(' ').collect::<Vec<&str>>()[..] else {
-        return Err("Malformed HTTPS request line".into());
+        return Err(ParseError::RequestLine(buffer));
     };
 
     Ok((
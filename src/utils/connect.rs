@@ -0,0 +1,125 @@
+use std::net::{IpAddr, SocketAddr};
+
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::{self as TokioTime, Duration};
+
+use crate::config::get_global_config;
+use crate::dns::DNS_RESOLVER;
+use crate::errors::{ConnectError, ProxyError};
+
+/// Order the resolved addresses as RFC 8305 suggests: alternate between address families,
+/// starting with IPv6, so a broken family never delays the other by more than one attempt.
+fn interleave_address_families(addrs: impl IntoIterator<Item = IpAddr>) -> Vec<IpAddr> {
+    let (ipv6, ipv4): (Vec<IpAddr>, Vec<IpAddr>) = addrs.into_iter().partition(|ip| ip.is_ipv6());
+
+    let mut ipv6 = ipv6.into_iter();
+    let mut ipv4 = ipv4.into_iter();
+    let mut ordered = Vec::new();
+
+    loop {
+        match (ipv6.next(), ipv4.next()) {
+            (None, None) => break,
+            (v6, v4) => ordered.extend(v6.into_iter().chain(v4)),
+        }
+    }
+
+    ordered
+}
+
+/// Outcome of a single attempt, `Err(None)` when it timed out.
+async fn connect_attempt(
+    addr: SocketAddr,
+    timeout: Duration,
+) -> (SocketAddr, Result<TcpStream, Option<String>>) {
+    let result = match TokioTime::timeout(timeout, TcpStream::connect(addr)).await {
+        Ok(Ok(stream)) => Ok(stream),
+        Ok(Err(e)) => Err(Some(e.to_string())),
+        Err(_) => Err(None),
+    };
+
+    (addr, result)
+}
+
+/// Race connection attempts to the addresses (RFC 8305 "Happy Eyeballs"). A new attempt starts
+/// whenever the previous one fails or `attempt_delay` elapses without an answer, the first
+/// successful connection wins and the remaining attempts are cancelled. The error is a timeout
+/// only when every attempt timed out.
+pub async fn connect_happy_eyeballs(
+    addrs: Vec<SocketAddr>,
+    connect_timeout: Duration,
+    attempt_delay: Duration,
+) -> Result<TcpStream, ConnectError> {
+    let mut pending = addrs.into_iter().peekable();
+    let mut attempts = JoinSet::new();
+    let mut errors = Vec::new();
+    let mut all_timed_out = true;
+
+    loop {
+        if attempts.is_empty() {
+            match pending.next() {
+                Some(addr) => {
+                    attempts.spawn(connect_attempt(addr, connect_timeout));
+                }
+                None => break,
+            }
+        }
+
+        tokio::select! {
+            Some(joined) = attempts.join_next() => {
+                let (addr, result) = joined.map_err(|e| ConnectError::Failed(e.to_string()))?;
+                match result {
+                    Ok(stream) => {
+                        tracing::debug!("Connected to {} ({} attempts still pending)", addr, attempts.len());
+                        return Ok(stream);
+                    }
+                    Err(e) => {
+                        all_timed_out &= e.is_none();
+                        let e = e.unwrap_or_else(|| format!("timed out after {:?}", connect_timeout));
+                        tracing::debug!("Connection attempt to {} failed: {}", addr, e);
+                        errors.push(format!("{}: {}", addr, e));
+
+                        if let Some(addr) = pending.next() {
+                            attempts.spawn(connect_attempt(addr, connect_timeout));
+                        }
+                    }
+                }
+            }
+            _ = TokioTime::sleep(attempt_delay), if pending.peek().is_some() => {
+                if let Some(addr) = pending.next() {
+                    tracing::debug!("No answer after {:?}, also trying {}", attempt_delay, addr);
+                    attempts.spawn(connect_attempt(addr, connect_timeout));
+                }
+            }
+        }
+    }
+
+    match (errors.is_empty(), all_timed_out) {
+        (true, _) => Err(ConnectError::NoAddress),
+        (false, true) => Err(ConnectError::Timeout(connect_timeout)),
+        (false, false) => Err(ConnectError::Failed(errors.join(", "))),
+    }
+}
+
+/// Resolve the host and connect to it, racing every A/AAAA record with the timeouts of the
+/// global configuration.
+pub async fn connect_to_host(host: &str, port: u16) -> Result<TcpStream, ProxyError> {
+    let config = get_global_config();
+
+    let lookup = DNS_RESOLVER.lookup_ip(host).await?;
+    let ips = interleave_address_families(lookup.iter());
+
+    tracing::info!("Resolved {} to {:?}", host, ips);
+
+    let addrs = ips
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect();
+    connect_happy_eyeballs(
+        addrs,
+        Duration::from_millis(config.connect_timeout_ms.max(1)),
+        Duration::from_millis(config.connect_attempt_delay_ms),
+    )
+    .await
+    .map_err(ProxyError::from)
+}
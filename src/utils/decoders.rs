@@ -31,3 +31,25 @@ pub fn decode_zstd(compressed: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Err
     let decompressed = zstd::decode_all(&compressed[..])?;
     Ok(decompressed)
 }
+
+/// Decode a body following its `Content-Encoding` header. Encodings are listed in the order
+/// they were applied, so they are undone in reverse.
+pub fn decode_content(
+    body: &[u8],
+    content_encoding: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut body = body.to_vec();
+
+    for enc in content_encoding.split(',').map(|e| e.trim()).rev() {
+        body = match enc {
+            "br" => decode_brotli(&body)?,
+            "gzip" => decode_gzip(&body)?,
+            "deflate" => decode_deflate(&body)?,
+            "zstd" => decode_zstd(&body)?,
+            "identity" | "" => body,
+            unknown => return Err(format!("Unknown content encoding '{}'", unknown).into()),
+        };
+    }
+
+    Ok(body)
+}
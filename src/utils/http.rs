@@ -1,12 +1,10 @@
 use std::collections::HashMap;
 
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
-use tokio_native_tls::TlsStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+use super::authority::{Authority, normalize_hostname};
 use super::buffer::read_headers_buffer;
+use crate::errors::ParseError;
 use crate::schemas::{HttpsRequest, HttpsResponse};
 
 pub fn parse_headers(lines: &[&str]) -> HashMap<String, String> {
@@ -41,9 +39,20 @@ pub fn parse_headers(lines: &[&str]) -> HashMap<String, String> {
     headers
 }
 
-async fn read_line_bytes(
-    tls_stream: &mut TlsStream<&mut TcpStream>,
-) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+/// Host of a `Host` header or authority, without port and IPv6 brackets.
+pub fn normalize_host(value: &str) -> String {
+    match Authority::parse(value, 0) {
+        Ok(authority) => authority.host,
+        Err(_) => normalize_hostname(value.trim_matches(|c| c == '[' || c == ']')),
+    }
+}
+
+async fn read_line_bytes<S>(
+    tls_stream: &mut S,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + Unpin,
+{
     let mut line = Vec::new();
     let mut byte = [0u8; 1];
 
@@ -69,9 +78,12 @@ async fn read_line_bytes(
     Ok(line)
 }
 
-pub async fn read_http_stream(
-    tls_stream: &mut TlsStream<&mut TcpStream>,
-) -> Result<HttpsRequest, Box<dyn std::error::Error + Send + Sync>> {
+pub async fn read_http_stream<S>(
+    tls_stream: &mut S,
+) -> Result<HttpsRequest, Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + Unpin,
+{
     let buffer_string = read_headers_buffer(tls_stream).await?;
 
     let lines = buffer_string.split("\r\n").collect::<Vec<&str>>();
@@ -79,8 +91,7 @@ pub async fn read_http_stream(
     // Parse request line
     let first_line = *lines.first().unwrap_or(&"");
     let [method, authority, version] = first_line.split(' ').collect::<Vec<&str>>()[..] else {
-        tracing::error!("Malformed HTTPS request line: {}", first_line);
-        return Err("Malformed HTTPS request line".into());
+        return Err(ParseError::RequestLine(first_line.to_string()).into());
     };
 
     // Parse headers
@@ -155,9 +166,12 @@ pub async fn read_http_stream(
     })
 }
 
-pub async fn read_stream_response(
-    tls_stream: &mut TlsStream<&mut TcpStream>,
-) -> Result<HttpsResponse, Box<dyn std::error::Error + Send + Sync>> {
+pub async fn read_stream_response<S>(
+    tls_stream: &mut S,
+) -> Result<HttpsResponse, Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + Unpin,
+{
     let headers_raw = read_headers_buffer(tls_stream).await?;
     let lines = headers_raw
         .split("\r\n")
@@ -249,10 +263,13 @@ pub async fn read_stream_response(
     })
 }
 
-pub async fn write_request(
-    tls_stream: &mut TlsStream<&mut TcpStream>,
+pub async fn write_request<S>(
+    tls_stream: &mut S,
     request: &HttpsRequest,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncWrite + Unpin,
+{
     let mut modified_headers = request.headers.clone();
     if let Some(body) = &request.body {
         if modified_headers
@@ -284,10 +301,13 @@ pub async fn write_request(
     Ok(())
 }
 
-pub async fn write_response(
-    tls_stream: &mut TlsStream<&mut TcpStream>,
+pub async fn write_response<S>(
+    tls_stream: &mut S,
     response: &HttpsResponse,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncWrite + Unpin,
+{
     let mut response_string = format!(
         "{} {} {}\r\n",
         response.version, response.status_code, response.status_text
@@ -0,0 +1,120 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, UdpSocket};
+
+use crate::dns::DNS_RESOLVER;
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Address families the servers listen on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListenFamily {
+    Ipv4,
+    Ipv6,
+    /// One listener per family, IPv4 and IPv6 clients are both served.
+    DualStack,
+}
+
+impl ListenFamily {
+    pub fn from_flags(ipv6: bool, dual_stack: bool) -> Self {
+        match (ipv6, dual_stack) {
+            (_, true) => ListenFamily::DualStack,
+            (true, false) => ListenFamily::Ipv6,
+            (false, false) => ListenFamily::Ipv4,
+        }
+    }
+}
+
+/// Resolve the addresses to listen on for the host. In dual-stack mode an unspecified address
+/// (`0.0.0.0` or `::`) listens on both unspecified addresses, and a host name on its first IPv4
+/// and first IPv6 address.
+pub async fn resolve_listen_addrs(
+    host: &str,
+    port: u16,
+    family: ListenFamily,
+) -> Result<Vec<SocketAddr>, Box<dyn std::error::Error + Send + Sync>> {
+    let (ipv4, ipv6) = match host
+        .trim_matches(|c| c == '[' || c == ']')
+        .parse::<IpAddr>()
+    {
+        Ok(ip) if ip.is_unspecified() && family == ListenFamily::DualStack => (
+            Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+        ),
+        _ => {
+            let lookup = DNS_RESOLVER.lookup_ip(host).await?;
+            (
+                lookup.iter().find(|ip| ip.is_ipv4()),
+                lookup.iter().find(|ip| ip.is_ipv6()),
+            )
+        }
+    };
+
+    let ips = match family {
+        ListenFamily::Ipv4 => {
+            vec![ipv4.ok_or("No IPv4 address found for the specified host")?]
+        }
+        ListenFamily::Ipv6 => {
+            vec![ipv6.ok_or("No IPv6 address found for the specified host")?]
+        }
+        ListenFamily::DualStack => {
+            let ips = ipv4.into_iter().chain(ipv6).collect::<Vec<_>>();
+            if ips.is_empty() {
+                return Err("No IP address found for the specified host".into());
+            }
+            ips
+        }
+    };
+
+    Ok(ips
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect())
+}
+
+/// Create a socket for the address. IPv6 sockets only accept IPv6 clients, so the IPv4
+/// listener of a dual-stack setup can bind the same port.
+fn new_socket(addr: SocketAddr, socket_type: Type, protocol: Protocol) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), socket_type, Some(protocol))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_nonblocking(true)?;
+
+    Ok(socket)
+}
+
+pub fn bind_tcp_listener(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = new_socket(addr, Type::STREAM, Protocol::TCP)?;
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+pub fn bind_udp_socket(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = new_socket(addr, Type::DGRAM, Protocol::UDP)?;
+    socket.bind(&addr.into())?;
+
+    UdpSocket::from_std(socket.into())
+}
+
+/// Resolve and bind a TCP listener per address.
+pub async fn bind_tcp_listeners(
+    host: &str,
+    port: u16,
+    family: ListenFamily,
+) -> Result<Vec<TcpListener>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut listeners = Vec::new();
+    for addr in resolve_listen_addrs(host, port, family).await? {
+        let listener =
+            bind_tcp_listener(addr).map_err(|e| format!("Unable to listen on {}: {}", addr, e))?;
+        listeners.push(listener);
+    }
+
+    Ok(listeners)
+}
@@ -1,9 +1,12 @@
+pub mod authority;
 pub mod buffer;
+pub mod connect;
 pub mod decoders;
-pub mod dns;
 pub mod http;
+pub mod listen;
 pub mod stream;
+pub mod time;
 pub mod tls;
 
 pub use buffer::read_headers_buffer;
-pub use dns::DNS_RESOLVER;
+pub use time::now_secs;
@@ -4,10 +4,13 @@ use certgenutil::generate_server_cert_by_ca_pem;
 use openssl::{asn1::Asn1Time, pkey::PKey, x509::X509};
 use rcgen::{CertificateParams, KeyPair};
 use time::{Duration, OffsetDateTime};
+use tokio::net::TcpStream;
+use tokio::time as TokioTime;
 
 use crate::config::{
     CERT_DAYS_VALID, CERT_PATH, ProxyConfig, get_global_config, set_global_config,
 };
+use crate::errors::TlsError;
 
 pub fn generate_ca() -> Result<(String, String), Box<dyn std::error::Error + Send + Sync>> {
     tracing::info!("Generating new CA certificate at {:?}", CERT_PATH);
@@ -57,12 +60,11 @@ pub fn get_ca_cert() -> Result<String, Box<dyn std::error::Error + Send + Sync>>
         let global_config = get_global_config();
         let updated_config = ProxyConfig {
             intercept_tls: false,
-            block_ads: global_config.block_ads,
-            cache_enabled: global_config.cache_enabled,
+            ..(*global_config).clone()
         };
 
         set_global_config(updated_config);
-        return Err("CA certificate has expired, please regenerate it")?;
+        return Err(TlsError::CaExpired.into());
     }
 
     Ok(ca_cert)
@@ -74,6 +76,35 @@ pub fn get_ca_key() -> Result<String, Box<dyn std::error::Error + Send + Sync>>
     Ok(ca_key)
 }
 
+/// Check that the CA certificate and key exist, belong together and that the certificate has not
+/// expired, without touching the global configuration like [`get_ca_cert`] does.
+pub fn validate_ca() -> Result<(), String> {
+    let ca_cert = fs::read_to_string(CERT_PATH.join("ca_cert.pem"))
+        .map_err(|e| format!("CA certificate not found: {}", e))?;
+    let ca_key = fs::read_to_string(CERT_PATH.join("ca_key.pem"))
+        .map_err(|e| format!("CA key not found: {}", e))?;
+
+    let cert =
+        X509::from_pem(ca_cert.as_bytes()).map_err(|e| format!("Invalid CA certificate: {}", e))?;
+    let key = PKey::private_key_from_pem(ca_key.as_bytes())
+        .map_err(|e| format!("Invalid CA key: {}", e))?;
+
+    let now = Asn1Time::days_from_now(0).map_err(|e| e.to_string())?;
+    if cert.not_after() < now {
+        return Err("CA certificate has expired, please regenerate it".to_string());
+    }
+
+    let matches = cert
+        .public_key()
+        .map(|public_key| public_key.public_eq(&key))
+        .unwrap_or(false);
+    if !matches {
+        return Err("CA key does not match the CA certificate".to_string());
+    }
+
+    Ok(())
+}
+
 pub fn generate_cert_for_domain(
     domain: &str,
 ) -> Result<(String, String), Box<dyn std::error::Error + Send + Sync>> {
@@ -103,3 +134,91 @@ pub fn generate_cert_for_domain(
 
     Ok((cert_pem, key_pem))
 }
+
+/// Extract the server name (SNI) from a raw TLS ClientHello record, if present.
+pub fn parse_sni(buffer: &[u8]) -> Option<String> {
+    fn read_u16(buffer: &[u8], pos: usize) -> Option<usize> {
+        let bytes = buffer.get(pos..pos + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+    }
+
+    // Record header: content type (handshake = 0x16), version (2 bytes), length (2 bytes)
+    if buffer.first() != Some(&0x16) {
+        return None;
+    }
+
+    // Handshake header: type (ClientHello = 0x01) and a 3 bytes length
+    let mut pos = 5;
+    if buffer.get(pos) != Some(&0x01) {
+        return None;
+    }
+    pos += 4;
+
+    // Client version (2 bytes) and random (32 bytes)
+    pos += 2 + 32;
+
+    // Session ID, cipher suites and compression methods
+    pos += 1 + *buffer.get(pos)? as usize;
+    pos += 2 + read_u16(buffer, pos)?;
+    pos += 1 + *buffer.get(pos)? as usize;
+
+    let extensions_end = pos + 2 + read_u16(buffer, pos)?;
+    pos += 2;
+
+    while pos + 4 <= extensions_end {
+        let extension_type = read_u16(buffer, pos)?;
+        let extension_len = read_u16(buffer, pos + 2)?;
+        pos += 4;
+
+        // server_name extension: list length (2), name type (1), name length (2), name
+        if extension_type == 0x0000 {
+            let name_type = *buffer.get(pos + 2)?;
+            let name_len = read_u16(buffer, pos + 3)?;
+            if name_type != 0x00 {
+                return None;
+            }
+
+            let name = buffer.get(pos + 5..pos + 5 + name_len)?;
+            return String::from_utf8(name.to_vec()).ok();
+        }
+
+        pos += extension_len;
+    }
+
+    None
+}
+
+const CLIENT_HELLO_PEEK_RETRIES: usize = 10;
+
+/// Peek the TLS ClientHello record without consuming it. Returns `None` for plain HTTP connections.
+pub async fn peek_client_hello(
+    stream: &TcpStream,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut buffer = vec![0u8; 16 * 1024];
+    let mut n = 0;
+
+    for _ in 0..CLIENT_HELLO_PEEK_RETRIES {
+        n = stream.peek(&mut buffer).await?;
+        if n == 0 {
+            return Err("Connection closed before any data received".into());
+        }
+
+        // 0x16 is the TLS handshake record type, anything else is treated as plain HTTP
+        if buffer[0] != 0x16 {
+            return Ok(None);
+        }
+
+        if n >= 5 {
+            let record_len = u16::from_be_bytes([buffer[3], buffer[4]]) as usize;
+            if n >= 5 + record_len {
+                break;
+            }
+        }
+
+        // The ClientHello may arrive in several segments
+        TokioTime::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    buffer.truncate(n);
+    Ok(Some(buffer))
+}